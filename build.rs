@@ -0,0 +1,40 @@
+//! Records the git commit and compiler the crate is built with, reported by
+//! the `version` command and the startup banner.
+
+use std::env;
+use std::path::Path;
+use std::process::Command;
+
+fn main() {
+    // Source snapshots outside a git checkout still build, as "unknown"
+    let commit = run("git", &["rev-parse", "--short=12", "HEAD"]).unwrap_or_else(|| "unknown".to_string());
+    let dirty = run("git", &["status", "--porcelain", "--untracked-files=no"]).is_some_and(|status| !status.is_empty());
+    let commit = if dirty { format!("{}-dirty", commit) } else { commit };
+
+    let rustc = env::var("RUSTC").unwrap_or_else(|_| "rustc".to_string());
+    let rustc_version = run(&rustc, &["--version"]).unwrap_or_else(|| "unknown".to_string());
+
+    let mut features: Vec<String> = env::vars()
+        .filter_map(|(name, _)| name.strip_prefix("CARGO_FEATURE_").map(|feature| feature.to_lowercase().replace('_', "-")))
+        .collect();
+    features.sort();
+
+    println!("cargo:rustc-env=DHT_GIT_COMMIT={}", commit);
+    println!("cargo:rustc-env=DHT_RUSTC_VERSION={}", rustc_version);
+    println!("cargo:rustc-env=DHT_FEATURES={}", features.join(","));
+    // Rebuild the metadata when the checked-out commit moves
+    for path in [".git/HEAD", ".git/refs", ".git/index"] {
+        if Path::new(path).exists() {
+            println!("cargo:rerun-if-changed={}", path);
+        }
+    }
+}
+
+// The trimmed standard output of a command, or `None` if it could not be run or failed
+fn run(program: &str, args: &[&str]) -> Option<String> {
+    let output = Command::new(program).args(args).output().ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    String::from_utf8(output.stdout).ok().map(|stdout| stdout.trim().to_string())
+}
@@ -0,0 +1,143 @@
+//! Timed batches of puts and gets of fresh random keys, giving a repeatable
+//! way to compare transports, quorums and other settings on one network.
+
+use crate::DhtNode;
+use futures::StreamExt;
+use sha2::{Digest, Sha256};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+/// Value size used when none is given.
+pub const DEFAULT_VALUE_BYTES: usize = 64;
+
+/// How one phase of a benchmark went.
+#[derive(Debug, Clone)]
+pub struct Phase {
+    /// How long each operation took, successful or not, shortest first.
+    pub latencies: Vec<Duration>,
+    pub succeeded: usize,
+    /// From the first operation starting to the last one finishing.
+    pub elapsed: Duration,
+}
+
+impl Phase {
+    fn new(mut latencies: Vec<Duration>, succeeded: usize, elapsed: Duration) -> Self {
+        latencies.sort();
+        Phase { latencies, succeeded, elapsed }
+    }
+
+    /// Operations run, whether or not they succeeded.
+    pub fn count(&self) -> usize {
+        self.latencies.len()
+    }
+
+    pub fn min(&self) -> Duration {
+        self.percentile(0.0)
+    }
+
+    pub fn median(&self) -> Duration {
+        self.percentile(0.5)
+    }
+
+    pub fn p95(&self) -> Duration {
+        self.percentile(0.95)
+    }
+
+    pub fn max(&self) -> Duration {
+        self.percentile(1.0)
+    }
+
+    /// The latency `fraction` of the operations took at most, by the nearest rank.
+    pub fn percentile(&self, fraction: f64) -> Duration {
+        let Some(last) = self.latencies.len().checked_sub(1) else {
+            return Duration::ZERO;
+        };
+        let rank = (fraction * self.latencies.len() as f64).ceil() as usize;
+        self.latencies[rank.saturating_sub(1).min(last)]
+    }
+
+    /// Share of the operations that succeeded, from 0 to 1.
+    pub fn success_rate(&self) -> f64 {
+        if self.latencies.is_empty() {
+            return 0.0;
+        }
+        self.succeeded as f64 / self.latencies.len() as f64
+    }
+
+    /// Operations completed a second over the whole phase.
+    pub fn throughput(&self) -> f64 {
+        let secs = self.elapsed.as_secs_f64();
+        if secs == 0.0 {
+            return 0.0;
+        }
+        self.latencies.len() as f64 / secs
+    }
+}
+
+/// The outcome of [`run`].
+#[derive(Debug, Clone)]
+pub struct Benchmark {
+    pub value_bytes: usize,
+    /// Puts with the default quorum; one only succeeds once that quorum stored it.
+    pub puts: Phase,
+    /// Gets of the same keys from other peers, ignoring this node's copies;
+    /// one only succeeds if it returns the value that was put.
+    pub gets: Phase,
+}
+
+/// Put `count` records of `value_bytes` each under fresh random keys, then get
+/// every one back from the DHT, keeping `put_concurrency` operations in flight.
+pub async fn run(node: &DhtNode, count: usize, value_bytes: usize) -> Benchmark {
+    let keys = random_keys(count);
+    let value = vec![b'x'; value_bytes];
+
+    let started = Instant::now();
+    let puts = timed(node, &keys, |key| {
+        let value = value.clone();
+        async move { node.put(&key, value, node.default_quorum()).await.is_ok() }
+    })
+    .await;
+    let puts = Phase::new(puts.0, puts.1, started.elapsed());
+
+    let started = Instant::now();
+    let gets = timed(node, &keys, |key| {
+        let value = &value;
+        async move { matches!(node.get_remote(&key).await, Ok(Some(found)) if found == *value) }
+    })
+    .await;
+    let gets = Phase::new(gets.0, gets.1, started.elapsed());
+
+    Benchmark { value_bytes, puts, gets }
+}
+
+// Run `operation` on every key, returning each one's latency and how many succeeded
+async fn timed<F, Fut>(node: &DhtNode, keys: &[String], operation: F) -> (Vec<Duration>, usize)
+where
+    F: Fn(String) -> Fut,
+    Fut: std::future::Future<Output = bool>,
+{
+    let mut outcomes = futures::stream::iter(keys.iter().cloned())
+        .map(|key| {
+            let started = Instant::now();
+            let operation = operation(key);
+            async move { (operation.await, started.elapsed()) }
+        })
+        .buffer_unordered(node.put_concurrency());
+
+    let mut latencies = Vec::with_capacity(keys.len());
+    let mut succeeded = 0;
+    while let Some((ok, latency)) = outcomes.next().await {
+        latencies.push(latency);
+        succeeded += usize::from(ok);
+    }
+    (latencies, succeeded)
+}
+
+// Keys no earlier run used, under a per-run label such as `7.3fa9c2e10d4b8a65.benchmark`
+fn random_keys(count: usize) -> Vec<String> {
+    let nanos = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_nanos();
+    let mut hasher = Sha256::new();
+    hasher.update(nanos.to_be_bytes());
+    hasher.update(std::process::id().to_be_bytes());
+    let run: String = hasher.finalize()[..8].iter().map(|b| format!("{:02x}", b)).collect();
+    (0..count).map(|i| format!("{}.{}.benchmark", i, run)).collect()
+}
@@ -0,0 +1,81 @@
+//! A bounded cache of values found in the DHT, so repeated `get`s of popular
+//! keys this node does not store are answered without another lookup.
+
+use crate::node::Entry;
+use libp2p::kad::{Record, RecordKey};
+use std::collections::{BTreeMap, HashMap};
+use std::time::{Duration, Instant};
+
+/// Holds up to `capacity` entries, evicting the least recently used one to make
+/// room. Entries are dropped once their record has been expired for `grace`,
+/// and served marked stale until then.
+///
+/// Each entry keeps the record it was opened from, so `describe` can show the
+/// copy and the peer it was fetched from.
+pub struct ResultCache {
+    capacity: usize,
+    grace: Duration,
+    // Each entry and its record with the tick it was last used at
+    entries: HashMap<RecordKey, (Entry, Record, u64)>,
+    // Keys by the tick they were last used at, oldest first
+    order: BTreeMap<u64, RecordKey>,
+    tick: u64,
+}
+
+impl ResultCache {
+    /// A cache of `capacity` entries; zero turns caching off.
+    pub fn new(capacity: usize, grace: Duration) -> Self {
+        ResultCache { capacity, grace, entries: HashMap::new(), order: BTreeMap::new(), tick: 0 }
+    }
+
+    /// The cached value of `key`, unless it is missing or past its grace
+    /// window; an expired one is marked stale.
+    pub fn get(&mut self, key: &RecordKey, now: Instant) -> Option<Entry> {
+        let grace = self.grace;
+        let (entry, _, used) = self.entries.get_mut(key)?;
+        if entry.expires.is_some_and(|expires| expires + grace <= now) {
+            self.remove(key);
+            return None;
+        }
+        self.tick += 1;
+        self.order.remove(used);
+        self.order.insert(self.tick, key.clone());
+        *used = self.tick;
+        let stale = entry.expires.is_some_and(|expires| expires <= now);
+        Some(Entry { stale, ..entry.clone() })
+    }
+
+    /// The cached entry of `key` and the record it came from, without counting
+    /// as a use. Expired entries are left for the next `get` to drop.
+    pub fn peek(&self, key: &RecordKey, now: Instant) -> Option<(&Entry, &Record)> {
+        let (entry, record, _) = self.entries.get(key)?;
+        if entry.expires.is_some_and(|expires| expires <= now) {
+            return None;
+        }
+        Some((entry, record))
+    }
+
+    /// Cache `entry`, opened from `record`, for `key`, replacing any older value.
+    pub fn insert(&mut self, key: RecordKey, entry: Entry, record: Record) {
+        if self.capacity == 0 {
+            return;
+        }
+        self.remove(&key);
+        while self.entries.len() >= self.capacity {
+            let Some((_, oldest)) = self.order.pop_first() else {
+                break;
+            };
+            self.entries.remove(&oldest);
+        }
+        self.tick += 1;
+        self.order.insert(self.tick, key.clone());
+        self.entries.insert(key, (entry, record, self.tick));
+    }
+
+    /// Forget `key`, as when it is written, deleted or invalidated.
+    pub fn remove(&mut self, key: &RecordKey) {
+        if let Some((_, _, used)) = self.entries.remove(key) {
+            self.order.remove(&used);
+        }
+    }
+}
@@ -0,0 +1,48 @@
+//! Optional zstd compression of record values, for large and repetitive ones
+//! like TXT records and zone blobs.
+//!
+//! A compressed value is stored as [`HEADER`] followed by a zstd frame, inside
+//! the signed envelope as any other value. Every node running this code
+//! decompresses such values when opening them, whether or not it compresses
+//! its own; older nodes just see the header and the compressed bytes.
+
+use zstd::zstd_safe::MAGICNUMBER;
+
+/// First byte of a compressed value. It never starts UTF-8 text, so TXT and
+/// JSON values are not mistaken for compressed ones.
+pub const HEADER: u8 = 0xf5;
+/// Largest value a compressed one may expand to, so a small record cannot
+/// make readers allocate without bound.
+pub const MAX_DECOMPRESSED_BYTES: usize = 16 * 1024 * 1024;
+const LEVEL: i32 = 3;
+
+/// The value to store for `value`: compressed if that makes it smaller, as is otherwise.
+///
+/// A value that would itself be read as compressed is always compressed, so
+/// it comes back unchanged.
+pub fn compress(value: Vec<u8>) -> Vec<u8> {
+    let Ok(frame) = zstd::bulk::compress(&value, LEVEL) else {
+        return value;
+    };
+    if frame.len() + 1 >= value.len() && !is_compressed(&value) {
+        return value;
+    }
+    let mut stored = Vec::with_capacity(frame.len() + 1);
+    stored.push(HEADER);
+    stored.extend_from_slice(&frame);
+    stored
+}
+
+/// The original of a stored value, or the value itself if it is not compressed
+/// or does not decompress within [`MAX_DECOMPRESSED_BYTES`].
+pub fn decompress(stored: Vec<u8>) -> Vec<u8> {
+    if !is_compressed(&stored) {
+        return stored;
+    }
+    zstd::bulk::decompress(&stored[1..], MAX_DECOMPRESSED_BYTES).unwrap_or(stored)
+}
+
+/// Whether `stored` is a header followed by the start of a zstd frame.
+pub fn is_compressed(stored: &[u8]) -> bool {
+    stored.first() == Some(&HEADER) && stored.get(1..5) == Some(&MAGICNUMBER.to_le_bytes()[..])
+}
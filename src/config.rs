@@ -0,0 +1,1017 @@
+//! Node configuration, loaded from a TOML file and overridden by command line flags.
+
+use crate::delegation::TrustAnchor;
+use crate::error::DhtError;
+use crate::keys::{KeyEncoding, KeyHashing};
+use crate::validator::SuffixValidator;
+use libp2p::{kad::Quorum, multiaddr::Protocol, pnet::PreSharedKey, Multiaddr, StreamProtocol};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::fs;
+use std::num::NonZeroUsize;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// Commented template printed by `--print-default-config`.
+pub const DEFAULT_CONFIG_TEMPLATE: &str = r#"# DHT node configuration
+
+# Transports to listen on when `listen` is empty: "tcp", "quic", "ws" (WebSocket) or "both" (tcp and quic).
+# Whatever is chosen, the node can still dial tcp, quic, ws and wss addresses
+transport = "both"
+
+# Explicit listen multiaddrs; when non-empty these replace the transport defaults
+# listen = ["/ip4/0.0.0.0/tcp/4001", "/ip6/::/tcp/4001", "/ip4/0.0.0.0/udp/4001/quic-v1"]
+listen = []
+
+# Also listen on IPv6 (::) next to each IPv4 default address. Hosts without IPv6
+# only log a warning when binding fails
+ipv6 = true
+
+# Only share and print addresses peers on other networks can dial. Loopback,
+# link-local and private (RFC 1918, IPv6 unique local) addresses are still listened
+# on, but left out of addrs and never advertised as external; addrs all shows them
+public_only = false
+
+# Relays to reserve a slot on, each ending in /p2p/<relay_peer_id>, so peers that
+# cannot dial us directly (e.g. behind NAT) can reach us through /p2p-circuit
+# relays = ["/ip4/203.0.113.9/tcp/4001/p2p/12D3KooW..."]
+relays = []
+
+# Pre-shared key file (the /key/swarm/psk/1.0.0/ swarm.key format) for a private
+# network: only nodes holding the same key can connect, and QUIC is turned off
+# as it cannot carry the key. Omit to join the open network
+# psk_path = "/etc/dht/swarm.key"
+
+# Security handshake for TCP, WebSocket and relayed connections: "noise", "tls" or
+# "both", which offers noise first and lets the remote pick whichever it supports.
+# A node only connects to peers sharing one of its handshakes. TLS needs no
+# certificate files: each start makes a self-signed one from a fresh key, with the
+# node's identity key signing it in a libp2p extension, so the peer id is the same
+# under either handshake. QUIC always uses TLS
+security = "noise"
+
+# Peers to join at startup, each ending in /p2p/<peer_id>
+# bootstrap = ["/ip4/203.0.113.7/tcp/4001/p2p/12D3KooW..."]
+bootstrap = []
+
+# URL of a published list of bootstrap peers, fetched at startup and joined along
+# with the ones above: a JSON array of multiaddrs, or one per line. If the fetch
+# fails or takes over 10 seconds the node starts with the configured peers alone
+# bootstrap_url = "https://example.com/dht/seeds.txt"
+
+# Seconds a connection may take to be established and upgraded before the dial fails
+dial_timeout_secs = 10
+
+# Failed dials or broken connections in a row after which a peer's address leaves
+# the routing table. Kademlia drops other addresses as soon as a dial to them fails
+# but keeps a peer's last one, so without this lookups keep trying peers that moved
+# or went away; pruning the last address removes the peer. 0 keeps them
+prune_after_failures = 3
+
+# Seconds a connection with nothing left to do stays open before it is closed.
+# Longer keeps repeated lookups to the same peers fast, since no new dial is needed,
+# but holds a file descriptor and some memory per idle peer; 0 closes them at once
+idle_timeout_secs = 60
+
+# Most connections kept open at once, and most still being set up in each direction;
+# further ones are refused. Omit either for no limit
+# max_connections = 256
+# max_pending_connections = 32
+
+# Seconds before an outstanding DHT query is abandoned
+query_timeout_secs = 10
+
+# Seconds a single `get` waits for the DHT before giving up, and how many times it
+# is re-issued after that, at most 86400 seconds and 100 retries. Omit get_timeout_secs
+# to use query_timeout_secs
+# get_timeout_secs = 5
+get_retries = 0
+
+# Peers that must acknowledge a put, or return the record for a get, unless the
+# command names its own: "all", "majority" or a number. Higher quorums are more
+# durable but fail outright on networks with fewer peers than required
+default_quorum = "1"
+
+# Seconds between lookups of each key being watched with `watch`
+watch_interval_secs = 10
+
+# Seconds a local copy is served by `get` without checking the DHT for a newer
+# version. Older copies are looked up again first; 0 checks on every get
+freshness_secs = 60
+
+# Stale-while-revalidate: a get of a local or cached copy that expired less than
+# swr_grace_secs ago returns it at once, marked stale, and looks the key up in the
+# background to refresh the cache. Older copies wait for the lookup as usual
+swr = false
+swr_grace_secs = 300
+
+# Seconds a record stays valid when `put` is not given an explicit TTL
+record_ttl_secs = 86400
+
+# Seconds between re-publications of the records we put, so they do not lapse on
+# other peers. Must be shorter than record_ttl_secs; omit to use half of it
+# republish_interval_secs = 43200
+
+# Seconds between re-replications of records stored on behalf of other peers
+replication_interval_secs = 3600
+
+# Seconds between routing table refreshes, each a bootstrap lookup of our own id
+# and random ones that keeps distant buckets filled on long-running nodes. 0 turns
+# them off, leaving only the bootstrap at startup and on `bootstrap`
+refresh_interval_secs = 300
+
+# Number of peers closest to a key that a put stores it on (Kademlia's K). Small
+# private networks can lower it; quorums of "all" and "majority" count against it
+replication_factor = 20
+
+# Largest value a put accepts, in bytes, and most records the local store holds,
+# including copies stored on behalf of other peers
+max_record_bytes = 66560
+max_records = 1024
+
+# Values found in the DHT for keys this node does not store are cached for repeated
+# gets, up to this many; the least recently used is evicted first. 0 turns it off
+cache_capacity = 1024
+
+# Where records are saved between runs; omit to use ~/.dht/records.db
+# store_path = "/var/lib/dht/records.db"
+
+# The node's private key, generated on first run; keeps the peer id stable across
+# restarts. Omit to use ~/.dht/identity
+# identity_path = "/var/lib/dht/identity"
+
+# Derive the private key from this string instead, so the peer id is the same on
+# every machine given it, as for test networks and well-known bootstrap nodes.
+# Anyone who knows the seed can act as the node. Cannot be set with identity_path
+# seed = "bootstrap-1"
+
+# Commands typed at the interactive prompt, kept across runs; omit to use ~/.dht/history
+# history_path = "/var/lib/dht/history"
+
+# Append-only log of puts, gets, deletes, query results and connections, one JSON
+# object per line, for tools to read later; omit to disable it. Written in the
+# background and flushed every second. Once the file would exceed
+# event_log_max_bytes it is renamed to <path>.1, older ones moving up to
+# <path>.<event_log_keep> and the oldest beyond that deleted. 0 bytes never rotates
+# event_log = "/var/log/dht/events.jsonl"
+event_log_max_bytes = 10485760
+event_log_keep = 3
+
+# Address for the HTTP API (PUT/GET/DELETE /records/<key>); omit to disable it
+# http_addr = "127.0.0.1:8080"
+
+# UDP address answering standard DNS queries (A, AAAA, TXT, CNAME); omit to disable it
+# dns_addr = "127.0.0.1:5353"
+
+# Address serving Prometheus metrics at /metrics; omit to disable it
+# metrics_addr = "127.0.0.1:9090"
+
+# Unix socket taking the same commands as stdin and answering with their output,
+# accessible to the owner only and removed on shutdown; omit to disable it
+# control_socket = "/run/dht.sock"
+
+# Compress values with zstd when that makes them smaller; max_record_bytes then
+# limits the compressed size. Nodes running this version decompress them whatever
+# this is set to
+compress = false
+
+# Refuse to serve or accept records that are not signed
+require_signed = false
+
+# Refuse every write, for a node serving only as a resolver: local puts, deletes,
+# provides and pins fail, and records and provider announcements pushed by peers
+# are dropped instead of stored. Gets and resolves still work
+readonly = false
+
+# Only accept names at or below one of these domains, from local puts and from
+# peers alike, so "example.com" accepts example.com and www.example.com but not
+# evilexample.com; needs key_hashing = "raw". Empty accepts every name
+# allow_suffixes = ["example.com"]
+allow_suffixes = []
+
+# The zone `resolve` trusts and the peer id whose key signs it, as <zone>=<peer_id>.
+# Records are then only accepted if signed by the key of their zone, reached from
+# here through the delegations stored by `delegate`; names outside it are refused.
+# Omit to accept any record
+# trust_anchor = "example.com=12D3KooW..."
+
+# Commands typed or pasted on stdin are throttled per command name (put, get, ...):
+# each may be issued command_burst times at once, then command_rate times a second.
+# A command_rate of 0 turns throttling off
+command_rate = 10.0
+command_burst = 20
+
+# Kademlia requests each peer may send us: inbound_burst at once, then inbound_rate a
+# second. Above that the peer's requests go unanswered for inbound_cooldown_secs, and
+# with inbound_disconnect its connections are closed too. Configured bootstrap peers
+# are never throttled. An inbound_rate of 0 turns throttling off
+inbound_rate = 50.0
+inbound_burst = 200
+inbound_cooldown_secs = 60
+inbound_disconnect = false
+
+# Puts that import and putmany keep in flight at once; each waits on the network on
+# its own, so more of them overlap their lookups at the cost of a burstier load
+put_concurrency = 16
+
+# How command responses are printed: "text" for people, or "json" for one JSON
+# object per line for scripts
+output = "text"
+
+# How names become DHT keys: "raw" uses the name itself, "sha256" its hash. Hashing
+# spreads keys evenly and keeps names out of the key space, but nodes only find
+# records written with the same setting, so a network must agree on it
+key_hashing = "raw"
+
+# How keys given to commands and the HTTP API are written: "utf8" names, "hex"
+# or "base64" bytes. Binary keys are stored under exactly those bytes, without
+# key_hashing. With utf8, a key of 0x and hex digits, or b64: and base64, is
+# also read as bytes, e.g. get 0xdeadbeef
+key_encoding = "utf8"
+
+# Kademlia protocol name. Nodes only route through peers that speak the same name,
+# so every node of a network must agree on it; a distinct name keeps a deployment
+# apart from any other DHT on the same hosts or LAN
+protocol_name = "/dht/kad/1.0.0"
+
+# Log filter in RUST_LOG syntax, e.g. "debug" or "warn,libp2p_kad=debug"; omit to use RUST_LOG
+# log_level = "info"
+
+# Log every step of each outgoing dial: the address tried, the connection opening,
+# the security and muxer upgrade, and the full reason an attempt failed
+trace_dials = false
+"#;
+
+/// Settings `config set` can change while the node runs, taking effect from
+/// the next request; the others are only read at startup.
+pub const RUNTIME_SETTINGS: &[&str] = &[
+    "log_level",
+    "default_quorum",
+    "get_timeout_secs",
+    "get_retries",
+    "inbound_rate",
+    "inbound_burst",
+    "inbound_cooldown_secs",
+    "command_rate",
+    "command_burst",
+];
+
+/// Longest `get_timeout_secs` or `inbound_cooldown_secs` accepted: a day.
+pub const MAX_TIMEOUT_SECS: u64 = 24 * 60 * 60;
+
+/// Most `get_retries` accepted.
+pub const MAX_GET_RETRIES: u32 = 100;
+
+/// Settings for a node. Every field has a default, so a config file only
+/// needs to mention the values it changes.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub transport: String,
+    pub listen: Vec<String>,
+    pub ipv6: bool,
+    pub public_only: bool,
+    pub bootstrap: Vec<String>,
+    pub bootstrap_url: Option<String>,
+    pub relays: Vec<String>,
+    pub psk_path: Option<PathBuf>,
+    pub security: String,
+    pub dial_timeout_secs: u64,
+    pub prune_after_failures: u32,
+    pub idle_timeout_secs: u64,
+    pub max_connections: Option<u32>,
+    pub max_pending_connections: Option<u32>,
+    pub query_timeout_secs: u64,
+    pub get_timeout_secs: Option<u64>,
+    pub get_retries: u32,
+    pub default_quorum: String,
+    pub watch_interval_secs: u64,
+    pub freshness_secs: u64,
+    pub swr: bool,
+    pub swr_grace_secs: u64,
+    pub record_ttl_secs: u64,
+    pub republish_interval_secs: Option<u64>,
+    pub replication_interval_secs: u64,
+    pub refresh_interval_secs: u64,
+    pub replication_factor: usize,
+    pub max_record_bytes: usize,
+    pub max_records: usize,
+    pub cache_capacity: usize,
+    pub store_path: Option<PathBuf>,
+    pub identity_path: Option<PathBuf>,
+    pub seed: Option<String>,
+    pub history_path: Option<PathBuf>,
+    pub event_log: Option<PathBuf>,
+    pub event_log_max_bytes: u64,
+    pub event_log_keep: usize,
+    pub http_addr: Option<SocketAddr>,
+    pub dns_addr: Option<SocketAddr>,
+    pub metrics_addr: Option<SocketAddr>,
+    pub control_socket: Option<PathBuf>,
+    pub command_rate: f64,
+    pub command_burst: u32,
+    pub inbound_rate: f64,
+    pub inbound_burst: u32,
+    pub inbound_cooldown_secs: u64,
+    pub inbound_disconnect: bool,
+    pub put_concurrency: usize,
+    pub output: String,
+    pub key_hashing: String,
+    pub key_encoding: String,
+    pub protocol_name: String,
+    pub log_level: Option<String>,
+    pub trace_dials: bool,
+    pub compress: bool,
+    pub require_signed: bool,
+    pub readonly: bool,
+    pub allow_suffixes: Vec<String>,
+    pub trust_anchor: Option<String>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            transport: "both".to_string(),
+            listen: Vec::new(),
+            ipv6: true,
+            public_only: false,
+            bootstrap: Vec::new(),
+            bootstrap_url: None,
+            relays: Vec::new(),
+            psk_path: None,
+            security: "noise".to_string(),
+            dial_timeout_secs: 10,
+            prune_after_failures: 3,
+            idle_timeout_secs: 60,
+            max_connections: None,
+            max_pending_connections: None,
+            query_timeout_secs: 10,
+            get_timeout_secs: None,
+            get_retries: 0,
+            default_quorum: "1".to_string(),
+            watch_interval_secs: 10,
+            freshness_secs: 60,
+            swr: false,
+            swr_grace_secs: 300,
+            record_ttl_secs: 24 * 60 * 60,
+            republish_interval_secs: None,
+            replication_interval_secs: 60 * 60,
+            refresh_interval_secs: 5 * 60,
+            replication_factor: 20,
+            max_record_bytes: 65 * 1024,
+            max_records: 1024,
+            cache_capacity: 1024,
+            store_path: None,
+            identity_path: None,
+            seed: None,
+            history_path: None,
+            event_log: None,
+            event_log_max_bytes: 10 * 1024 * 1024,
+            event_log_keep: 3,
+            http_addr: None,
+            dns_addr: None,
+            metrics_addr: None,
+            control_socket: None,
+            command_rate: 10.0,
+            command_burst: 20,
+            inbound_rate: 50.0,
+            inbound_burst: 200,
+            inbound_cooldown_secs: 60,
+            inbound_disconnect: false,
+            put_concurrency: 16,
+            output: "text".to_string(),
+            key_hashing: "raw".to_string(),
+            key_encoding: "utf8".to_string(),
+            protocol_name: "/dht/kad/1.0.0".to_string(),
+            log_level: None,
+            trace_dials: false,
+            compress: false,
+            require_signed: false,
+            readonly: false,
+            allow_suffixes: Vec::new(),
+            trust_anchor: None,
+        }
+    }
+}
+
+impl Config {
+    /// Read a config file.
+    pub fn load(path: &Path) -> Result<Self, DhtError> {
+        let text = fs::read_to_string(path)
+            .map_err(|e| DhtError::Config(format!("could not read config {}: {}", path.display(), e)))?;
+        let config = toml::from_str(&text)
+            .map_err(|e| DhtError::Config(format!("invalid config {}: {}", path.display(), e)))?;
+        Ok(config)
+    }
+
+    /// Build the configuration from the command line.
+    ///
+    /// Starts from `--config <path>` when given (or the defaults otherwise) and
+    /// then applies any explicit flags on top.
+    pub fn from_args(args: &ConfigArgs) -> Result<Self, DhtError> {
+        let mut config = match &args.config {
+            Some(path) => Config::load(path)?,
+            None => Config::default(),
+        };
+
+        if let Some(transport) = &args.transport {
+            config.transport = transport.clone();
+        }
+        if args.no_ipv6 {
+            config.ipv6 = false;
+        }
+        if args.public_only {
+            config.public_only = true;
+        }
+        // Each --listen adds an address, and --port is shorthand for TCP on every interface
+        let mut listen = args.listen.clone();
+        if let Some(port) = args.port {
+            listen.push(format!("/ip4/0.0.0.0/tcp/{}", port));
+            if config.ipv6 {
+                listen.push(format!("/ip6/::/tcp/{}", port));
+            }
+        }
+        if !listen.is_empty() {
+            for addr in &listen {
+                addr.parse::<Multiaddr>().map_err(|e| DhtError::Config(format!("invalid --listen address '{}': {}", addr, e)))?;
+            }
+            config.listen = listen;
+        }
+        if !args.relay.is_empty() {
+            config.relays = args.relay.clone();
+        }
+        if let Some(path) = &args.psk {
+            config.psk_path = Some(path.clone());
+        }
+        set(&mut config.security, args.security.clone());
+        if !args.bootstrap.is_empty() {
+            config.bootstrap = args.bootstrap.iter().filter(|entry| !entry.is_empty()).cloned().collect();
+        }
+        set_some(&mut config.bootstrap_url, args.bootstrap_url.clone());
+        set(&mut config.dial_timeout_secs, args.dial_timeout);
+        set(&mut config.prune_after_failures, args.prune_after_failures);
+        set(&mut config.idle_timeout_secs, args.idle_timeout);
+        set_some(&mut config.max_connections, args.max_connections);
+        set_some(&mut config.max_pending_connections, args.max_pending);
+        set(&mut config.query_timeout_secs, args.query_timeout);
+        set_some(&mut config.get_timeout_secs, args.get_timeout);
+        set(&mut config.get_retries, args.get_retries);
+        set(&mut config.default_quorum, args.default_quorum.clone());
+        set(&mut config.watch_interval_secs, args.watch_interval);
+        set(&mut config.freshness_secs, args.freshness);
+        if args.swr {
+            config.swr = true;
+        }
+        set(&mut config.swr_grace_secs, args.swr_grace);
+        set(&mut config.record_ttl_secs, args.record_ttl);
+        set_some(&mut config.republish_interval_secs, args.republish_interval);
+        set(&mut config.replication_interval_secs, args.replication_interval);
+        set(&mut config.refresh_interval_secs, args.refresh_interval);
+        set(&mut config.replication_factor, args.replication_factor);
+        set(&mut config.max_record_bytes, args.max_record_bytes);
+        set(&mut config.max_records, args.max_records);
+        set(&mut config.cache_capacity, args.cache_capacity);
+        set_some(&mut config.store_path, args.store_path.clone());
+        set_some(&mut config.identity_path, args.identity.clone());
+        set_some(&mut config.seed, args.seed.clone());
+        set_some(&mut config.event_log, args.event_log.clone());
+        set(&mut config.event_log_max_bytes, args.event_log_max_bytes);
+        set(&mut config.event_log_keep, args.event_log_keep);
+        set_some(&mut config.http_addr, args.http_addr);
+        set_some(&mut config.dns_addr, args.dns_addr);
+        set_some(&mut config.metrics_addr, args.metrics_addr);
+        set_some(&mut config.control_socket, args.control_socket.clone());
+        set(&mut config.command_rate, args.command_rate);
+        set(&mut config.command_burst, args.command_burst);
+        set(&mut config.inbound_rate, args.inbound_rate);
+        set(&mut config.inbound_burst, args.inbound_burst);
+        set(&mut config.inbound_cooldown_secs, args.inbound_cooldown);
+        if args.inbound_disconnect {
+            config.inbound_disconnect = true;
+        }
+        set(&mut config.put_concurrency, args.put_concurrency);
+        set(&mut config.output, args.output.clone());
+        set(&mut config.key_hashing, args.key_hashing.clone());
+        set(&mut config.key_encoding, args.key_encoding.clone());
+        set(&mut config.protocol_name, args.protocol_name.clone());
+        set_some(&mut config.log_level, args.log_level.clone());
+        if args.trace_dials {
+            config.trace_dials = true;
+        }
+        if args.compress {
+            config.compress = true;
+        }
+        if args.require_signed {
+            config.require_signed = true;
+        }
+        if args.readonly {
+            config.readonly = true;
+        }
+        if !args.allow_suffix.is_empty() {
+            config.allow_suffixes = args.allow_suffix.clone();
+        }
+        set_some(&mut config.trust_anchor, args.trust_anchor.clone());
+
+        // Runtime settings are checked whether they came from the file or the command line
+        config.check_runtime()?;
+        Ok(config)
+    }
+
+    /// The value of setting `key`, as named in the config file, or `None` if there is no such setting.
+    pub fn get(&self, key: &str) -> Option<Value> {
+        match serde_json::to_value(self) {
+            Ok(Value::Object(mut fields)) => fields.remove(key),
+            _ => None,
+        }
+    }
+
+    /// Change setting `key` to `value`, written as in the config file, or bare
+    /// for text; `none` unsets an optional setting. Only [`RUNTIME_SETTINGS`]
+    /// can be changed, and on error the config is left as it was.
+    pub fn set(&mut self, key: &str, value: &str) -> Result<(), DhtError> {
+        let Some(current) = self.get(key) else {
+            return Err(DhtError::Config(format!("unknown setting '{}'", key)));
+        };
+        if !RUNTIME_SETTINGS.contains(&key) {
+            return Err(DhtError::Config(format!("{} requires restart; set it in the config file or on the command line", key)));
+        }
+        let value = match current {
+            _ if value == "none" => Value::Null,
+            Value::String(_) => Value::String(value.to_string()),
+            _ => serde_json::from_str(value).unwrap_or_else(|_| Value::String(value.to_string())),
+        };
+        let Ok(Value::Object(mut fields)) = serde_json::to_value(&*self) else {
+            return Err(DhtError::Config("could not read the current settings".to_string()));
+        };
+        fields.insert(key.to_string(), value.clone());
+        let updated: Config = serde_json::from_value(Value::Object(fields))
+            .map_err(|e| DhtError::Config(format!("invalid {} '{}': {}", key, value, e)))?;
+        updated.check_runtime()?;
+        *self = updated;
+        Ok(())
+    }
+
+    // Refuse values of the runtime settings that would stall or overflow the requests using them
+    fn check_runtime(&self) -> Result<(), DhtError> {
+        self.quorum()?;
+        for (key, rate) in [("command_rate", self.command_rate), ("inbound_rate", self.inbound_rate)] {
+            if !rate.is_finite() || rate < 0.0 {
+                return Err(DhtError::Config(format!("invalid {} '{}': expected a number of requests a second, 0 or more", key, rate)));
+            }
+        }
+        for (key, burst) in [("command_burst", self.command_burst), ("inbound_burst", self.inbound_burst)] {
+            if burst == 0 {
+                return Err(DhtError::Config(format!("invalid {} '0': expected at least 1", key)));
+            }
+        }
+        if let Some(secs) = self.get_timeout_secs
+            && !(1..=MAX_TIMEOUT_SECS).contains(&secs)
+        {
+            return Err(DhtError::Config(format!("invalid get_timeout_secs '{}': expected 1 to {}", secs, MAX_TIMEOUT_SECS)));
+        }
+        if self.inbound_cooldown_secs > MAX_TIMEOUT_SECS {
+            return Err(DhtError::Config(format!("invalid inbound_cooldown_secs '{}': expected at most {}", self.inbound_cooldown_secs, MAX_TIMEOUT_SECS)));
+        }
+        if self.get_retries > MAX_GET_RETRIES {
+            return Err(DhtError::Config(format!("invalid get_retries '{}': expected at most {}", self.get_retries, MAX_GET_RETRIES)));
+        }
+        Ok(())
+    }
+
+    pub fn dial_timeout(&self) -> Duration {
+        Duration::from_secs(self.dial_timeout_secs)
+    }
+
+    pub fn idle_timeout(&self) -> Duration {
+        Duration::from_secs(self.idle_timeout_secs)
+    }
+
+    pub fn inbound_cooldown(&self) -> Duration {
+        Duration::from_secs(self.inbound_cooldown_secs)
+    }
+
+    pub fn query_timeout(&self) -> Duration {
+        Duration::from_secs(self.query_timeout_secs)
+    }
+
+    /// How long one attempt of a `get` may take, defaulting to the query timeout.
+    pub fn get_timeout(&self) -> Duration {
+        self.get_timeout_secs.map(Duration::from_secs).unwrap_or_else(|| self.query_timeout())
+    }
+
+    pub fn quorum(&self) -> Result<Quorum, DhtError> {
+        parse_quorum(&self.default_quorum).map_err(|e| DhtError::Config(format!("invalid default_quorum: {}", e)))
+    }
+
+    pub fn output(&self) -> Result<Output, DhtError> {
+        match self.output.as_str() {
+            "text" => Ok(Output::Text),
+            "json" => Ok(Output::Json),
+            other => Err(DhtError::Config(format!("unknown output '{}', expected text or json", other))),
+        }
+    }
+
+    pub fn key_hashing(&self) -> Result<KeyHashing, DhtError> {
+        match self.key_hashing.as_str() {
+            "raw" => Ok(KeyHashing::Raw),
+            "sha256" => Ok(KeyHashing::Sha256),
+            other => Err(DhtError::Config(format!("unknown key hashing '{}', expected raw or sha256", other))),
+        }
+    }
+
+    pub fn key_encoding(&self) -> Result<KeyEncoding, DhtError> {
+        match self.key_encoding.as_str() {
+            "utf8" => Ok(KeyEncoding::Utf8),
+            "hex" => Ok(KeyEncoding::Hex),
+            "base64" => Ok(KeyEncoding::Base64),
+            other => Err(DhtError::Config(format!("unknown key encoding '{}', expected utf8, hex or base64", other))),
+        }
+    }
+
+    /// The validator built from `allow_suffixes`, if any are set.
+    pub fn suffix_validator(&self) -> Result<Option<SuffixValidator>, DhtError> {
+        if self.allow_suffixes.is_empty() {
+            return Ok(None);
+        }
+        if self.key_hashing()? != KeyHashing::Raw {
+            return Err(DhtError::Config("allow_suffixes needs key_hashing = \"raw\", hashed keys carry no name to check".to_string()));
+        }
+        Ok(Some(SuffixValidator::new(&self.allow_suffixes)))
+    }
+
+    pub fn trust_anchor(&self) -> Result<Option<TrustAnchor>, DhtError> {
+        self.trust_anchor.as_deref().map(str::parse).transpose().map_err(DhtError::Config)
+    }
+
+    pub fn protocol_name(&self) -> Result<StreamProtocol, DhtError> {
+        StreamProtocol::try_from_owned(self.protocol_name.clone())
+            .map_err(|_| DhtError::Config(format!("invalid protocol name '{}', it must start with /", self.protocol_name)))
+    }
+
+    pub fn watch_interval(&self) -> Duration {
+        Duration::from_secs(self.watch_interval_secs)
+    }
+
+    pub fn freshness(&self) -> Duration {
+        Duration::from_secs(self.freshness_secs)
+    }
+
+    /// How long past its expiry a copy is still served stale, zero unless `swr` is on.
+    pub fn swr_grace(&self) -> Duration {
+        if self.swr { Duration::from_secs(self.swr_grace_secs) } else { Duration::ZERO }
+    }
+
+    pub fn record_ttl(&self) -> Duration {
+        Duration::from_secs(self.record_ttl_secs)
+    }
+
+    /// How often our records are re-published, which must be shorter than the
+    /// record TTL or they would expire on other peers before being refreshed.
+    pub fn republish_interval(&self) -> Result<Duration, DhtError> {
+        let interval = self.republish_interval_secs
+            .map(Duration::from_secs)
+            .unwrap_or_else(|| self.record_ttl() / 2);
+        if interval.is_zero() || interval >= self.record_ttl() {
+            return Err(DhtError::Config(format!(
+                "republish interval of {}s must be between 1s and the record TTL of {}s",
+                interval.as_secs(), self.record_ttl_secs,
+            )));
+        }
+        Ok(interval)
+    }
+
+    pub fn replication_interval(&self) -> Duration {
+        Duration::from_secs(self.replication_interval_secs)
+    }
+
+    /// `None` when periodic routing table refreshes are off.
+    pub fn refresh_interval(&self) -> Option<Duration> {
+        (self.refresh_interval_secs > 0).then(|| Duration::from_secs(self.refresh_interval_secs))
+    }
+
+    pub fn replication_factor(&self) -> Result<NonZeroUsize, DhtError> {
+        NonZeroUsize::new(self.replication_factor).ok_or_else(|| DhtError::Config("replication_factor must be at least 1".to_string()))
+    }
+
+    pub fn store_path(&self) -> PathBuf {
+        self.store_path.clone().unwrap_or_else(crate::persist::default_store_path)
+    }
+
+    pub fn identity_path(&self) -> PathBuf {
+        self.identity_path.clone().unwrap_or_else(crate::persist::default_identity_path)
+    }
+
+    /// The seed the node key is derived from, if set; it cannot be combined with `identity_path`.
+    pub fn seed(&self) -> Result<Option<&str>, DhtError> {
+        match (&self.seed, &self.identity_path) {
+            (Some(_), Some(_)) => Err(DhtError::Config("seed and identity_path cannot both be set: the seed derives the key instead of loading it".to_string())),
+            (seed, _) => Ok(seed.as_deref()),
+        }
+    }
+
+    pub fn history_path(&self) -> PathBuf {
+        self.history_path.clone().unwrap_or_else(crate::persist::default_history_path)
+    }
+
+    pub fn security(&self) -> Result<Security, DhtError> {
+        match self.security.as_str() {
+            "noise" => Ok(Security::Noise),
+            "tls" => Ok(Security::Tls),
+            "both" => Ok(Security::Both),
+            other => Err(DhtError::Config(format!("unknown security '{}', expected noise, tls or both", other))),
+        }
+    }
+
+    /// The private network key read from `psk_path`, if one is configured.
+    pub fn psk(&self) -> Result<Option<PreSharedKey>, DhtError> {
+        let Some(path) = &self.psk_path else {
+            return Ok(None);
+        };
+        let text = fs::read_to_string(path)
+            .map_err(|e| DhtError::Config(format!("could not read pre-shared key {}: {}", path.display(), e)))?;
+        let psk = text.parse()
+            .map_err(|e| DhtError::Config(format!("invalid pre-shared key {}: {}", path.display(), e)))?;
+        Ok(Some(psk))
+    }
+
+    /// Circuit addresses to listen on, one through each configured relay.
+    pub fn relay_listen_addrs(&self) -> Result<Vec<Multiaddr>, DhtError> {
+        self.relays.iter()
+            .map(|relay| {
+                let addr: Multiaddr = relay.parse().map_err(|e| DhtError::Config(format!("invalid relay address '{}': {}", relay, e)))?;
+                if !matches!(addr.iter().last(), Some(Protocol::P2p(_))) {
+                    return Err(DhtError::Config(format!("relay address '{}' must end in /p2p/<peer_id>", relay)));
+                }
+                Ok(addr.with(Protocol::P2pCircuit))
+            })
+            .collect()
+    }
+
+    /// Addresses to listen on: the explicit `listen` list, or the defaults for
+    /// `transport`, on IPv6 as well as IPv4 when `ipv6` is set.
+    pub fn listen_addrs(&self) -> Result<Vec<Multiaddr>, DhtError> {
+        let addrs: Vec<String> = if !self.listen.is_empty() {
+            self.listen.clone()
+        } else {
+            let defaults: &[&str] = match self.transport.as_str() {
+                "tcp" => &["/tcp/0"],
+                "quic" => &["/udp/0/quic-v1"],
+                "ws" => &["/tcp/0/ws"],
+                "both" => &["/tcp/0", "/udp/0/quic-v1"],
+                other => return Err(DhtError::Config(format!("unknown transport '{}', expected tcp, quic, ws or both", other))),
+            };
+            let hosts: &[&str] = if self.ipv6 { &["/ip4/0.0.0.0", "/ip6/::"] } else { &["/ip4/0.0.0.0"] };
+            hosts.iter()
+                .flat_map(|host| defaults.iter().map(move |rest| format!("{}{}", host, rest)))
+                .collect()
+        };
+
+        addrs.iter()
+            .map(|addr| addr.parse().map_err(|e| DhtError::Config(format!("invalid listen address '{}': {}", addr, e))))
+            .collect()
+    }
+}
+
+/// Command line flags overriding the config file, shared by every subcommand.
+/// Each is `None` (or empty) unless given, so the file's value stands.
+#[derive(Debug, Clone, Default, clap::Args)]
+pub struct ConfigArgs {
+    /// TOML config file; flags override its values
+    #[arg(long, global = true, value_name = "PATH")]
+    pub config: Option<PathBuf>,
+    /// Transports to listen on by default [default: both]
+    #[arg(long, global = true, value_parser = ["tcp", "quic", "ws", "both"])]
+    pub transport: Option<String>,
+    /// Address to listen on; repeatable, replaces the transport defaults
+    #[arg(long, global = true, value_name = "MULTIADDR")]
+    pub listen: Vec<String>,
+    /// Shorthand for --listen /ip4/0.0.0.0/tcp/<PORT>, plus /ip6/::/tcp/<PORT>
+    #[arg(long, global = true)]
+    pub port: Option<u16>,
+    /// Listen on IPv4 only
+    #[arg(long, global = true)]
+    pub no_ipv6: bool,
+    /// Leave loopback, link-local and private addresses out of what is shared and printed
+    #[arg(long, global = true)]
+    pub public_only: bool,
+    /// Peers to join through, each ending in /p2p/<peer_id>
+    #[arg(long, global = true, value_name = "MULTIADDR", value_delimiter = ',')]
+    pub bootstrap: Vec<String>,
+    /// Also join the peers listed at this URL, as JSON or one multiaddr per line
+    #[arg(long, global = true, value_name = "URL")]
+    pub bootstrap_url: Option<String>,
+    /// Reserve a slot on a relay ending in /p2p/<peer_id>; repeatable
+    #[arg(long, global = true, value_name = "MULTIADDR")]
+    pub relay: Vec<String>,
+    /// Pre-shared swarm.key; only nodes with the same key connect, and QUIC is off
+    #[arg(long, global = true, value_name = "FILE")]
+    pub psk: Option<PathBuf>,
+    /// Security handshake; both lets the remote pick noise or TLS [default: noise]
+    #[arg(long, global = true, value_parser = ["noise", "tls", "both"])]
+    pub security: Option<String>,
+    /// Seconds to establish a connection [default: 10]
+    #[arg(long, global = true, value_name = "SECS")]
+    pub dial_timeout: Option<u64>,
+    /// Failures in a row after which a peer's last address, and the peer, leave the routing table, 0 for never [default: 3]
+    #[arg(long, global = true, value_name = "N")]
+    pub prune_after_failures: Option<u32>,
+    /// Seconds an idle connection stays open; longer speeds repeat lookups but holds more file descriptors [default: 60]
+    #[arg(long, global = true, value_name = "SECS")]
+    pub idle_timeout: Option<u64>,
+    /// Established connections [default: unlimited]
+    #[arg(long, global = true, value_name = "N")]
+    pub max_connections: Option<u32>,
+    /// Connections being set up in each direction [default: unlimited]
+    #[arg(long, global = true, value_name = "N")]
+    pub max_pending: Option<u32>,
+    /// Seconds before a DHT query is abandoned [default: 10]
+    #[arg(long, global = true, value_name = "SECS")]
+    pub query_timeout: Option<u64>,
+    /// Seconds per attempt of a get [default: the query timeout]
+    #[arg(long, global = true, value_name = "SECS")]
+    pub get_timeout: Option<u64>,
+    /// Times a timed-out get is re-issued [default: 0]
+    #[arg(long, global = true, value_name = "N")]
+    pub get_retries: Option<u32>,
+    /// Quorum for commands that name none: all, majority or a number; higher quorums fail on small networks [default: 1]
+    #[arg(long, global = true, value_name = "QUORUM")]
+    pub default_quorum: Option<String>,
+    /// Seconds between lookups of a watched key [default: 10]
+    #[arg(long, global = true, value_name = "SECS")]
+    pub watch_interval: Option<u64>,
+    /// Age in seconds after which get checks the DHT for a newer local copy [default: 60]
+    #[arg(long, global = true, value_name = "SECS")]
+    pub freshness: Option<u64>,
+    /// Serve copies that expired within the grace window at once while refreshing them in the background
+    #[arg(long, global = true)]
+    pub swr: bool,
+    /// Seconds past expiry a copy is served stale with --swr [default: 300]
+    #[arg(long, global = true, value_name = "SECS")]
+    pub swr_grace: Option<u64>,
+    /// Default TTL for puts in seconds [default: 86400]
+    #[arg(long, global = true, value_name = "SECS")]
+    pub record_ttl: Option<u64>,
+    /// Seconds between re-publishing our records [default: half the TTL]
+    #[arg(long, global = true, value_name = "SECS")]
+    pub republish_interval: Option<u64>,
+    /// Seconds between re-replicating others' records [default: 3600]
+    #[arg(long, global = true, value_name = "SECS")]
+    pub replication_interval: Option<u64>,
+    /// Seconds between routing table refreshes; 0 turns them off [default: 300]
+    #[arg(long, global = true, value_name = "SECS")]
+    pub refresh_interval: Option<u64>,
+    /// Peers each record is stored on [default: 20]
+    #[arg(long, global = true, value_name = "N")]
+    pub replication_factor: Option<usize>,
+    /// Largest value a put accepts, in bytes [default: 66560]
+    #[arg(long, global = true, value_name = "N")]
+    pub max_record_bytes: Option<usize>,
+    /// Local store capacity [default: 1024]
+    #[arg(long, global = true, value_name = "N")]
+    pub max_records: Option<usize>,
+    /// Values of earlier lookups kept for repeated gets, 0 for none [default: 1024]
+    #[arg(long, global = true, value_name = "N")]
+    pub cache_capacity: Option<usize>,
+    /// Where records are saved [default: ~/.dht/records.db]
+    #[arg(long, global = true, value_name = "PATH")]
+    pub store_path: Option<PathBuf>,
+    /// Node key, created on first run [default: ~/.dht/identity]
+    #[arg(long, global = true, value_name = "PATH")]
+    pub identity: Option<PathBuf>,
+    /// Derive the node key from this string, for peer ids stable across machines
+    #[arg(long, global = true, value_name = "STRING", conflicts_with = "identity")]
+    pub seed: Option<String>,
+    /// Append a JSON line for each operation, query result and connection event
+    #[arg(long, global = true, value_name = "PATH")]
+    pub event_log: Option<PathBuf>,
+    /// Size at which the event log is rotated, 0 for never [default: 10485760]
+    #[arg(long, global = true, value_name = "BYTES")]
+    pub event_log_max_bytes: Option<u64>,
+    /// Rotated event logs kept [default: 3]
+    #[arg(long, global = true, value_name = "N")]
+    pub event_log_keep: Option<usize>,
+    /// Serve the HTTP API, with /healthz and /readyz
+    #[arg(long, global = true, value_name = "IP:PORT")]
+    pub http_addr: Option<SocketAddr>,
+    /// Answer DNS queries over UDP
+    #[arg(long, global = true, value_name = "IP:PORT")]
+    pub dns_addr: Option<SocketAddr>,
+    /// Serve Prometheus metrics at /metrics
+    #[arg(long, global = true, value_name = "IP:PORT")]
+    pub metrics_addr: Option<SocketAddr>,
+    /// Take commands on a Unix socket at this path, as on stdin
+    #[arg(long, global = true, value_name = "PATH")]
+    pub control_socket: Option<PathBuf>,
+    /// Stdin commands of each kind per second, 0 for no limit [default: 10]
+    #[arg(long, global = true, value_name = "N")]
+    pub command_rate: Option<f64>,
+    /// Stdin commands of each kind allowed at once [default: 20]
+    #[arg(long, global = true, value_name = "N")]
+    pub command_burst: Option<u32>,
+    /// Kademlia requests a second each peer may send once its burst is spent; 0 is unlimited [default: 50]
+    #[arg(long, global = true, value_name = "N")]
+    pub inbound_rate: Option<f64>,
+    /// Kademlia requests each peer may send at once [default: 200]
+    #[arg(long, global = true, value_name = "N")]
+    pub inbound_burst: Option<u32>,
+    /// Seconds a peer over its request limit goes unanswered [default: 60]
+    #[arg(long, global = true, value_name = "SECS")]
+    pub inbound_cooldown: Option<u64>,
+    /// Also disconnect peers that go over their request limit
+    #[arg(long, global = true)]
+    pub inbound_disconnect: bool,
+    /// Puts import and putmany keep in flight [default: 16]
+    #[arg(long, global = true, value_name = "N", value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(1..))]
+    pub put_concurrency: Option<usize>,
+    /// How responses are printed; json prints one JSON object per command [default: text]
+    #[arg(long, global = true, value_parser = ["text", "json"])]
+    pub output: Option<String>,
+    /// Store names under their SHA-256 hash; all nodes must agree [default: raw]
+    #[arg(long, global = true, value_parser = ["raw", "sha256"])]
+    pub key_hashing: Option<String>,
+    /// Read keys as names, or as hex or base64 bytes [default: utf8]
+    #[arg(long, global = true, value_parser = ["utf8", "hex", "base64"])]
+    pub key_encoding: Option<String>,
+    /// Kademlia protocol name; all nodes must agree [default: /dht/kad/1.0.0]
+    #[arg(long, global = true, value_name = "NAME")]
+    pub protocol_name: Option<String>,
+    /// Log filter, e.g. debug; overrides RUST_LOG
+    #[arg(long, global = true, value_name = "FILTER")]
+    pub log_level: Option<String>,
+    /// Log each step of every dial and the full reason it failed
+    #[arg(long, global = true)]
+    pub trace_dials: bool,
+    /// Store values zstd-compressed when that makes them smaller
+    #[arg(long, global = true)]
+    pub compress: bool,
+    /// Refuse to serve or accept unsigned records
+    #[arg(long, global = true)]
+    pub require_signed: bool,
+    /// Refuse local writes and records pushed by peers, serving only reads
+    #[arg(long, global = true)]
+    pub readonly: bool,
+    /// Only accept names at or below this domain, e.g. example.com; repeatable
+    #[arg(long, global = true, value_name = "SUFFIX", value_delimiter = ',')]
+    pub allow_suffix: Vec<String>,
+    /// Only resolve records signed through delegations from this zone and key [default: accept any record]
+    #[arg(long, global = true, value_name = "ZONE=PEER_ID")]
+    pub trust_anchor: Option<String>,
+}
+
+// Override `field` with a flag's value when the flag was given
+fn set<T>(field: &mut T, flag: Option<T>) {
+    if let Some(value) = flag {
+        *field = value;
+    }
+}
+
+fn set_some<T>(field: &mut Option<T>, flag: Option<T>) {
+    if flag.is_some() {
+        *field = flag;
+    }
+}
+
+/// How the command line prints command responses.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Output {
+    Text,
+    /// Exactly one JSON object per line for each command.
+    Json,
+}
+
+/// The security handshakes offered on TCP, WebSocket and relayed connections.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Security {
+    Noise,
+    /// TLS 1.3 with a certificate made from the node's keypair, as QUIC uses.
+    Tls,
+    /// Noise preferred, TLS for peers that only speak TLS.
+    Both,
+}
+
+/// Parse a quorum given as `all`, `majority` or a positive number of peers.
+pub fn parse_quorum(quorum: &str) -> Result<Quorum, String> {
+    match quorum.to_ascii_lowercase().as_str() {
+        "all" => Ok(Quorum::All),
+        "majority" => Ok(Quorum::Majority),
+        count => match count.parse::<usize>() {
+            Ok(1) => Ok(Quorum::One),
+            Ok(n) => NonZeroUsize::new(n)
+                .map(Quorum::N)
+                .ok_or_else(|| "quorum must be at least 1".to_string()),
+            Err(_) => Err(format!("invalid quorum '{}', expected all, majority or a number", quorum)),
+        },
+    }
+}
+
+/// Remove a boolean `<name>` switch from the arguments, returning whether it was present.
+pub fn take_switch(args: &mut Vec<String>, name: &str) -> bool {
+    match args.iter().position(|arg| arg == name) {
+        Some(index) => {
+            args.remove(index);
+            true
+        }
+        None => false,
+    }
+}
@@ -0,0 +1,133 @@
+//! Optional Unix domain socket taking the same text commands as stdin, so
+//! scripts can drive a daemonized node without opening a TCP port.
+//!
+//! Each connection sends commands one per line and reads their output back;
+//! commands run as they arrive, so output of slow ones may follow later
+//! commands. The server closes a connection once its client has stopped
+//! sending and every command it sent has finished. Several connections can be
+//! open at once.
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use tokio::sync::mpsc;
+
+/// A command line read from a connection, with the value that followed it for
+/// `put <key> -` and the channel its output lines go back on.
+pub struct Request {
+    pub line: String,
+    pub value: Option<Vec<u8>>,
+    pub output: mpsc::UnboundedSender<String>,
+}
+
+/// A socket accepting connections in the background; dropping it removes the socket file.
+pub struct ControlSocket {
+    path: PathBuf,
+    accepting: tokio::task::JoinHandle<()>,
+}
+
+impl ControlSocket {
+    /// Listen at `path`, readable and writable by the owner only, handing each
+    /// command to `requests`. Missing directories above it are created owner-only too. `takes_value` says whether a command line is
+    /// followed by a value, read up to a blank line as on stdin.
+    ///
+    /// A socket file left by a node that is no longer running is replaced; one
+    /// still answering is an error. Must be called from within a Tokio runtime.
+    #[cfg(unix)]
+    pub async fn open(path: &Path, takes_value: fn(&str) -> bool, requests: mpsc::Sender<Request>) -> io::Result<Self> {
+        use std::os::unix::fs::{DirBuilderExt, FileTypeExt, PermissionsExt};
+        use tokio::net::{UnixListener, UnixStream};
+
+        if fs::symlink_metadata(path).is_ok_and(|metadata| metadata.file_type().is_socket()) {
+            if UnixStream::connect(path).await.is_ok() {
+                return Err(io::Error::new(io::ErrorKind::AddrInUse, "in use by a running node"));
+            }
+            fs::remove_file(path)?;
+        }
+        let parent = path.parent().filter(|parent| !parent.as_os_str().is_empty()).unwrap_or(Path::new("."));
+        fs::DirBuilder::new().recursive(true).mode(0o700).create(parent)?;
+        // Bind in a directory only we can enter, then move the socket into place once it is
+        // owner-only, so it is never reachable with the looser permissions it is created with
+        let staging = parent.join(format!(".control-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&staging);
+        fs::DirBuilder::new().mode(0o700).create(&staging)?;
+        let staged = staging.join("s");
+        let listener = UnixListener::bind(&staged)
+            .and_then(|listener| fs::set_permissions(&staged, fs::Permissions::from_mode(0o600)).map(|()| listener))
+            .and_then(|listener| fs::rename(&staged, path).map(|()| listener));
+        let _ = fs::remove_dir_all(&staging);
+        let listener = listener?;
+        let shown = path.display().to_string();
+        let accepting = tokio::spawn(async move {
+            loop {
+                match listener.accept().await {
+                    Ok((stream, _)) => {
+                        tokio::spawn(connection(stream, takes_value, requests.clone()));
+                    }
+                    Err(e) => tracing::warn!("control socket {}: could not accept a connection: {}", shown, e),
+                }
+            }
+        });
+        Ok(ControlSocket { path: path.to_path_buf(), accepting })
+    }
+
+    #[cfg(not(unix))]
+    pub async fn open(_path: &Path, _takes_value: fn(&str) -> bool, _requests: mpsc::Sender<Request>) -> io::Result<Self> {
+        Err(io::Error::new(io::ErrorKind::Unsupported, "control sockets need a Unix system"))
+    }
+}
+
+impl Drop for ControlSocket {
+    fn drop(&mut self) {
+        self.accepting.abort();
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+// Read commands off one connection and write back their output
+#[cfg(unix)]
+async fn connection(stream: tokio::net::UnixStream, takes_value: fn(&str) -> bool, requests: mpsc::Sender<Request>) {
+    use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+
+    let (reader, mut writer) = stream.into_split();
+    let (output, mut lines) = mpsc::unbounded_channel::<String>();
+    let reading = async move {
+        let mut reader = BufReader::new(reader).lines();
+        while let Ok(Some(line)) = reader.next_line().await {
+            let line = line.trim().to_string();
+            if line.is_empty() {
+                continue;
+            }
+            let value = if takes_value(&line) {
+                let mut value = Vec::new();
+                while let Ok(Some(value_line)) = reader.next_line().await {
+                    let value_line = value_line.strip_suffix('\r').unwrap_or(&value_line);
+                    if value_line.is_empty() {
+                        break;
+                    }
+                    if !value.is_empty() {
+                        value.push(b'\n');
+                    }
+                    value.extend_from_slice(value_line.as_bytes());
+                }
+                Some(value)
+            } else {
+                None
+            };
+            if requests.send(Request { line, value, output: output.clone() }).await.is_err() {
+                break;
+            }
+        }
+        // The connection stays open for output until the commands holding a clone are done
+        drop(output);
+    };
+    let writing = async move {
+        while let Some(mut line) = lines.recv().await {
+            line.push('\n');
+            if writer.write_all(line.as_bytes()).await.is_err() {
+                break;
+            }
+        }
+    };
+    tokio::join!(reading, writing);
+}
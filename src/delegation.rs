@@ -0,0 +1,150 @@
+//! Delegated zones and the chain of trust through them, in the spirit of
+//! DNSSEC's DS records.
+//!
+//! A zone is signed by one key, a peer id: every record under it is put by the
+//! node holding that key. A parent zone hands a child zone to another key
+//! with a [`Delegation`], stored under `_delegation.<child>` and signed,
+//! like any record, by the parent's key. Starting from a configured
+//! [`TrustAnchor`], [`zone_key`] follows the delegations down to the zone a
+//! name is in, and so learns which key must have signed its record.
+
+use crate::dns_record::normalize_key;
+use crate::{DhtError, DhtNode};
+use libp2p::PeerId;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::str::FromStr;
+use tracing::debug;
+
+/// Most zones below the trust anchor a name may be nested in, so a lookup of
+/// a long name makes a bounded number of delegation lookups.
+pub const MAX_DELEGATION_DEPTH: usize = 8;
+
+/// A zone and the key trusted to sign it, from the `trust_anchor` setting.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TrustAnchor {
+    pub zone: String,
+    pub key: PeerId,
+}
+
+impl FromStr for TrustAnchor {
+    type Err = String;
+
+    /// Parses `<zone>=<peer_id>`, such as `example.com=12D3KooW...`.
+    fn from_str(anchor: &str) -> Result<Self, String> {
+        let (zone, key) = anchor.split_once('=').ok_or_else(|| format!("trust anchor '{}' is not <zone>=<peer_id>", anchor))?;
+        let zone = normalize_key(zone)?;
+        let key = key.parse().map_err(|e| format!("trust anchor key '{}' is not a peer id: {}", key, e))?;
+        Ok(TrustAnchor { zone, key })
+    }
+}
+
+impl fmt::Display for TrustAnchor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}={}", self.zone, self.key)
+    }
+}
+
+/// The record handing `child` to the key that signs it, signed by the key of `parent`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Delegation {
+    pub parent: String,
+    pub child: String,
+    #[serde(with = "peer_id")]
+    pub key: PeerId,
+}
+
+impl Delegation {
+    /// A delegation of `child` from `parent`, which it must be strictly below.
+    pub fn new(parent: &str, child: &str, key: PeerId) -> Result<Self, DhtError> {
+        let parent = normalize_key(parent).map_err(DhtError::InvalidKey)?;
+        let child = normalize_key(child).map_err(DhtError::InvalidKey)?;
+        if child == parent || !within(&child, &parent) {
+            return Err(DhtError::InvalidCommand(format!("{} is not a zone below {}", child, parent)));
+        }
+        Ok(Delegation { parent, child, key })
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        serde_json::to_vec(self).expect("a delegation always serializes")
+    }
+
+    /// `None` if `value` is not a delegation.
+    pub fn decode(value: &[u8]) -> Option<Self> {
+        serde_json::from_slice(value).ok()
+    }
+}
+
+/// The key a delegation of `child` is stored under.
+pub fn record_key(child: &str) -> String {
+    format!("_delegation.{}", child)
+}
+
+/// Whether `name` is `zone` or a name below it.
+pub fn within(name: &str, zone: &str) -> bool {
+    name == zone || name.strip_suffix(zone).is_some_and(|rest| rest.ends_with('.'))
+}
+
+/// The zone `name` is in and the key that signs it, following delegations
+/// down from `anchor`. A delegation not signed by its parent's key is passed
+/// over, leaving the names below it to the parent; so is one that fails to
+/// decode or names other zones than where it is stored.
+///
+/// Names outside the anchor's zone, or nested more than
+/// [`MAX_DELEGATION_DEPTH`] labels below it, are in no chain of trust.
+pub async fn zone_key(node: &DhtNode, anchor: &TrustAnchor, name: &str) -> Result<(String, PeerId), DhtError> {
+    let untrusted = |reason: String| DhtError::Untrusted { name: name.to_string(), reason };
+    if !within(name, &anchor.zone) {
+        return Err(untrusted(format!("it is outside {}, the zone of the trust anchor", anchor.zone)));
+    }
+    // Zones from just below the anchor down to the name itself
+    let below = name[..name.len() - anchor.zone.len()].trim_end_matches('.');
+    let labels: Vec<&str> = below.split('.').filter(|label| !label.is_empty()).collect();
+    if labels.len() > MAX_DELEGATION_DEPTH {
+        return Err(untrusted(format!("it is more than {} labels below {}", MAX_DELEGATION_DEPTH, anchor.zone)));
+    }
+
+    let (mut zone, mut key) = (anchor.zone.clone(), anchor.key);
+    for depth in (0..labels.len()).rev() {
+        let child = format!("{}.{}", labels[depth..].join("."), anchor.zone);
+        let Some(entry) = node.get_entry(&record_key(&child), node.default_quorum()).await? else {
+            continue;
+        };
+        match Delegation::decode(&entry.value) {
+            Some(delegation) if entry.signer == Some(key) && delegation.parent == zone && delegation.child == child => {
+                zone = child;
+                key = delegation.key;
+            }
+            _ => debug!("ignoring delegation of {}: it is not a delegation from zone {} signed by {}", child, zone, key),
+        }
+    }
+    Ok((zone, key))
+}
+
+/// Check that the record found for `name` was signed by `signer` with the key
+/// of the zone it is in, by [`zone_key`].
+pub async fn verify(node: &DhtNode, anchor: &TrustAnchor, name: &str, signer: Option<PeerId>) -> Result<(), DhtError> {
+    let (zone, key) = zone_key(node, anchor, name).await?;
+    match signer {
+        Some(signer) if signer == key => Ok(()),
+        Some(signer) => Err(DhtError::Untrusted {
+            name: name.to_string(),
+            reason: format!("it is signed by {}, but zone {} is signed by {}", signer, zone, key),
+        }),
+        None => Err(DhtError::Untrusted { name: name.to_string(), reason: "it is not signed".to_string() }),
+    }
+}
+
+mod peer_id {
+    use libp2p::PeerId;
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(peer_id: &PeerId, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&peer_id.to_base58())
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<PeerId, D::Error> {
+        let peer_id = String::deserialize(deserializer)?;
+        peer_id.parse().map_err(serde::de::Error::custom)
+    }
+}
@@ -0,0 +1,180 @@
+//! DNS-over-UDP frontend so `dig` and ordinary resolvers can query the DHT.
+//!
+//! Each query's name is looked up through the node exactly like the
+//! `get` command, and the typed records found there are encoded as the answer,
+//! SRV and MX records in priority order. CNAMEs are included in the answer and
+//! chased, as a recursive resolver would. A name with no record of its own is
+//! answered from its nearest wildcard, such as `*.example.com`. With a trust
+//! anchor configured, records not signed through its delegations fail with
+//! SERVFAIL, as a validating resolver answers for bogus DNSSEC data.
+
+use crate::delegation;
+use crate::dns_record::{self, DnsRecord, MAX_CNAME_DEPTH};
+use crate::DhtNode;
+use hickory_proto::op::{Message, MessageType, OpCode, ResponseCode};
+use hickory_proto::rr::{rdata, Name, RData, Record, RecordType};
+use std::net::SocketAddr;
+use std::sync::Arc;
+use tokio::net::UdpSocket;
+
+/// Answer DNS queries on `addr` until the process exits.
+pub async fn serve(addr: SocketAddr, node: DhtNode) -> std::io::Result<()> {
+    let socket = Arc::new(UdpSocket::bind(addr).await?);
+    tracing::info!("DNS frontend listening on udp://{}", socket.local_addr()?);
+
+    let mut buffer = [0u8; 512];
+    loop {
+        let (len, peer) = socket.recv_from(&mut buffer).await?;
+        let request = match Message::from_vec(&buffer[..len]) {
+            Ok(request) => request,
+            Err(e) => {
+                tracing::debug!("ignoring malformed DNS packet from {}: {}", peer, e);
+                continue;
+            }
+        };
+
+        // Lookups can wait on the network, so answer each query on its own task
+        let socket = socket.clone();
+        let node = node.clone();
+        tokio::spawn(async move {
+            let response = answer(&request, &node).await;
+            match response.to_vec() {
+                Ok(bytes) => {
+                    if let Err(e) = socket.send_to(&bytes, peer).await {
+                        tracing::debug!("could not send DNS response to {}: {}", peer, e);
+                    }
+                }
+                Err(e) => tracing::warn!("could not encode DNS response: {}", e),
+            }
+        });
+    }
+}
+
+/// Build the response to a single DNS request, looking its name up through `node`.
+pub async fn answer(request: &Message, node: &DhtNode) -> Message {
+    let mut response = Message::new();
+    response
+        .set_id(request.id())
+        .set_message_type(MessageType::Response)
+        .set_op_code(request.op_code())
+        .set_recursion_desired(request.recursion_desired())
+        .set_recursion_available(true);
+    response.add_queries(request.queries().to_vec());
+
+    let query = match request.queries() {
+        [query] if request.op_code() == OpCode::Query => query,
+        _ => {
+            response.set_response_code(ResponseCode::NotImp);
+            return response;
+        }
+    };
+
+    // The name asked for, then one lookup for each CNAME followed
+    let mut name = query.name().clone();
+    for _ in 0..=MAX_CNAME_DEPTH {
+        let (records, ttl) = match lookup(&name, node).await {
+            Lookup::Found(records, ttl) => (records, ttl),
+            Lookup::NotFound if response.answers().is_empty() => {
+                response.set_response_code(ResponseCode::NXDomain);
+                return response;
+            }
+            // The alias exists but its target does not resolve: return what we have
+            Lookup::NotFound => return response,
+            Lookup::Failed => {
+                response.set_response_code(ResponseCode::ServFail);
+                return response;
+            }
+        };
+
+        if let [DnsRecord::Cname(target)] = &records[..] {
+            let Ok(target_name) = Name::from_ascii(target) else {
+                response.set_response_code(ResponseCode::ServFail);
+                return response;
+            };
+            response.add_answer(Record::from_rdata(name, ttl, RData::CNAME(rdata::CNAME(target_name.clone()))));
+            if query.query_type() == RecordType::CNAME {
+                return response;
+            }
+            name = target_name;
+            continue;
+        }
+
+        // The name may hold a different type, leaving NOERROR with no answers
+        for record in records {
+            match rdata_for(record, query.query_type()) {
+                Some(Ok(rdata)) => {
+                    response.add_answer(Record::from_rdata(name.clone(), ttl, rdata));
+                }
+                Some(Err(())) => {
+                    response.set_response_code(ResponseCode::ServFail);
+                    return response;
+                }
+                None => {}
+            }
+        }
+        return response;
+    }
+
+    // Chain too long, most likely a loop
+    response.set_response_code(ResponseCode::ServFail);
+    response
+}
+
+// The answer data for `record` if it is of `query_type`, or `Err` if a target in it is not a valid name
+fn rdata_for(record: DnsRecord, query_type: RecordType) -> Option<Result<RData, ()>> {
+    let rdata = match (record, query_type) {
+        (DnsRecord::A(addr), RecordType::A) => Ok(RData::A(rdata::A(addr))),
+        (DnsRecord::Aaaa(addr), RecordType::AAAA) => Ok(RData::AAAA(rdata::AAAA(addr))),
+        (DnsRecord::Txt(text), RecordType::TXT) => Ok(RData::TXT(rdata::TXT::new(vec![text]))),
+        (DnsRecord::Srv { priority, weight, port, target }, RecordType::SRV) => Name::from_ascii(&target)
+            .map(|target| RData::SRV(rdata::SRV::new(priority, weight, port, target)))
+            .map_err(|_| ()),
+        (DnsRecord::Mx { preference, exchange }, RecordType::MX) => Name::from_ascii(&exchange)
+            .map(|exchange| RData::MX(rdata::MX::new(preference, exchange)))
+            .map_err(|_| ()),
+        _ => return None,
+    };
+    Some(rdata)
+}
+
+enum Lookup {
+    Found(Vec<DnsRecord>, u32),
+    NotFound,
+    Failed,
+}
+
+// Resolve one name through the node into a typed record and its remaining TTL,
+// falling back to the nearest wildcard only if the name itself holds nothing
+async fn lookup(name: &Name, node: &DhtNode) -> Lookup {
+    let name = name.to_ascii();
+    for candidate in std::iter::once(name.clone()).chain(dns_record::wildcards(&name)) {
+        if let Some(lookup) = lookup_exact(&candidate, node).await {
+            return lookup;
+        }
+    }
+    Lookup::NotFound
+}
+
+// Like `lookup` without wildcards, or `None` if nothing is stored under `name`
+async fn lookup_exact(name: &str, node: &DhtNode) -> Option<Lookup> {
+    // The node normalizes case and the trailing dot too, but the chain of trust needs the name itself
+    let Ok(name) = dns_record::normalize_key(name) else {
+        return Some(Lookup::Failed);
+    };
+    let entry = match node.get_entry(&name, node.default_quorum()).await {
+        Ok(Some(entry)) => entry,
+        Ok(None) => return None,
+        Err(_) => return Some(Lookup::Failed),
+    };
+    if let Some(anchor) = node.trust_anchor()
+        && delegation::verify(node, anchor, &name, entry.signer).await.is_err()
+    {
+        return Some(Lookup::Failed);
+    }
+    let lookup = match DnsRecord::decode_all(&entry.value) {
+        Some(records) => Lookup::Found(records, dns_record::ttl_secs(entry.expires)),
+        // Raw values have no DNS meaning, but still keep a wildcard from answering
+        None => Lookup::NotFound,
+    };
+    Some(lookup)
+}
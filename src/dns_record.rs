@@ -0,0 +1,318 @@
+//! Typed DNS records stored as record values.
+
+use crate::error::DhtError;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::time::Instant;
+
+/// Most CNAMEs followed from the name asked for; a chain of exactly this
+/// many aliases resolves, and a longer one is an error.
+pub const MAX_CNAME_DEPTH: usize = 8;
+
+/// Most wildcard names tried for a name that has no record of its own.
+pub const MAX_WILDCARD_LOOKUPS: usize = 8;
+
+/// The supported record types, by their DNS names.
+pub const RECORD_TYPES: [&str; 6] = ["A", "AAAA", "TXT", "CNAME", "SRV", "MX"];
+
+/// TTL handed out for records that carry no expiry.
+pub const DEFAULT_TTL_SECS: u32 = 300;
+
+// Name limits from RFC 1035, counted without the trailing dot
+const MAX_NAME_LEN: usize = 253;
+const MAX_LABEL_LEN: usize = 63;
+
+/// Canonical form of a DNS-style key: ASCII lowercased, without a trailing dot.
+///
+/// Rejects empty names, names with empty labels (`a..b`, a leading dot),
+/// labels over 63 bytes and names over 253 bytes.
+pub fn normalize_key(name: &str) -> Result<String, String> {
+    let normalized = name.strip_suffix('.').unwrap_or(name).to_ascii_lowercase();
+    if normalized.is_empty() {
+        return Err("name must not be empty".to_string());
+    }
+    if normalized.len() > MAX_NAME_LEN {
+        return Err(format!("'{}' is {} bytes long, the maximum is {}", name, normalized.len(), MAX_NAME_LEN));
+    }
+    for label in normalized.split('.') {
+        if label.is_empty() {
+            return Err(format!("'{}' contains an empty label", name));
+        }
+        if label.len() > MAX_LABEL_LEN {
+            return Err(format!("label '{}' is {} bytes long, the maximum is {}", label, label.len(), MAX_LABEL_LEN));
+        }
+    }
+    Ok(normalized)
+}
+
+/// The key records of `record_type` are stored under beside the ones of
+/// `name` itself, e.g. `www.example.com|aaaa`, so a name can hold records of
+/// several types.
+pub fn typed_key(name: &str, record_type: &str) -> String {
+    format!("{}|{}", name, record_type.to_ascii_lowercase())
+}
+
+/// Check that a CNAME may be followed to `target` from the names in `chain`,
+/// which `hops` CNAMEs have led through so far: it must not lead back to one
+/// of them, nor be more than [`MAX_CNAME_DEPTH`] hops from the name asked for.
+pub fn follow_cname(chain: &[String], hops: usize, target: &str) -> Result<(), DhtError> {
+    if chain.iter().any(|name| name == target) {
+        return Err(DhtError::CnameChain(format!("CNAME loop detected: {} -> {}", chain.join(" -> "), target)));
+    }
+    if hops >= MAX_CNAME_DEPTH {
+        let start = chain.first().map_or(target, String::as_str);
+        return Err(DhtError::CnameChain(format!("CNAME chain starting at {} exceeds {} hops", start, MAX_CNAME_DEPTH)));
+    }
+    Ok(())
+}
+
+/// The TTL to hand out for a record valid until `expires`: the seconds it
+/// has left, or [`DEFAULT_TTL_SECS`] if it never expires.
+pub fn ttl_secs(expires: Option<Instant>) -> u32 {
+    expires
+        .map(|expires| expires.saturating_duration_since(Instant::now()).as_secs().min(u32::MAX as u64) as u32)
+        .unwrap_or(DEFAULT_TTL_SECS)
+}
+
+/// The wildcards that stand in for `name` when it has no record of its own,
+/// nearest first: `*.b.example.com`, `*.example.com` and `*.com` for
+/// `a.b.example.com`, at most [`MAX_WILDCARD_LOOKUPS`] of them.
+///
+/// An exact match always wins, so these are only looked up once `name` itself
+/// was not found; the first one found is the answer.
+pub fn wildcards(name: &str) -> Vec<String> {
+    let name = name.strip_suffix('.').unwrap_or(name);
+    let mut parent = name;
+    let mut wildcards = Vec::new();
+    while let Some((_, rest)) = parent.split_once('.') {
+        parent = rest;
+        let wildcard = format!("*.{}", parent);
+        // `*.example.com` itself falls back to `*.com`, not to itself
+        if wildcard != name {
+            wildcards.push(wildcard);
+        }
+        if wildcards.len() == MAX_WILDCARD_LOOKUPS {
+            break;
+        }
+    }
+    wildcards
+}
+
+/// A DNS resource record, serialized as JSON into the record value.
+///
+/// SRV and MX names usually point at several hosts, so a value may also hold
+/// a list of records, see [`DnsRecord::encode_all`].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", content = "data")]
+pub enum DnsRecord {
+    A(Ipv4Addr),
+    #[serde(rename = "AAAA")]
+    Aaaa(Ipv6Addr),
+    #[serde(rename = "TXT")]
+    Txt(String),
+    #[serde(rename = "CNAME")]
+    Cname(String),
+    /// A service location: clients try lower priorities first, and share load
+    /// between equal priorities in proportion to their weights.
+    #[serde(rename = "SRV")]
+    Srv { priority: u16, weight: u16, port: u16, target: String },
+    /// A mail exchange: lower preferences are tried first.
+    #[serde(rename = "MX")]
+    Mx { preference: u16, exchange: String },
+}
+
+impl DnsRecord {
+    /// Whether `record_type` names one of the supported record types (case-insensitive).
+    pub fn is_type(record_type: &str) -> bool {
+        RECORD_TYPES.iter().any(|known| known.eq_ignore_ascii_case(record_type))
+    }
+
+    /// The DNS name of the record's type, as in [`RECORD_TYPES`].
+    pub fn record_type(&self) -> &'static str {
+        match self {
+            DnsRecord::A(_) => "A",
+            DnsRecord::Aaaa(_) => "AAAA",
+            DnsRecord::Txt(_) => "TXT",
+            DnsRecord::Cname(_) => "CNAME",
+            DnsRecord::Srv { .. } => "SRV",
+            DnsRecord::Mx { .. } => "MX",
+        }
+    }
+
+    /// How many whitespace-separated words the data of `record_type` takes on
+    /// the command line: `priority weight port target` for SRV,
+    /// `preference exchange` for MX and a single word otherwise.
+    pub fn field_count(record_type: &str) -> usize {
+        match record_type.to_ascii_uppercase().as_str() {
+            "SRV" => 4,
+            "MX" => 2,
+            _ => 1,
+        }
+    }
+
+    /// Build a record from a type name and its textual data, e.g. `("A", "192.0.2.1")`
+    /// or `("MX", "10 mail.example.com")`.
+    pub fn parse(record_type: &str, data: &str) -> Result<Self, String> {
+        match record_type.to_ascii_uppercase().as_str() {
+            "A" => data.parse().map(DnsRecord::A)
+                .map_err(|_| format!("invalid IPv4 address '{}'", data)),
+            "AAAA" => data.parse().map(DnsRecord::Aaaa)
+                .map_err(|_| format!("invalid IPv6 address '{}'", data)),
+            "TXT" => Ok(DnsRecord::Txt(data.to_string())),
+            "CNAME" if data.is_empty() => Err("CNAME target must not be empty".to_string()),
+            "CNAME" => Ok(DnsRecord::Cname(data.to_string())),
+            "SRV" => match data.split_whitespace().collect::<Vec<_>>()[..] {
+                [priority, weight, port, target] => Ok(DnsRecord::Srv {
+                    priority: parse_number("priority", priority)?,
+                    weight: parse_number("weight", weight)?,
+                    port: parse_number("port", port)?,
+                    target: target.to_string(),
+                }),
+                _ => Err(format!("invalid SRV data '{}', expected <priority> <weight> <port> <target>", data)),
+            },
+            "MX" => match data.split_whitespace().collect::<Vec<_>>()[..] {
+                [preference, exchange] => Ok(DnsRecord::Mx {
+                    preference: parse_number("preference", preference)?,
+                    exchange: exchange.to_string(),
+                }),
+                _ => Err(format!("invalid MX data '{}', expected <preference> <exchange>", data)),
+            },
+            other => Err(format!("unsupported record type '{}', expected A, AAAA, TXT, CNAME, SRV or MX", other)),
+        }
+    }
+
+    /// Build the records of one name from its data: one SRV or MX record per
+    /// non-empty line, or a single record of any other type.
+    pub fn parse_all(record_type: &str, data: &str) -> Result<Vec<Self>, String> {
+        if Self::field_count(record_type) == 1 {
+            return Ok(vec![Self::parse(record_type, data)?]);
+        }
+        let records = data.lines()
+            .filter(|line| !line.trim().is_empty())
+            .map(|line| Self::parse(record_type, line))
+            .collect::<Result<Vec<_>, _>>()?;
+        if records.is_empty() {
+            return Err(format!("no {} records given", record_type.to_ascii_uppercase()));
+        }
+        Ok(records)
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        serde_json::to_vec(self).expect("DNS record serialization does not fail")
+    }
+
+    /// Encode the records of one name: a single record as by [`encode`](Self::encode),
+    /// several as a list in resolution order.
+    pub fn encode_all(records: &[DnsRecord]) -> Vec<u8> {
+        match records {
+            [record] => record.encode(),
+            records => {
+                let mut records = records.to_vec();
+                sort_for_resolution(&mut records);
+                serde_json::to_vec(&records).expect("DNS record serialization does not fail")
+            }
+        }
+    }
+
+    /// Decode a record value, returning `None` if it is not a single typed DNS record.
+    pub fn decode(value: &[u8]) -> Option<Self> {
+        serde_json::from_slice(value).ok()
+    }
+
+    /// Decode a value holding one record or a list of them, in resolution order.
+    /// Returns `None` if it is not made of typed DNS records.
+    pub fn decode_all(value: &[u8]) -> Option<Vec<Self>> {
+        if let Some(record) = Self::decode(value) {
+            return Some(vec![record]);
+        }
+        let mut records: Vec<Self> = serde_json::from_slice(value).ok()?;
+        sort_for_resolution(&mut records);
+        Some(records)
+    }
+
+    /// The record's data without its type, as `resolve` prints it.
+    pub fn data(&self) -> String {
+        match self {
+            DnsRecord::A(addr) => addr.to_string(),
+            DnsRecord::Aaaa(addr) => addr.to_string(),
+            DnsRecord::Txt(text) => format!("\"{}\"", text),
+            DnsRecord::Cname(target) => target.clone(),
+            DnsRecord::Srv { priority, weight, port, target } => format!("{} {} {} {}", priority, weight, port, target),
+            DnsRecord::Mx { preference, exchange } => format!("{} {}", preference, exchange),
+        }
+    }
+}
+
+// Put SRV records in priority order, heaviest first within a priority, and MX
+// records in preference order, as DNS clients try them. Others keep their order
+fn sort_for_resolution(records: &mut [DnsRecord]) {
+    records.sort_by_key(|record| match record {
+        DnsRecord::Srv { priority, weight, .. } => (*priority, u16::MAX - weight),
+        DnsRecord::Mx { preference, .. } => (*preference, 0),
+        _ => (0, 0),
+    });
+}
+
+fn parse_number(field: &str, value: &str) -> Result<u16, String> {
+    value.parse().map_err(|_| format!("invalid {} '{}', expected a number from 0 to 65535", field, value))
+}
+
+impl fmt::Display for DnsRecord {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DnsRecord::A(addr) => write!(f, "A {}", addr),
+            DnsRecord::Aaaa(addr) => write!(f, "AAAA {}", addr),
+            DnsRecord::Txt(text) => write!(f, "TXT \"{}\"", text),
+            DnsRecord::Cname(target) => write!(f, "CNAME {}", target),
+            DnsRecord::Srv { .. } => write!(f, "SRV {}", self.data()),
+            DnsRecord::Mx { .. } => write!(f, "MX {}", self.data()),
+        }
+    }
+}
+
+/// What a stub resolver makes of a name's records when asked for no type in
+/// particular, as [`natural`] finds it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Natural {
+    /// The name's addresses: its A records, or if it has none its AAAA records.
+    Addresses(Vec<IpAddr>),
+    /// The name has no addresses but is an alias of this name.
+    Alias(String),
+    /// Neither, such as a name holding only TXT or MX records.
+    Other,
+}
+
+/// The natural answer for a name holding `records`: A records first, then
+/// AAAA, then a CNAME to follow.
+pub fn natural(records: &[DnsRecord]) -> Natural {
+    let v4: Vec<IpAddr> = records.iter().filter_map(|record| match record {
+        DnsRecord::A(addr) => Some(IpAddr::V4(*addr)),
+        _ => None,
+    }).collect();
+    if !v4.is_empty() {
+        return Natural::Addresses(v4);
+    }
+    let v6: Vec<IpAddr> = records.iter().filter_map(|record| match record {
+        DnsRecord::Aaaa(addr) => Some(IpAddr::V6(*addr)),
+        _ => None,
+    }).collect();
+    if !v6.is_empty() {
+        return Natural::Addresses(v6);
+    }
+    match records.iter().find_map(|record| match record {
+        DnsRecord::Cname(target) => Some(target.clone()),
+        _ => None,
+    }) {
+        Some(target) => Natural::Alias(target),
+        None => Natural::Other,
+    }
+}
+
+/// Render a record value for display: typed records pretty-printed, anything else as text.
+pub fn format_value(value: &[u8]) -> String {
+    match DnsRecord::decode_all(value) {
+        Some(records) => records.iter().map(DnsRecord::to_string).collect::<Vec<_>>().join(", "),
+        None => String::from_utf8_lossy(value).into_owned(),
+    }
+}
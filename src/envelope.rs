@@ -0,0 +1,184 @@
+//! Signed envelopes wrapping record values.
+//!
+//! On the wire a record's value is the serialized [`SignedEnvelope`], holding
+//! the real value, its version, the signer's public key and a signature over
+//! the record key, version, ownership claim and value. `get` verifies and
+//! unwraps it so callers only ever see the value.
+//!
+//! A record that claims ownership makes its signer the only writer of the key
+//! on nodes that enforce it; the first claim a node sees wins. Nodes that do
+//! not enforce ownership treat the claim as advisory.
+//!
+//! Envelopes also carry signed [`Metadata`]: when the record was first written,
+//! when this version was written and the TTL its writer gave it.
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+use libp2p::{identity, PeerId};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::fmt;
+
+// Bumped if the signed message layout ever changes. Version 1 envelopes, from
+// before records were versioned, are still opened as version 0 of their record,
+// version 2 ones as records that claim no owner, and version 3 ones as records
+// without metadata
+const ENVELOPE_VERSION: u8 = 4;
+const UNTIMED_ENVELOPE: u8 = 3;
+const UNOWNED_ENVELOPE: u8 = 2;
+const UNVERSIONED_ENVELOPE: u8 = 1;
+
+#[derive(Serialize, Deserialize)]
+struct SignedEnvelope {
+    v: u8,
+    // Version of the record, one more than the newest its writer had seen
+    #[serde(default)]
+    seq: u64,
+    // Whether the signer claims the key as its own
+    #[serde(default)]
+    owned: bool,
+    #[serde(default, flatten)]
+    metadata: Option<Metadata>,
+    #[serde(with = "base64_bytes")]
+    value: Vec<u8>,
+    #[serde(with = "base64_bytes")]
+    signer_pubkey: Vec<u8>,
+    #[serde(with = "base64_bytes")]
+    signature: Vec<u8>,
+}
+
+/// When a record was written and for how long, as its writer signed it.
+/// Times are milliseconds since the Unix epoch.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Metadata {
+    /// When the first version of the record was written.
+    pub created: u64,
+    /// When this version was written.
+    pub updated: u64,
+    /// The TTL the writer gave the record.
+    pub ttl_secs: u64,
+}
+
+/// A value recovered from a record.
+pub enum Opened {
+    /// The value carried a valid signature from `signer`, who claims the key
+    /// as its own if `owned` is set. Envelopes sealed before metadata was
+    /// added have none.
+    Signed { value: Vec<u8>, signer: PeerId, seq: u64, owned: bool, metadata: Option<Metadata> },
+    /// The value was stored without an envelope, so it has no version.
+    Unsigned(Vec<u8>),
+}
+
+/// Why an envelope was rejected.
+#[derive(Debug)]
+pub enum EnvelopeError {
+    InvalidPublicKey,
+    BadSignature,
+}
+
+impl fmt::Display for EnvelopeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EnvelopeError::InvalidPublicKey => write!(f, "envelope has an invalid signer public key"),
+            EnvelopeError::BadSignature => write!(f, "envelope signature does not match the key and value"),
+        }
+    }
+}
+
+impl std::error::Error for EnvelopeError {}
+
+/// Sign version `seq` of `value` for `key` with `keypair`, claiming the key for
+/// the signer if `owned`, and return the serialized envelope to store.
+pub fn seal(keypair: &identity::Keypair, key: &[u8], value: &[u8], seq: u64, owned: bool, metadata: Metadata) -> Vec<u8> {
+    let signature = keypair.sign(&signed_message(key, Some(seq), Some(owned), Some(&metadata), value))
+        .expect("ed25519 signing does not fail");
+    let envelope = SignedEnvelope {
+        v: ENVELOPE_VERSION,
+        seq,
+        owned,
+        metadata: Some(metadata),
+        value: value.to_vec(),
+        signer_pubkey: keypair.public().encode_protobuf(),
+        signature,
+    };
+    serde_json::to_vec(&envelope).expect("envelope serialization does not fail")
+}
+
+/// Size of the envelope `seal` produces for a value of `value_len` bytes signed by `keypair`.
+pub fn sealed_len(keypair: &identity::Keypair, value_len: usize) -> usize {
+    // Everything but the value has a fixed size for a given key type, taking the longest
+    // version and times; the value grows by base64's 4/3
+    let longest = Metadata { created: u64::MAX, updated: u64::MAX, ttl_secs: u64::MAX };
+    seal(keypair, &[], &[], u64::MAX, false, longest).len() + value_len.div_ceil(3) * 4
+}
+
+/// Recover the value stored for `key`, verifying its signature if it is in an envelope.
+pub fn open(key: &[u8], raw: &[u8]) -> Result<Opened, EnvelopeError> {
+    let (mut envelope, seq, owned) = match serde_json::from_slice::<SignedEnvelope>(raw) {
+        Ok(envelope) if envelope.v == ENVELOPE_VERSION && envelope.metadata.is_some() => {
+            let (seq, owned) = (envelope.seq, envelope.owned);
+            (envelope, Some(seq), Some(owned))
+        }
+        Ok(envelope) if envelope.v == UNTIMED_ENVELOPE => {
+            let (seq, owned) = (envelope.seq, envelope.owned);
+            (envelope, Some(seq), Some(owned))
+        }
+        Ok(envelope) if envelope.v == UNOWNED_ENVELOPE => {
+            let seq = envelope.seq;
+            (envelope, Some(seq), None)
+        }
+        Ok(envelope) if envelope.v == UNVERSIONED_ENVELOPE => (envelope, None, None),
+        _ => return Ok(Opened::Unsigned(raw.to_vec())),
+    };
+    // Only the current layout signs the metadata, so any found in an older envelope is not trusted
+    if envelope.v != ENVELOPE_VERSION {
+        envelope.metadata = None;
+    }
+
+    let public_key = identity::PublicKey::try_decode_protobuf(&envelope.signer_pubkey)
+        .map_err(|_| EnvelopeError::InvalidPublicKey)?;
+    let message = signed_message(key, seq, owned, envelope.metadata.as_ref(), &envelope.value);
+    if !public_key.verify(&message, &envelope.signature) {
+        return Err(EnvelopeError::BadSignature);
+    }
+
+    Ok(Opened::Signed {
+        value: envelope.value,
+        signer: public_key.to_peer_id(),
+        seq: seq.unwrap_or(0),
+        owned: owned.unwrap_or(false),
+        metadata: envelope.metadata,
+    })
+}
+
+// Length-prefix the key so a signature cannot be replayed by shifting bytes between key and value.
+// The version, ownership byte and metadata sit between them, fixed-width, and are absent from older envelopes
+fn signed_message(key: &[u8], seq: Option<u64>, owned: Option<bool>, metadata: Option<&Metadata>, value: &[u8]) -> Vec<u8> {
+    let mut message = Vec::with_capacity(4 + key.len() + 33 + value.len());
+    message.extend_from_slice(&(key.len() as u32).to_be_bytes());
+    message.extend_from_slice(key);
+    if let Some(seq) = seq {
+        message.extend_from_slice(&seq.to_be_bytes());
+    }
+    if let Some(owned) = owned {
+        message.push(u8::from(owned));
+    }
+    if let Some(metadata) = metadata {
+        message.extend_from_slice(&metadata.created.to_be_bytes());
+        message.extend_from_slice(&metadata.updated.to_be_bytes());
+        message.extend_from_slice(&metadata.ttl_secs.to_be_bytes());
+    }
+    message.extend_from_slice(value);
+    message
+}
+
+mod base64_bytes {
+    use super::*;
+
+    pub fn serialize<S: Serializer>(bytes: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&STANDARD.encode(bytes))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<u8>, D::Error> {
+        let encoded = String::deserialize(deserializer)?;
+        STANDARD.decode(encoded).map_err(serde::de::Error::custom)
+    }
+}
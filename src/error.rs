@@ -0,0 +1,90 @@
+//! Errors returned by the node API.
+
+use libp2p::{kad, swarm::DialError, PeerId};
+use std::io;
+use std::num::NonZeroUsize;
+use thiserror::Error;
+
+/// Why a [`DhtNode`](crate::DhtNode) request could not be carried out.
+#[derive(Debug, Error)]
+pub enum DhtError {
+    /// The node could not be built from its configuration.
+    #[error("could not start node: {0}")]
+    Setup(String),
+    /// A setting, in a config file, on the command line or given to `config set`, is invalid.
+    #[error("{0}")]
+    Config(String),
+    /// A command was not understood or its arguments are malformed.
+    #[error("invalid command: {0}")]
+    InvalidCommand(String),
+    /// The key is not a valid DNS-style name.
+    #[error("invalid key: {0}")]
+    InvalidKey(String),
+    /// The event loop is no longer running, so the request was never handled.
+    #[error("node is not running")]
+    NodeStopped,
+    /// The DHT query did not finish within the query timeout.
+    #[error("DHT query timed out")]
+    Timeout,
+    /// Fewer than `quorum` peers took part: for a put, `succeeded` peers accepted
+    /// a copy (the record is still stored locally); for a get, `succeeded`
+    /// peers returned one.
+    #[error("quorum failed; needed {quorum} peers, only {succeeded} succeeded")]
+    QuorumFailed { quorum: NonZeroUsize, succeeded: usize },
+    /// The DHT holds version `current` of the key, newer than the version
+    /// `known` this node last stored or read, so a put would silently undo
+    /// someone else's write. Read the key again, or force the put.
+    #[error("version conflict; the DHT holds version {current} but this node last saw version {known}, get the key again or force the put")]
+    Conflict { known: u64, current: u64 },
+    /// The key is owned by another peer, and only its owner may write it.
+    #[error("not authorized; the key is owned by {owner}, only its owner may write it")]
+    Unauthorized { owner: PeerId },
+    /// The node runs with `readonly` set, so it makes no writes.
+    #[error("node is read-only; puts, deletes, provides and pins are refused, restart it without --readonly to write")]
+    ReadOnly,
+    /// The value is longer than the configured `max_record_bytes`.
+    #[error("value too large ({size} > {max} bytes), raise --max-record-bytes to store it")]
+    ValueTooLarge { size: usize, max: usize },
+    /// The local store already holds its configured `max_records`, so the
+    /// record was neither stored nor published.
+    #[error("local store is full ({max_records} records), delete some or raise --max-records (max_records in the config file)")]
+    StoreFull { max_records: usize },
+    /// The key is content-addressed and the value does not hash to it: a put
+    /// of different data, or a get that only found tampered copies.
+    #[error("value does not match content key {key}, its SHA-256 digest differs")]
+    ContentMismatch { key: String },
+    /// The configured record validator refused the record, for `reason`.
+    #[error("record rejected by validator: {reason}")]
+    Rejected { reason: String },
+    /// The local record store refused the record.
+    #[error("local store rejected record: {}", store_reason(.0))]
+    Store(#[from] kad::store::Error),
+    /// Bootstrapping needs at least one known peer address.
+    #[error("no known peers, add at least one address with `bootstrap <multiaddr> <peer_id>` first")]
+    NoKnownPeers,
+    /// The peer at the given address could not be dialed.
+    #[error("dial failed: {0}")]
+    Dial(#[from] DialError),
+    /// A `ping` got no answer: the peer could not be reached, stopped
+    /// responding or does not support the protocol.
+    #[error("ping failed: {0}")]
+    PingFailed(String),
+    /// Following a name's CNAMEs ran into a loop or too long a chain.
+    #[error("{0}")]
+    CnameChain(String),
+    /// The record found for `name` is not in a valid chain of trust from the
+    /// configured trust anchor, for `reason`.
+    #[error("record for {name} is not in the chain of trust: {reason}")]
+    Untrusted { name: String, reason: String },
+    /// A file named by a command could not be read or written.
+    #[error("{0}")]
+    Io(#[from] io::Error),
+}
+
+// Kademlia's own message for a full provider table does not say whose limit was hit
+fn store_reason(e: &kad::store::Error) -> String {
+    match e {
+        kad::store::Error::MaxProvidedKeys => "this node provides too many keys already".to_string(),
+        e => e.to_string(),
+    }
+}
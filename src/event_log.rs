@@ -0,0 +1,178 @@
+//! An append-only log of operations, query results and connection events, one
+//! JSON object per line, for audits and post-mortems. Separate from the
+//! `tracing` output, which is for people reading along.
+//!
+//! Lines are handed to a background task that buffers them and writes them
+//! out every [`FLUSH_INTERVAL`], so recording an event never waits on the
+//! disk. Once the file would grow past its size limit it is rotated, keeping
+//! a configured number of older files next to it as `<path>.1`, `<path>.2`, ...
+
+use prometheus::IntCounter;
+use serde_json::{Map, Value};
+use std::ffi::OsString;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::fs::{self, File};
+use tokio::io::AsyncWriteExt;
+use tokio::sync::{mpsc, oneshot};
+use tracing::warn;
+
+/// How often buffered lines are written out.
+pub const FLUSH_INTERVAL: Duration = Duration::from_secs(1);
+// Lines waiting for the writer; beyond this new ones are dropped rather than wait
+const CAPACITY: usize = 4096;
+// Buffered bytes that are written out straight away rather than at the next flush
+const MAX_BUFFER_BYTES: usize = 64 * 1024;
+
+enum Message {
+    Line(String),
+    Flush(oneshot::Sender<()>),
+}
+
+/// A handle to the event log; a default one records nothing.
+#[derive(Clone, Default)]
+pub(crate) struct EventLog {
+    lines: Option<mpsc::Sender<Message>>,
+    dropped: Option<IntCounter>,
+}
+
+impl EventLog {
+    /// Append to the file at `path`, creating it and its directory if needed,
+    /// and start the task writing to it. Must be called from within a Tokio runtime.
+    ///
+    /// `max_bytes` of 0 never rotates; otherwise up to `keep` rotated files are kept.
+    pub(crate) fn open(path: &Path, max_bytes: u64, keep: usize, dropped: IntCounter) -> io::Result<Self> {
+        if let Some(parent) = path.parent().filter(|parent| !parent.as_os_str().is_empty()) {
+            std::fs::create_dir_all(parent)?;
+        }
+        let file = std::fs::OpenOptions::new().create(true).append(true).open(path)?;
+        let written = file.metadata()?.len();
+        let writer = Writer { file: File::from_std(file), path: path.to_path_buf(), written, max_bytes, keep, buffer: Vec::new() };
+        let (lines, receiver) = mpsc::channel(CAPACITY);
+        tokio::spawn(writer.run(receiver));
+        Ok(EventLog { lines: Some(lines), dropped: Some(dropped) })
+    }
+
+    /// Record `event` with `fields`, which should be a JSON object, along with
+    /// the time in unix milliseconds. Dropped, and counted, if the writer has
+    /// fallen too far behind.
+    pub(crate) fn record(&self, event: &str, fields: Value) {
+        let Some(lines) = &self.lines else {
+            return;
+        };
+        let mut line = Map::new();
+        line.insert("ts".to_string(), unix_millis().into());
+        line.insert("event".to_string(), event.into());
+        if let Value::Object(fields) = fields {
+            line.extend(fields);
+        }
+        if lines.try_send(Message::Line(Value::Object(line).to_string())).is_err()
+            && let Some(dropped) = &self.dropped
+        {
+            dropped.inc();
+        }
+    }
+
+    /// Wait until every line recorded so far is written out.
+    pub(crate) async fn flush(&self) {
+        let Some(lines) = &self.lines else {
+            return;
+        };
+        let (done, written) = oneshot::channel();
+        if lines.send(Message::Flush(done)).await.is_ok() {
+            let _ = written.await;
+        }
+    }
+}
+
+// The background task owning the file
+struct Writer {
+    file: File,
+    path: PathBuf,
+    // Size of the current file, to know when it is due to be rotated
+    written: u64,
+    max_bytes: u64,
+    keep: usize,
+    buffer: Vec<u8>,
+}
+
+impl Writer {
+    async fn run(mut self, mut messages: mpsc::Receiver<Message>) {
+        let mut flush_timer = tokio::time::interval(FLUSH_INTERVAL);
+        loop {
+            tokio::select! {
+                message = messages.recv() => match message {
+                    Some(Message::Line(line)) => {
+                        self.buffer.extend_from_slice(line.as_bytes());
+                        self.buffer.push(b'\n');
+                        if self.buffer.len() >= MAX_BUFFER_BYTES {
+                            self.flush().await;
+                        }
+                    }
+                    Some(Message::Flush(done)) => {
+                        self.flush().await;
+                        let _ = done.send(());
+                    }
+                    // Every handle is gone, so nothing more will be recorded
+                    None => break,
+                },
+                _ = flush_timer.tick() => self.flush().await,
+            }
+        }
+        self.flush().await;
+    }
+
+    // Write out the buffer, rotating first if it would take the file past its limit.
+    // Lines that cannot be written are reported and dropped
+    async fn flush(&mut self) {
+        if self.buffer.is_empty() {
+            return;
+        }
+        if self.max_bytes > 0 && self.written > 0 && self.written + self.buffer.len() as u64 > self.max_bytes
+            && let Err(e) = self.rotate().await
+        {
+            warn!("could not rotate event log {}: {}", self.path.display(), e);
+        }
+        let result = async {
+            self.file.write_all(&self.buffer).await?;
+            self.file.flush().await
+        }
+        .await;
+        match result {
+            Ok(()) => self.written += self.buffer.len() as u64,
+            Err(e) => warn!("could not write event log {}: {}", self.path.display(), e),
+        }
+        self.buffer.clear();
+    }
+
+    // Move each kept file up one place, the oldest dropping off the end, and start a new one
+    async fn rotate(&mut self) -> io::Result<()> {
+        self.file.flush().await?;
+        for n in (1..self.keep).rev() {
+            match fs::rename(self.rotated(n), self.rotated(n + 1)).await {
+                Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+                _ => {}
+            }
+        }
+        if self.keep > 0 {
+            fs::rename(&self.path, self.rotated(1)).await?;
+        } else {
+            fs::remove_file(&self.path).await?;
+        }
+        self.file = fs::OpenOptions::new().create(true).append(true).open(&self.path).await?;
+        self.written = 0;
+        Ok(())
+    }
+
+    // `<path>.<n>`, e.g. events.jsonl.2
+    fn rotated(&self, n: usize) -> PathBuf {
+        let mut name = OsString::from(self.path.as_os_str());
+        name.push(format!(".{}", n));
+        PathBuf::from(name)
+    }
+}
+
+fn unix_millis() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|since| since.as_millis() as u64).unwrap_or(0)
+}
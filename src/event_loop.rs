@@ -0,0 +1,2532 @@
+//! The event loop that owns the swarm and carries out requests from [`DhtNode`](crate::DhtNode) handles.
+
+use crate::cache::ResultCache;
+use crate::compression;
+use crate::config::{Config, MAX_TIMEOUT_SECS};
+use crate::display_key;
+use crate::envelope::{self, Metadata, Opened};
+use crate::error::DhtError;
+use crate::event_log::EventLog;
+use crate::invalidation::{self, Invalidation};
+use crate::keys::{self, KeyHashing};
+use crate::metrics::Metrics;
+use crate::node::{BucketInfo, ClosestPeers, Command, ConnectionSecurity, DryRun, Entry, Health, NetworkStatus, NodeAddrs, NodeStats, PeerInfo, Reachability, RecordDescription, RecordEvent, RecordSummary, Settings, read_settings};
+use crate::persist;
+use crate::seed_list;
+use crate::throttle::{self, Throttled};
+use crate::transport;
+use crate::validator::RecordValidator;
+use futures::StreamExt;
+use libp2p::{
+    autonat,
+    connection_limits::{self, ConnectionLimits},
+    core::transport::ListenerId,
+    gossipsub, identity, kad::{
+        self, store::{MemoryStore, MemoryStoreConfig, RecordStore}, GetProvidersOk, GetRecordOk, PutRecordOk, QueryId, QueryResult, Quorum, Record,
+        RecordKey,
+    },
+    multiaddr::Protocol,
+    mdns, ping, relay,
+    swarm::{self, dial_opts::DialOpts, ConnectionId, DialError, ListenError, NetworkBehaviour, Swarm, SwarmEvent},
+    Multiaddr, PeerId, StreamProtocol,
+};
+use serde_json::json;
+use std::borrow::Cow;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::num::NonZeroUsize;
+use std::path::PathBuf;
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tokio::sync::{broadcast, mpsc, oneshot};
+use tracing::{debug, error, info, warn};
+
+// How often the local store is written to disk
+const PERSIST_INTERVAL: Duration = Duration::from_secs(30);
+// How often expired records are removed from the local store
+const EXPIRY_SWEEP_INTERVAL: Duration = Duration::from_secs(60);
+// How often pinned records are checked for ones due to be republished
+const PIN_REFRESH_INTERVAL: Duration = Duration::from_secs(60);
+// Delay before the first reconnect to a lost bootstrap peer, doubled after each failure up to the cap,
+// and the consecutive failures after which we stop until mDNS finds the peer again
+const RECONNECT_MIN_DELAY: Duration = Duration::from_secs(1);
+const RECONNECT_MAX_DELAY: Duration = Duration::from_secs(5 * 60);
+const MAX_RECONNECT_FAILURES: u32 = 10;
+// How often each connection is pinged, and how long a ping may take before it fails
+pub(crate) const PING_INTERVAL: Duration = Duration::from_secs(15);
+pub(crate) const PING_TIMEOUT: Duration = Duration::from_secs(20);
+
+// Network behaviours combined into the node's swarm
+#[derive(NetworkBehaviour)]
+struct Behaviour {
+    limits: connection_limits::Behaviour,
+    kademlia: Throttled,
+    mdns: mdns::tokio::Behaviour,
+    autonat: autonat::Behaviour,
+    relay_client: relay::client::Behaviour,
+    gossipsub: gossipsub::Behaviour,
+    ping: ping::Behaviour,
+}
+
+// DHT queries that are still waiting on the network, mapped to the caller awaiting each one
+#[derive(Default)]
+struct PendingQueries {
+    gets: HashMap<QueryId, PendingGet>,
+    get_alls: HashMap<QueryId, PendingGetAll>,
+    describes: HashMap<QueryId, PendingDescribe>,
+    // Puts reading the current version of their key before writing
+    put_reads: HashMap<QueryId, PendingPut>,
+    puts: HashMap<QueryId, oneshot::Sender<Result<(), DhtError>>>,
+    provides: HashMap<QueryId, oneshot::Sender<Result<(), DhtError>>>,
+    providers: HashMap<QueryId, PendingProviders>,
+    // Periodic lookups for watched keys
+    watches: HashMap<QueryId, RecordKey>,
+    // Lookups checking that a newly connected routing table peer speaks our Kademlia protocol
+    probes: HashMap<QueryId, PeerId>,
+    find_nodes: HashMap<QueryId, PendingFindNode>,
+}
+
+// A `find-node` lookup, with the key its results are sorted by distance to
+struct PendingFindNode {
+    target: kad::KBucketKey<Vec<u8>>,
+    reply: oneshot::Sender<Result<ClosestPeers, DhtError>>,
+}
+
+// A provider lookup gathering peers across several progress events until the query ends
+struct PendingProviders {
+    reply: oneshot::Sender<Result<Vec<PeerId>, DhtError>>,
+    found: HashSet<PeerId>,
+}
+
+// A `put` waiting to learn the newest version of its key in the DHT
+struct PendingPut {
+    key: String,
+    value: Vec<u8>,
+    quorum: Quorum,
+    ttl: Option<Duration>,
+    force: bool,
+    claim: bool,
+    // Newest version this node had stored or read when the put was issued, and the newest the lookup found
+    known: u64,
+    newest: u64,
+    // When the record was first written, according to our copy and to the newest one the lookup found
+    known_created: Option<u64>,
+    newest_created: Option<u64>,
+    reply: oneshot::Sender<Result<(), DhtError>>,
+}
+
+// A `get` collecting valid copies until it has seen `needed` of them
+struct PendingGet {
+    reply: oneshot::Sender<Result<Option<Entry>, DhtError>>,
+    key: RecordKey,
+    needed: NonZeroUsize,
+    found: usize,
+    // The newest version among the copies found so far, with the record it was opened from
+    best: Option<(Entry, Record)>,
+    // Our own copy, when it was too old to serve without checking for a newer one;
+    // it is still the answer if the DHT has nothing newer
+    stale: Option<Entry>,
+    // Whether our own copy is passed over, so only other peers' copies count
+    remote: bool,
+    // The digest a content-addressed key must hash to, and how many copies failed that check
+    content: Option<[u8; 32]>,
+    tampered: usize,
+    // When the first attempt started, and when this one is abandoned and how many more are made after it
+    started: Instant,
+    deadline: Instant,
+    retries_left: u32,
+}
+
+// A `get-all` keeping every distinct value found until the lookup ends
+struct PendingGetAll {
+    reply: oneshot::Sender<Result<Vec<Entry>, DhtError>>,
+    key: RecordKey,
+    found: Vec<Entry>,
+}
+
+// A `describe` of the DHT's copies, keeping the newest valid one until the lookup ends
+struct PendingDescribe {
+    reply: oneshot::Sender<Result<Option<RecordDescription>, DhtError>>,
+    key: String,
+    newest: Option<RecordDescription>,
+}
+
+// A peer from the configured bootstrap list, redialed when its last connection closes
+struct BootstrapPeer {
+    addr: Multiaddr,
+    // Reconnects that failed since the last successful connection
+    failures: u32,
+    // When the next reconnect is due, if one is scheduled
+    next_attempt: Option<Instant>,
+    // Whether a reconnect dial of ours is under way, so failures of other dials are not counted
+    dialing: bool,
+}
+
+// A key looked up periodically, with the last value its watcher was sent
+struct WatchState {
+    updates: mpsc::Sender<Option<Vec<u8>>>,
+    last: Option<Vec<u8>>,
+    // Whether the first lookup's result has been sent yet; it is sent even if the key is missing
+    reported: bool,
+    // The lookup under way, so a slow one is not stacked with the next
+    query: Option<QueryId>,
+}
+
+// Why a record a peer pushed was not stored
+enum Refusal {
+    // Expected in normal operation, such as an older copy being replicated to us
+    Routine(String),
+    // A sign of a misbehaving or misconfigured peer, worth a warning
+    Suspect(String),
+}
+
+/// Drives the swarm and answers requests from the node's [`DhtNode`](crate::DhtNode) handles.
+pub struct EventLoop {
+    swarm: Swarm<Behaviour>,
+    commands: mpsc::Receiver<Command>,
+    pending: PendingQueries,
+    // Remote addresses of each open connection, per connected peer
+    connected: HashMap<PeerId, Vec<(Multiaddr, Instant)>>,
+    // Filled in by the transport as connections are upgraded, and moved to `secured` once the swarm reports them
+    upgrades: transport::Upgrades,
+    secured: HashMap<ConnectionId, ConnectionSecurity>,
+    bootstrap_peers: HashMap<PeerId, BootstrapPeer>,
+    // Failed dials or broken connections in a row to each address of a peer, without the
+    // address's /p2p part; an address leaves the routing table at `prune_after_failures`
+    address_failures: HashMap<(PeerId, Multiaddr), u32>,
+    prune_after_failures: u32,
+    // Log each dial's progress and failures in full
+    trace_dials: bool,
+    // Keep addresses other networks cannot dial to ourselves
+    public_only: bool,
+    // The Kademlia protocol name this node speaks, for log messages
+    protocol_name: StreamProtocol,
+    listeners: Vec<ListenerId>,
+    // Addresses our listeners are bound to, as reported by the swarm
+    listen_addrs: Vec<Multiaddr>,
+    metrics: Arc<Metrics>,
+    // When the loop was built, for the uptime in health checks
+    started: Instant,
+    // Rolling average round-trip time of each peer that has answered a ping
+    rtts: HashMap<PeerId, Duration>,
+    // Callers of `ping` waiting on the next round trip to each peer
+    pings: HashMap<PeerId, Vec<oneshot::Sender<Result<Duration, DhtError>>>>,
+    // Subscribers to store changes and resolved lookups; sending with none is fine
+    events: broadcast::Sender<RecordEvent>,
+    // Where finished queries and connection events are recorded
+    event_log: EventLog,
+    // Signs the envelope of every record we put
+    keypair: identity::Keypair,
+    // Refuse to serve or accept records without a valid signature
+    require_signed: bool,
+    // Store nothing peers push to us
+    readonly: bool,
+    // Operator policy every record must pass before it is stored, whoever wrote it
+    validator: Option<Arc<dyn RecordValidator>>,
+    // Largest value `put` accepts, after any compression and before it is sealed in its envelope
+    max_record_bytes: usize,
+    // Largest envelope a peer may push, the sealed size of a `max_record_bytes` value
+    max_sealed_bytes: usize,
+    compress: bool,
+    max_records: usize,
+    // The settings `config set` changes, among them how long each attempt of a
+    // `get` may take and how often it is re-issued after timing out
+    settings: Arc<RwLock<Settings>>,
+    // Peers a put stores a record on, which gets with a large quorum are measured against
+    replication_factor: NonZeroUsize,
+    watches: HashMap<RecordKey, WatchState>,
+    watch_interval: Duration,
+    refresh_interval: Option<Duration>,
+    // The routing table refresh under way, with the table's size when it started
+    refresh: Option<(QueryId, usize)>,
+    invalidation_topic: gossipsub::IdentTopic,
+    // Newest invalidation version applied to each key, so replayed or overtaken messages are ignored
+    invalidations: HashMap<RecordKey, u64>,
+    // Newest version of each key our lookups have returned, which a put must not fall behind
+    seen_versions: HashMap<RecordKey, u64>,
+    // When each local record was stored or last checked against the DHT; a `get`
+    // serves copies younger than `freshness` straight from the store
+    checked: HashMap<RecordKey, Instant>,
+    freshness: Duration,
+    // How long past its expiry a local or cached copy is still served, stale, while it is refreshed
+    swr_grace: Duration,
+    // Values recent lookups found for keys we do not store
+    cache: ResultCache,
+    // How names given to commands map to record keys
+    key_hashing: KeyHashing,
+    // The peer each owned key belongs to, set by the first claim we see; only it may write the key
+    owners: HashMap<RecordKey, PeerId>,
+    // Keys republished before they expire, however long the node runs
+    pinned: HashSet<RecordKey>,
+    // The TTL a pinned record is republished with, and the default for puts without one
+    record_ttl: Duration,
+    store_path: PathBuf,
+    owners_path: PathBuf,
+    pins_path: PathBuf,
+}
+
+impl EventLoop {
+    pub(crate) async fn new(
+        config: &Config,
+        commands: mpsc::Receiver<Command>,
+        metrics: Arc<Metrics>,
+        events: broadcast::Sender<RecordEvent>,
+        event_log: EventLog,
+        validator: Option<Arc<dyn RecordValidator>>,
+        settings: Arc<RwLock<Settings>>,
+    ) -> Result<Self, DhtError> {
+        let setup = |e: &dyn std::fmt::Display| DhtError::Setup(e.to_string());
+        let replication_factor = config.replication_factor().map_err(|e| setup(&e))?;
+        let republish_interval = config.republish_interval().map_err(|e| setup(&e))?;
+
+        // Reuse the identity from earlier runs so our PeerId, and addresses others saved for us, stay valid
+        let local_key = match config.seed().map_err(|e| setup(&e))? {
+            Some(seed) => {
+                info!("Derived identity from the configured seed");
+                persist::identity_from_seed(seed)
+            }
+            None => {
+                let identity_path = config.identity_path();
+                let (local_key, generated) = persist::load_or_generate_identity(&identity_path)
+                    .map_err(|e| DhtError::Setup(format!("identity {}: {}", identity_path.display(), e)))?;
+                if generated {
+                    info!("Generated new identity, saved to {}", identity_path.display());
+                } else {
+                    info!("Loaded identity from {}", identity_path.display());
+                }
+                local_key
+            }
+        };
+        let local_peer_id = PeerId::from(local_key.public());
+        info!("Local peer id: {}", local_peer_id);
+
+        // Create a Kademlia behavior. The store holds sealed envelopes, so its
+        // limit is the envelope size of the largest value we accept
+        let max_sealed_bytes = envelope::sealed_len(&local_key, config.max_record_bytes);
+        let store = MemoryStore::with_config(local_peer_id, MemoryStoreConfig {
+            max_records: config.max_records,
+            // The store rejects values of `max_value_bytes` or more
+            max_value_bytes: max_sealed_bytes + 1,
+            ..MemoryStoreConfig::default()
+        });
+        let mut kad_config = kad::Config::default();
+        // Leave room beside the value for the key, publisher and message framing
+        kad_config.set_max_packet_size(max_sealed_bytes + 4096);
+        kad_config.set_query_timeout(config.query_timeout());
+        // Records without an explicit TTL expire after the default, and ours are
+        // re-published before that so they stay alive while we are running
+        kad_config.set_replication_factor(replication_factor);
+        kad_config.set_record_ttl(Some(config.record_ttl()));
+        kad_config.set_publication_interval(Some(republish_interval));
+        kad_config.set_replication_interval(Some(config.replication_interval()));
+        // Peers speaking another protocol name never answer our requests, which keeps networks apart
+        let protocol_name = config.protocol_name().map_err(|e| setup(&e))?;
+        kad_config.set_protocol_names(vec![protocol_name.clone()]);
+        info!(
+            "Kademlia {}: replication factor {}, record TTL {}s, republish every {}s, replicate every {}s",
+            protocol_name, replication_factor, config.record_ttl_secs, republish_interval.as_secs(), config.replication_interval_secs,
+        );
+        // Hand inbound records and providers to the event loop, which decides whether each is stored
+        kad_config.set_record_filtering(kad::StoreInserts::FilterBoth);
+        let mut kademlia = kad::Behaviour::with_config(local_peer_id, store, kad_config);
+
+        // Answer queries from other peers even before an external address is confirmed
+        kademlia.set_mode(Some(kad::Mode::Server));
+
+        // Stop answering a peer that floods us with requests, for a while
+        let kademlia = Throttled::new(kademlia, inbound_limits(config), metrics.throttled_peers.clone(), metrics.throttled_requests.clone());
+
+        // Discover other nodes on the local network automatically
+        let mdns = mdns::tokio::Behaviour::new(mdns::Config::default(), local_peer_id).map_err(|e| setup(&e))?;
+
+        // Refuse connections beyond the configured limits rather than let them pile up
+        let limits = connection_limits::Behaviour::new(ConnectionLimits::default()
+            .with_max_established(config.max_connections)
+            .with_max_pending_incoming(config.max_pending_connections)
+            .with_max_pending_outgoing(config.max_pending_connections));
+
+        // Ask peers to dial us back, to learn whether we are reachable from outside and on which address
+        let autonat = autonat::Behaviour::new(local_peer_id, autonat::Config::default());
+
+        // Lets us listen on, and dial, /p2p-circuit addresses through a relay
+        let (relay_transport, relay_client) = relay::client::new(local_peer_id);
+
+        // Tell peers caching a key when it changes. Messages carry the new sealed
+        // value, so they must fit the largest record we accept
+        let gossipsub_config = gossipsub::ConfigBuilder::default()
+            .max_transmit_size(max_sealed_bytes * 2 + 4096)
+            .build()
+            .map_err(|e| setup(&e))?;
+        let mut gossipsub = gossipsub::Behaviour::new(gossipsub::MessageAuthenticity::Signed(local_key.clone()), gossipsub_config)
+            .map_err(|e| setup(&e))?;
+        let invalidation_topic = gossipsub::IdentTopic::new(invalidation::TOPIC);
+        gossipsub.subscribe(&invalidation_topic).map_err(|e| setup(&e))?;
+
+        // Measure round-trip times, and notice connections whose peer has gone away
+        let ping = ping::Behaviour::new(ping::Config::new().with_interval(PING_INTERVAL).with_timeout(PING_TIMEOUT));
+
+        let psk = config.psk().map_err(|e| setup(&e))?;
+        match &psk {
+            Some(psk) => info!("Private network mode: on (pre-shared key fingerprint {})", psk.fingerprint()),
+            None => info!("Private network mode: off"),
+        }
+        let upgrades = transport::Upgrades::default();
+        let security = config.security().map_err(|e| setup(&e))?;
+        info!("Security handshake: {}", config.security);
+        let transport = transport::build(&local_key, config.dial_timeout(), relay_transport, psk, security, upgrades.clone(), config.trace_dials)?;
+        let swarm = Swarm::new(
+            transport,
+            Behaviour { limits, kademlia, mdns, autonat, relay_client, gossipsub, ping },
+            local_peer_id,
+            swarm::Config::with_tokio_executor().with_idle_connection_timeout(config.idle_timeout()),
+        );
+
+        // Key owners are saved next to the records, e.g. ~/.dht/records.acl
+        let owners_path = config.store_path().with_extension("acl");
+        let pins_path = config.store_path().with_extension("pins");
+        let mut event_loop = EventLoop {
+            swarm,
+            commands,
+            pending: PendingQueries::default(),
+            connected: HashMap::new(),
+            upgrades,
+            secured: HashMap::new(),
+            bootstrap_peers: HashMap::new(),
+            address_failures: HashMap::new(),
+            prune_after_failures: config.prune_after_failures,
+            trace_dials: config.trace_dials,
+            public_only: config.public_only,
+            protocol_name,
+            listeners: Vec::new(),
+            listen_addrs: Vec::new(),
+            metrics,
+            started: Instant::now(),
+            rtts: HashMap::new(),
+            pings: HashMap::new(),
+            events,
+            event_log,
+            keypair: local_key,
+            require_signed: config.require_signed,
+            readonly: config.readonly,
+            validator,
+            max_record_bytes: config.max_record_bytes,
+            max_sealed_bytes,
+            compress: config.compress,
+            max_records: config.max_records,
+            settings,
+            replication_factor,
+            watches: HashMap::new(),
+            watch_interval: config.watch_interval(),
+            refresh_interval: config.refresh_interval(),
+            refresh: None,
+            invalidation_topic,
+            invalidations: HashMap::new(),
+            seen_versions: HashMap::new(),
+            checked: HashMap::new(),
+            freshness: config.freshness(),
+            swr_grace: config.swr_grace(),
+            cache: ResultCache::new(config.cache_capacity, config.swr_grace()),
+            key_hashing: config.key_hashing().map_err(|e| setup(&e))?,
+            owners: persist::load_owners(&owners_path),
+            pinned: persist::load_pins(&pins_path),
+            record_ttl: config.record_ttl(),
+            store_path: config.store_path(),
+            owners_path,
+            pins_path,
+        };
+
+        // Listen on the configured addresses (all interfaces and a random port by default)
+        for addr in config.listen_addrs().map_err(|e| setup(&e))? {
+            if psk.is_some() && addr.iter().any(|protocol| matches!(protocol, Protocol::QuicV1)) {
+                warn!("not listening on {}: QUIC cannot be used in private network mode", addr);
+                continue;
+            }
+            match event_loop.swarm.listen_on(addr.clone()) {
+                Ok(listener) => event_loop.listeners.push(listener),
+                // Hosts without IPv6 still start on IPv4 alone, and a node that
+                // cannot bind at all still serves its local store
+                Err(e) => {
+                    // The transport error itself displays nothing useful; its cause says why the bind failed
+                    let reason = std::error::Error::source(&e).map_or_else(|| e.to_string(), ToString::to_string);
+                    warn!("not listening on {}: {}", addr, reason);
+                }
+            }
+        }
+        if event_loop.listeners.is_empty() {
+            warn!("no listener could be bound; operating in local-only mode, DHT unavailable");
+        }
+
+        // Reserve a slot on each relay; the circuit address is reported like any other listen address
+        for addr in config.relay_listen_addrs().map_err(|e| setup(&e))? {
+            let listener = event_loop.swarm.listen_on(addr).map_err(|e| setup(&e))?;
+            event_loop.listeners.push(listener);
+        }
+
+        // Restore records saved by a previous run before serving any requests
+        let records = persist::load_records(&event_loop.store_path);
+        let loaded = records.len();
+        for record in records {
+            event_loop.learn_owner(&record.key, &record.value);
+            if let Err(e) = event_loop.store().put(record) {
+                warn!("could not restore record: {}", e);
+            }
+        }
+        info!("Loaded {} record(s) from {}", loaded, event_loop.store_path.display());
+        event_loop.update_store_gauge();
+
+        // Join a known network if bootstrap peers were given at launch, or are published for it
+        let mut bootstrap = config.bootstrap.clone();
+        if let Some(url) = &config.bootstrap_url {
+            match seed_list::fetch(url).await {
+                Ok(fetched) => {
+                    info!("Fetched {} bootstrap peer(s) from {}", fetched.len(), url);
+                    bootstrap.extend(fetched);
+                }
+                Err(e) => warn!(
+                    "could not fetch bootstrap peers from {}: {}; joining through the {} configured one(s)",
+                    url, e, config.bootstrap.len(),
+                ),
+            }
+        }
+        if !bootstrap.is_empty() {
+            for entry in &bootstrap {
+                match entry.parse::<Multiaddr>() {
+                    Ok(addr) => match peer_id_of(&addr) {
+                        Some(peer_id) => {
+                            // Bootstrap peers are also trusted to tell us whether we are reachable
+                            event_loop.swarm.behaviour_mut().autonat.add_server(peer_id, Some(addr.clone()));
+                            event_loop.swarm.behaviour_mut().kademlia.add_address(&peer_id, addr.clone());
+                            event_loop.swarm.behaviour_mut().kademlia.exempt(peer_id);
+                            event_loop.bootstrap_peers.insert(peer_id, BootstrapPeer { addr, failures: 0, next_attempt: None, dialing: false });
+                        }
+                        None => warn!("bootstrap address is missing a /p2p/<peer_id> component: {}", entry),
+                    },
+                    Err(e) => warn!("invalid bootstrap address {}: {}", entry, e),
+                }
+            }
+            if let Err(e) = event_loop.start_bootstrap() {
+                warn!("cannot bootstrap: {}", e);
+            }
+        }
+
+        Ok(event_loop)
+    }
+
+    pub(crate) fn local_peer_id(&self) -> PeerId {
+        *self.swarm.local_peer_id()
+    }
+
+    /// Run the node until [`DhtNode::shutdown`](crate::DhtNode::shutdown) is called or every handle is dropped.
+    ///
+    /// The local store is saved to disk periodically and once more on the way out.
+    pub async fn run(mut self) {
+        // Periodically flush the store so a crash loses at most one interval of writes
+        let mut persist_timer = tokio::time::interval(PERSIST_INTERVAL);
+        let mut watch_timer = tokio::time::interval(self.watch_interval);
+        // The store only hides expired records from lookups; this reclaims them
+        let mut sweep_timer = tokio::time::interval(EXPIRY_SWEEP_INTERVAL);
+        let mut pin_timer = tokio::time::interval(PIN_REFRESH_INTERVAL);
+        // Startup already bootstraps, so the first refresh waits a whole interval
+        let refresh_period = self.refresh_interval.unwrap_or(Duration::from_secs(3600));
+        let mut refresh_timer = tokio::time::interval_at(tokio::time::Instant::now() + refresh_period, refresh_period);
+
+        let shutdown = loop {
+            // Wake up for the first `get` to outlive its deadline, if any are pending
+            let next_deadline = self.pending.gets.values().map(|get| get.deadline).min();
+            let get_expiry = tokio::time::sleep_until(next_deadline.unwrap_or_else(Instant::now).into());
+            // And for the first bootstrap peer due to be redialed
+            let next_reconnect = self.bootstrap_peers.values().filter_map(|peer| peer.next_attempt).min();
+            let reconnect_due = tokio::time::sleep_until(next_reconnect.unwrap_or_else(Instant::now).into());
+
+            tokio::select! {
+                command = self.commands.recv() => match command {
+                    Some(Command::Shutdown { reply }) => break Some(reply),
+                    Some(command) => {
+                        self.handle_command(command);
+                        self.update_store_gauge();
+                    }
+                    None => break None,
+                },
+                event = self.swarm.select_next_some() => self.handle_event(event),
+                _ = persist_timer.tick() => {
+                    // Also picks up records stored by remote peers
+                    self.update_store_gauge();
+                    if let Err(e) = self.save_records() {
+                        warn!("could not save records to {}: {}", self.store_path.display(), e);
+                    }
+                }
+                _ = get_expiry, if next_deadline.is_some() => self.expire_gets(),
+                _ = reconnect_due, if next_reconnect.is_some() => self.reconnect_bootstrap_peers(),
+                _ = watch_timer.tick() => self.poll_watches(),
+                _ = sweep_timer.tick() => {
+                    self.sweep_expired();
+                    self.update_store_gauge();
+                }
+                _ = pin_timer.tick() => self.refresh_pins(),
+                _ = refresh_timer.tick(), if self.refresh_interval.is_some() => self.refresh_routing_table(),
+            }
+        };
+
+        // Flush once more so writes since the last tick are not lost
+        match self.save_records() {
+            Ok(count) => info!("Saved {} record(s) to {}", count, self.store_path.display()),
+            Err(e) => warn!("could not save records to {}: {}", self.store_path.display(), e),
+        }
+        for listener in std::mem::take(&mut self.listeners) {
+            self.swarm.remove_listener(listener);
+        }
+        self.event_log.record("stop", json!({}));
+        self.event_log.flush().await;
+
+        if let Some(reply) = shutdown {
+            let _ = reply.send(());
+        }
+    }
+
+    fn handle_event<E: std::fmt::Display>(&mut self, event: SwarmEvent<BehaviourEvent, E>) {
+        self.log_event(&event);
+        match event {
+            SwarmEvent::NewListenAddr { address, .. } => {
+                info!("Listening on {}", address);
+                self.listen_addrs.push(address);
+            }
+            SwarmEvent::ExpiredListenAddr { address, .. } => {
+                info!("No longer listening on {}", address);
+                self.listen_addrs.retain(|addr| addr != &address);
+            }
+            SwarmEvent::ListenerClosed { listener_id, addresses, reason } => {
+                // A relay listener closes this way when the relay cannot be reached or refuses us
+                if let Err(e) = reason {
+                    warn!("listener closed: {}", e);
+                }
+                self.listen_addrs.retain(|addr| !addresses.contains(addr));
+                self.listeners.retain(|listener| *listener != listener_id);
+            }
+            SwarmEvent::Dialing { peer_id, connection_id } if self.trace_dials => {
+                let peer = peer_id.map(|peer_id| peer_id.to_string()).unwrap_or_else(|| "unknown peer".to_string());
+                info!("dial {:?}: dialing {}", connection_id, peer);
+            }
+            SwarmEvent::ConnectionEstablished {
+                peer_id, connection_id, endpoint, num_established, concurrent_dial_errors, established_in, ..
+            } => {
+                let addr = endpoint.get_remote_address().clone();
+                if self.trace_dials && endpoint.is_dialer() {
+                    info!("dial {:?}: established to {} at {} in {:?}", connection_id, peer_id, addr, established_in);
+                    for (failed, error) in concurrent_dial_errors.iter().flatten() {
+                        info!("dial {:?}: other address {} failed: {}", connection_id, failed, transport::error_chain(error));
+                    }
+                }
+                let upgrade = self.upgrades.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).remove(&(peer_id, addr.clone()));
+                // Every transport we build encrypts, so a connection without a recorded upgrade came
+                // through some path that does not; fail closed rather than talk to it in the clear
+                let Some(upgrade) = upgrade else {
+                    error!("Connection to {} at {} negotiated no encryption, closing it", peer_id, addr);
+                    self.swarm.close_connection(connection_id);
+                    return;
+                };
+                info!(
+                    "Connected to {} at {} (security {}, muxer {}{})",
+                    peer_id, addr, upgrade.security, upgrade.muxer, if upgrade.private_network { ", private network" } else { "" },
+                );
+                if endpoint.is_dialer() {
+                    self.address_failures.remove(&(peer_id, without_peer_id(&addr)));
+                }
+                if let Some(peer) = self.bootstrap_peers.get_mut(&peer_id) {
+                    if peer.dialing || peer.failures > 0 {
+                        info!("Reconnected to bootstrap peer {}", peer_id);
+                    }
+                    *peer = BootstrapPeer { addr: peer.addr.clone(), failures: 0, next_attempt: None, dialing: false };
+                }
+                self.secured.insert(connection_id, ConnectionSecurity {
+                    peer_id,
+                    addr,
+                    security: upgrade.security,
+                    muxer: upgrade.muxer,
+                    private_network: upgrade.private_network,
+                });
+                self.connected.entry(peer_id).or_default().push((endpoint.get_remote_address().clone(), Instant::now()));
+                self.metrics.connected_peers.set(self.connected.len() as i64);
+                if num_established.get() == 1 && self.routable(&peer_id) {
+                    self.probe_protocol(peer_id);
+                }
+            }
+            SwarmEvent::ConnectionClosed { peer_id, connection_id, endpoint, num_established, cause } => {
+                self.secured.remove(&connection_id);
+                // A connection we dialed that broke counts against its address like a failed dial
+                if cause.is_some() && endpoint.is_dialer() {
+                    self.address_failed(peer_id, endpoint.get_remote_address());
+                }
+                if num_established == 0 {
+                    self.connected.remove(&peer_id);
+                    self.answer_pings(peer_id, Err("connection closed".to_string()));
+                    if let Some(peer) = self.bootstrap_peers.get_mut(&peer_id) {
+                        info!("Lost connection to bootstrap peer {}, reconnecting in {:?}", peer_id, RECONNECT_MIN_DELAY);
+                        peer.next_attempt = Some(Instant::now() + RECONNECT_MIN_DELAY);
+                    }
+                } else if let Some(addrs) = self.connected.get_mut(&peer_id)
+                    && let Some(index) = addrs.iter().position(|(addr, _)| addr == endpoint.get_remote_address())
+                {
+                    addrs.remove(index);
+                }
+                self.metrics.connected_peers.set(self.connected.len() as i64);
+            }
+            SwarmEvent::OutgoingConnectionError { peer_id, error: DialError::Denied { ref cause }, .. }
+                if let Some(exceeded) = cause.downcast_ref::<connection_limits::Exceeded>() =>
+            {
+                let peer = peer_id.map(|peer_id| peer_id.to_string()).unwrap_or_else(|| "peer".to_string());
+                warn!("dial to {} rejected: {}", peer, exceeded);
+            }
+            SwarmEvent::IncomingConnectionError { send_back_addr, error: ListenError::Denied { ref cause }, .. }
+                if let Some(exceeded) = cause.downcast_ref::<connection_limits::Exceeded>() =>
+            {
+                warn!("incoming connection from {} rejected: {}", send_back_addr, exceeded);
+            }
+            SwarmEvent::OutgoingConnectionError { connection_id, peer_id, error } => {
+                if self.trace_dials {
+                    self.trace_dial_error(connection_id, peer_id, &error);
+                }
+                match peer_id {
+                    Some(peer_id) => {
+                        warn!("failed to connect to {}: {}", peer_id, error);
+                        match &error {
+                            DialError::Transport(attempts) => {
+                                for (addr, _) in attempts {
+                                    self.address_failed(peer_id, addr);
+                                }
+                            }
+                            // Whoever answers there now, it is not this peer
+                            DialError::WrongPeerId { endpoint, .. } => self.address_failed(peer_id, endpoint.get_remote_address()),
+                            _ => {}
+                        }
+                        // Other dials to the peer may still be under way
+                        if !self.connected.contains_key(&peer_id) && !self.swarm.is_connected(&peer_id) {
+                            self.answer_pings(peer_id, Err(format!("could not connect: {}", error)));
+                            self.bootstrap_dial_failed(peer_id);
+                        }
+                    }
+                    None => warn!("failed to connect: {}", error),
+                }
+            }
+            SwarmEvent::Behaviour(BehaviourEvent::Ping(ping::Event { peer, connection, result })) => match result {
+                Ok(rtt) => {
+                    // Weight the newest sample by a quarter, so one slow round trip does not dominate
+                    let average = self.rtts.entry(peer).or_insert(rtt);
+                    *average = (*average * 3 + rtt) / 4;
+                    debug!("Ping to {}: {:?} (average {:?})", peer, rtt, average);
+                    self.answer_pings(peer, Ok(rtt));
+                }
+                Err(ping::Failure::Unsupported) => {
+                    debug!("{} does not support ping", peer);
+                    self.answer_pings(peer, Err("peer does not support ping".to_string()));
+                }
+                Err(e) => {
+                    // The peer stopped answering: drop the connection rather than keep routing through it
+                    warn!("{} stopped responding to pings ({}), closing the connection", peer, e);
+                    self.swarm.close_connection(connection);
+                    self.answer_pings(peer, Err(e.to_string()));
+                }
+            },
+            SwarmEvent::Behaviour(BehaviourEvent::Kademlia(kad::Event::OutboundQueryProgressed {
+                id,
+                result: QueryResult::GetRecord(result),
+                ..
+            })) => {
+                if self.pending.put_reads.contains_key(&id) {
+                    self.handle_put_read(id, result);
+                } else if self.pending.get_alls.contains_key(&id) {
+                    self.handle_get_all(id, result);
+                } else if self.pending.describes.contains_key(&id) {
+                    self.handle_describe(id, result);
+                } else if self.pending.watches.contains_key(&id) {
+                    self.handle_watch(id, result);
+                } else {
+                    self.handle_get_record(id, result);
+                }
+            }
+            SwarmEvent::Behaviour(BehaviourEvent::Kademlia(kad::Event::OutboundQueryProgressed {
+                id,
+                result: QueryResult::PutRecord(result),
+                ..
+            })) => {
+                self.handle_put_record(id, result);
+            }
+            SwarmEvent::Behaviour(BehaviourEvent::Kademlia(kad::Event::OutboundQueryProgressed {
+                id,
+                result: QueryResult::StartProviding(result),
+                ..
+            })) => {
+                if let Some(reply) = self.pending.provides.remove(&id) {
+                    let _ = reply.send(result.map(|_| ()).map_err(|_| DhtError::Timeout));
+                }
+            }
+            SwarmEvent::Behaviour(BehaviourEvent::Kademlia(kad::Event::OutboundQueryProgressed {
+                id,
+                result: QueryResult::GetProviders(result),
+                ..
+            })) => {
+                self.handle_get_providers(id, result);
+            }
+            SwarmEvent::Behaviour(BehaviourEvent::Kademlia(kad::Event::OutboundQueryProgressed {
+                id,
+                result: QueryResult::GetClosestPeers(result),
+                stats,
+                ..
+            })) => {
+                let timed_out = result.is_err();
+                let peers = match result {
+                    Ok(kad::GetClosestPeersOk { peers, .. }) | Err(kad::GetClosestPeersError::Timeout { peers, .. }) => peers,
+                };
+                if let Some(peer_id) = self.pending.probes.remove(&id) {
+                    self.probe_finished(peer_id, peers.contains(&peer_id));
+                } else if let Some(PendingFindNode { target, reply }) = self.pending.find_nodes.remove(&id) {
+                    let mut peers: Vec<_> = peers.into_iter()
+                        .map(|peer_id| (target.distance(&kad::KBucketKey::from(peer_id)), peer_id))
+                        .collect();
+                    peers.sort();
+                    let _ = reply.send(Ok(ClosestPeers {
+                        peers: peers.into_iter().map(|(distance, peer_id)| (peer_id, distance.ilog2())).collect(),
+                        asked: stats.num_requests(),
+                        answered: stats.num_successes(),
+                        duration: stats.duration(),
+                        timed_out,
+                    }));
+                }
+            }
+            SwarmEvent::Behaviour(BehaviourEvent::Kademlia(kad::Event::OutboundQueryProgressed {
+                id,
+                result: QueryResult::Bootstrap(result),
+                step,
+                ..
+            })) => {
+                if let Some((_, before)) = self.refresh.filter(|(refresh, _)| *refresh == id) {
+                    if let Err(e) = result {
+                        warn!("routing table refresh failed: {}", e);
+                    }
+                    // Each of the refresh's lookups reports once; the last one ends it
+                    if step.last {
+                        self.refresh = None;
+                        info!("Routing table refresh done: {} -> {} peer(s)", before, self.routing_table_size());
+                    }
+                    return;
+                }
+                match result {
+                    Ok(kad::BootstrapOk { peer, num_remaining }) => {
+                        debug!("Bootstrap progress: reached {} ({} remaining)", peer, num_remaining);
+                        if num_remaining == 0 {
+                            info!("Bootstrap complete, routing table populated");
+                        }
+                    }
+                    Err(e) => warn!("bootstrap failed: {}", e),
+                }
+            }
+            SwarmEvent::Behaviour(BehaviourEvent::Kademlia(kad::Event::InboundRequest {
+                request: kad::InboundRequest::PutRecord { source, record: Some(record), .. },
+            })) => {
+                let key = display_key(record.key.as_ref());
+                let outcome = self.accept_inbound(record);
+                if self.count_inbound(&key, source, outcome) {
+                    self.emit(RecordEvent::Put { key });
+                }
+            }
+            SwarmEvent::Behaviour(BehaviourEvent::Kademlia(kad::Event::InboundRequest {
+                request: kad::InboundRequest::GetRecord { num_closer_peers, present_locally },
+            })) => {
+                debug!("answered a record lookup: {}, {} closer peers", if present_locally { "found" } else { "not found" }, num_closer_peers);
+            }
+            SwarmEvent::Behaviour(BehaviourEvent::Kademlia(kad::Event::InboundRequest {
+                request: kad::InboundRequest::AddProvider { record: Some(record) },
+            })) => {
+                // Provider records carry no signature to check
+                if self.readonly {
+                    self.metrics.readonly_refusals.inc();
+                    debug!("refused provider {} of key {}: the node is read-only", record.provider, display_key(record.key.as_ref()));
+                    return;
+                }
+                if let Err(e) = self.store().add_provider(record) {
+                    warn!("could not store provider record: {}", e);
+                }
+            }
+            SwarmEvent::Behaviour(BehaviourEvent::Autonat(autonat::Event::StatusChanged { old, new })) => {
+                info!("NAT status changed from {:?} to {:?}", old, new);
+                // Advertise the address peers managed to reach us on, and stop once that no longer holds
+                if let autonat::NatStatus::Public(addr) = old {
+                    self.swarm.remove_external_address(&addr);
+                }
+                if let autonat::NatStatus::Public(addr) = new {
+                    if self.public_only && !is_public(&addr) {
+                        info!("Not advertising {}: not dialable from other networks", addr);
+                    } else {
+                        self.swarm.add_external_address(addr);
+                    }
+                }
+            }
+            SwarmEvent::Behaviour(BehaviourEvent::RelayClient(event)) => match event {
+                relay::client::Event::ReservationReqAccepted { relay_peer_id, renewal: false, .. } => {
+                    info!("Reservation accepted by relay {}", relay_peer_id);
+                }
+                relay::client::Event::ReservationReqFailed { relay_peer_id, error, .. } => {
+                    warn!("relay {} refused our reservation: {}", relay_peer_id, error);
+                }
+                relay::client::Event::InboundCircuitEstablished { src_peer_id, .. } => {
+                    debug!("Peer {} reached us through a relay", src_peer_id);
+                }
+                other => debug!("Relay event: {:?}", other),
+            },
+            SwarmEvent::Behaviour(BehaviourEvent::Gossipsub(gossipsub::Event::Message { message, .. })) => {
+                self.handle_invalidation(message);
+            }
+            SwarmEvent::Behaviour(BehaviourEvent::Mdns(mdns::Event::Discovered(peers))) => {
+                for (peer_id, addr) in peers {
+                    info!("Discovered peer {} at {} via mDNS", peer_id, addr);
+                    self.swarm.behaviour_mut().kademlia.add_address(&peer_id, addr);
+                    // A bootstrap peer we gave up on is back in reach, so start reconnecting again
+                    if let Some(peer) = self.bootstrap_peers.get_mut(&peer_id)
+                        && peer.failures >= MAX_RECONNECT_FAILURES
+                        && !self.connected.contains_key(&peer_id)
+                    {
+                        info!("Bootstrap peer {} rediscovered via mDNS, resuming reconnects", peer_id);
+                        peer.failures = 0;
+                        peer.next_attempt = Some(Instant::now());
+                    }
+                }
+            }
+            SwarmEvent::Behaviour(BehaviourEvent::Mdns(mdns::Event::Expired(peers))) => {
+                for (peer_id, addr) in peers {
+                    debug!("mDNS entry expired for peer {} at {}", peer_id, addr);
+                    self.address_failures.remove(&(peer_id, without_peer_id(&addr)));
+                    if self.swarm.behaviour_mut().kademlia.remove_address(&peer_id, &addr).is_some() {
+                        self.forget_failures(&peer_id);
+                    }
+                }
+            }
+            SwarmEvent::Behaviour(BehaviourEvent::Kademlia(kad::Event::RoutingUpdated { peer, old_peer: Some(evicted), .. })) => {
+                debug!("{} took the routing table slot of {}", peer, evicted);
+                self.forget_failures(&evicted);
+            }
+            SwarmEvent::Behaviour(event) => {
+                debug!("Network event received: {:?}", event);
+            }
+            _ => {}
+        }
+    }
+
+    // Record finished queries and connections opening, closing and failing in the event log
+    fn log_event<E: std::fmt::Display>(&self, event: &SwarmEvent<BehaviourEvent, E>) {
+        match event {
+            SwarmEvent::Behaviour(BehaviourEvent::Kademlia(kad::Event::OutboundQueryProgressed { id, result, stats, step })) if step.last => {
+                let (kind, error) = match result {
+                    QueryResult::GetRecord(result) => ("get_record", result.as_ref().err().map(ToString::to_string)),
+                    QueryResult::PutRecord(result) => ("put_record", result.as_ref().err().map(ToString::to_string)),
+                    QueryResult::RepublishRecord(result) => ("republish_record", result.as_ref().err().map(ToString::to_string)),
+                    QueryResult::StartProviding(result) => ("start_providing", result.as_ref().err().map(ToString::to_string)),
+                    QueryResult::RepublishProvider(result) => ("republish_provider", result.as_ref().err().map(ToString::to_string)),
+                    QueryResult::GetProviders(result) => ("get_providers", result.as_ref().err().map(ToString::to_string)),
+                    QueryResult::GetClosestPeers(result) => ("get_closest_peers", result.as_ref().err().map(ToString::to_string)),
+                    QueryResult::Bootstrap(result) => ("bootstrap", result.as_ref().err().map(ToString::to_string)),
+                };
+                self.event_log.record("query", json!({
+                    "id": format!("{:?}", id),
+                    "kind": kind,
+                    "ok": error.is_none(),
+                    "error": error,
+                    "millis": stats.duration().map(|duration| duration.as_millis() as u64),
+                    "requests": stats.num_requests(),
+                    "successes": stats.num_successes(),
+                }));
+            }
+            SwarmEvent::ConnectionEstablished { peer_id, connection_id, endpoint, num_established, .. } => {
+                self.event_log.record("connection_established", json!({
+                    "peer_id": peer_id.to_string(),
+                    "connection": format!("{:?}", connection_id),
+                    "addr": endpoint.get_remote_address().to_string(),
+                    "dialer": endpoint.is_dialer(),
+                    "connections": num_established.get(),
+                }));
+            }
+            SwarmEvent::ConnectionClosed { peer_id, connection_id, endpoint, num_established, cause } => {
+                self.event_log.record("connection_closed", json!({
+                    "peer_id": peer_id.to_string(),
+                    "connection": format!("{:?}", connection_id),
+                    "addr": endpoint.get_remote_address().to_string(),
+                    "connections": num_established,
+                    "cause": cause.as_ref().map(ToString::to_string),
+                }));
+            }
+            SwarmEvent::OutgoingConnectionError { connection_id, peer_id, error } => {
+                self.event_log.record("dial_failed", json!({
+                    "peer_id": peer_id.map(|peer_id| peer_id.to_string()),
+                    "connection": format!("{:?}", connection_id),
+                    "error": error.to_string(),
+                }));
+            }
+            SwarmEvent::IncomingConnectionError { connection_id, send_back_addr, error, .. } => {
+                self.event_log.record("incoming_failed", json!({
+                    "connection": format!("{:?}", connection_id),
+                    "addr": send_back_addr.to_string(),
+                    "error": error.to_string(),
+                }));
+            }
+            _ => {}
+        }
+    }
+
+    // Carry out a request, replying now or once its DHT query completes
+    fn handle_command(&mut self, command: Command) {
+        match command {
+            Command::Put { key, value, quorum, ttl, force, claim, reply } => {
+                self.metrics.puts.inc();
+                let value = match self.stored_value(&key, value) {
+                    Ok(value) => value,
+                    Err(e) => {
+                        self.metrics.rejected_puts.inc();
+                        let _ = reply.send(Err(e));
+                        return;
+                    }
+                };
+                let record_key = self.key_hashing.record_key(&key);
+                if let Err(e) = self.check_owner(&record_key) {
+                    let _ = reply.send(Err(e));
+                    return;
+                }
+
+                // Read the newest version first, so the write can supersede it and a write we never saw is caught
+                let known = self.stored_version(&record_key).unwrap_or(0)
+                    .max(self.seen_versions.get(&record_key).copied().unwrap_or(0));
+                let known_created = self.store().get(&record_key).and_then(|record| created_at(record.key.as_ref(), &record.value));
+                self.metrics.queries_issued.inc();
+                let query_id = self.swarm.behaviour_mut().kademlia.get_record(record_key);
+                debug!("Reading current version of key: {} before writing", key);
+                let pending = PendingPut {
+                    key, value, quorum, ttl, force, claim, known, newest: 0, known_created, newest_created: None, reply,
+                };
+                self.pending.put_reads.insert(query_id, pending);
+            }
+            Command::Get { key, quorum, remote, reply } => {
+                let record_key = self.key_hashing.record_key(&key);
+                let needed = required_copies(quorum, self.replication_factor);
+                let local = needed.get() == 1 && !remote;
+
+                // Try the local store first, ignoring a record once expired or if it fails verification.
+                // A larger quorum needs other peers' copies too, so it always goes to the DHT
+                self.metrics.gets.inc();
+                let now = Instant::now();
+                let swr_grace = self.swr_grace;
+                let mut local_value = self.store().get(&record_key)
+                    .filter(|record| local && record.expires.is_none_or(|expires| expires + swr_grace > now))
+                    .map(|record| (record.value.clone(), record.expires))
+                    .and_then(|(raw, expires)| self.open_entry(record_key.as_ref(), &raw, expires))
+                    .map(|entry| Entry { stale: entry.expires.is_some_and(|expires| expires <= now), ..entry });
+                // A content-addressed key is only ever answered with a value that hashes to it
+                let content = keys::content_digest(&key);
+                let mut tampered = 0;
+                if local_value.as_ref().is_some_and(|entry| !keys::matches_content(content.as_ref(), &entry.value)) {
+                    warn!("ignoring local copy of key {}: its value does not match the content key", key);
+                    local_value = None;
+                    tampered = 1;
+                }
+                if let Some(entry) = &local_value {
+                    self.saw_version(&record_key, entry.version);
+                }
+                let fresh = self.checked.get(&record_key).is_some_and(|checked| checked.elapsed() < self.freshness);
+                // A key we do not store may have been found by a recent lookup
+                let cached = match &local_value {
+                    None if local => self.cache.get(&record_key, now),
+                    _ => None,
+                };
+                let cached = cached.filter(|entry| keys::matches_content(content.as_ref(), &entry.value));
+                if let Some(entry) = cached {
+                    self.metrics.cache_hits.inc();
+                    debug!("Found cached record for key: {}", key);
+                    if entry.stale {
+                        self.revalidate(record_key, content, None);
+                    }
+                    self.emit(RecordEvent::Resolved { key, value: entry.value.clone(), source: entry.source });
+                    let _ = reply.send(Ok(Some(entry)));
+                    return;
+                }
+                match local_value {
+                    // Expired, but within the grace window: answer now and refresh it for the next get
+                    Some(entry) if entry.stale => {
+                        self.metrics.local_hits.inc();
+                        debug!("Serving expired local copy of key {} while it is refreshed", key);
+                        self.revalidate(record_key, content, Some(entry.clone()));
+                        self.emit(RecordEvent::Resolved { key, value: entry.value.clone(), source: None });
+                        let _ = reply.send(Ok(Some(entry)));
+                    }
+                    Some(entry) if fresh => {
+                        self.metrics.local_hits.inc();
+                        debug!("Found record locally for key: {}", key);
+                        self.emit(RecordEvent::Resolved { key, value: entry.value.clone(), source: None });
+                        let _ = reply.send(Ok(Some(entry)));
+                    }
+                    // An old copy may have been overwritten elsewhere, so look for a newer version first
+                    stale => {
+                        if stale.is_some() {
+                            debug!("Local copy of key {} is older than {:?}, checking the DHT", key, self.freshness);
+                        }
+                        self.metrics.dht_misses.inc();
+                        let now = Instant::now();
+                        let (attempt, retries) = read_settings(&self.settings).get_attempts();
+                        self.start_get(PendingGet {
+                            reply,
+                            key: record_key,
+                            needed,
+                            found: 0,
+                            best: None,
+                            stale,
+                            remote,
+                            content,
+                            tampered,
+                            started: now,
+                            deadline: deadline_after(now, attempt),
+                            retries_left: retries,
+                        });
+                    }
+                }
+            }
+            Command::GetAll { key, reply } => {
+                // The local copy, if any, is reported by the query itself, so the store is not read here
+                self.metrics.gets.inc();
+                self.metrics.queries_issued.inc();
+                let record_key = self.key_hashing.record_key(&key);
+                let query_id = self.swarm.behaviour_mut().kademlia.get_record(record_key.clone());
+                debug!("Collecting every record for key: {} (Query ID: {:?})", key, query_id);
+                self.pending.get_alls.insert(query_id, PendingGetAll { reply, key: record_key, found: Vec::new() });
+            }
+            Command::Describe { key, remote: false, reply } => {
+                let record_key = self.key_hashing.record_key(&key);
+                let description = match live_record(self.store(), &record_key).map(Cow::into_owned) {
+                    Some(record) => describe(key, &record, None),
+                    // Without a copy of our own, the one a get last fetched may be cached
+                    None => self.cache.peek(&record_key, Instant::now()).and_then(|(entry, record)| {
+                        let description = describe(key, record, entry.source)?;
+                        Some(RecordDescription { cached: true, ..description })
+                    }),
+                };
+                let _ = reply.send(Ok(description));
+            }
+            Command::Describe { key, remote: true, reply } => {
+                self.metrics.queries_issued.inc();
+                let record_key = self.key_hashing.record_key(&key);
+                let query_id = self.swarm.behaviour_mut().kademlia.get_record(record_key);
+                debug!("Looking up every copy of key: {} to describe it (Query ID: {:?})", key, query_id);
+                self.pending.describes.insert(query_id, PendingDescribe { reply, key, newest: None });
+            }
+            Command::Watch { key, updates, reply } => {
+                let record_key = self.key_hashing.record_key(&key);
+                debug!("Watching key: {}", key);
+                self.watches.insert(record_key.clone(), WatchState { updates, last: None, reported: false, query: None });
+                // Report the current value straight away rather than on the next tick
+                self.poll_watch(record_key);
+                let _ = reply.send(());
+            }
+            Command::Unwatch { key, reply } => {
+                // A lookup still under way finds no watch when it completes and is dropped
+                let _ = reply.send(self.watches.remove(&self.key_hashing.record_key(&key)).is_some());
+            }
+            Command::Delete { key, reply } => {
+                let record_key = self.key_hashing.record_key(&key);
+
+                // Deleting a missing key is not an error, so repeated deletes are harmless
+                let existed = self.store().get(&record_key).is_some();
+                self.store().remove(&record_key);
+                self.checked.remove(&record_key);
+                self.cache.remove(&record_key);
+                self.pinned.remove(&record_key);
+
+                // Stop republishing the record; remote copies are dropped on the invalidation below or expire on their TTL
+                self.swarm.behaviour_mut().kademlia.remove_record(&record_key);
+                if existed {
+                    self.emit(RecordEvent::Removed { key: key.clone() });
+                }
+                self.publish_invalidation(&record_key, None);
+                let _ = reply.send(existed);
+            }
+            Command::DryPut { key, value, claim, reply } => {
+                let _ = reply.send(self.dry_put(key, value, claim));
+            }
+            Command::DryDelete { key, reply } => {
+                let _ = reply.send(self.dry_run(key));
+            }
+            Command::Pin { key, reply } => {
+                let record_key = self.key_hashing.record_key(&key);
+                if self.store().get(&record_key).is_none() {
+                    let _ = reply.send(false);
+                    return;
+                }
+                if self.pinned.insert(record_key) {
+                    info!("Pinned key: {}", key);
+                }
+                // A record put with a short TTL may be due straight away
+                self.refresh_pins();
+                let _ = reply.send(true);
+            }
+            Command::Unpin { key, reply } => {
+                let unpinned = self.pinned.remove(&self.key_hashing.record_key(&key));
+                if unpinned {
+                    info!("Unpinned key: {}", key);
+                }
+                let _ = reply.send(unpinned);
+            }
+            Command::Records { reply } => {
+                let pinned = &self.pinned;
+                let records = self.swarm.behaviour_mut().kademlia.store_mut().records()
+                    .map(|record| RecordSummary {
+                        key: record.key.to_vec(),
+                        size: record.value.len(),
+                        pinned: pinned.contains(&record.key),
+                    })
+                    .collect();
+                let _ = reply.send(records);
+            }
+            Command::Expired { reply } => {
+                let now = Instant::now();
+                let pinned = &self.pinned;
+                let records = self.swarm.behaviour_mut().kademlia.store_mut().records()
+                    .filter(|record| record.is_expired(now))
+                    .map(|record| RecordSummary {
+                        key: record.key.to_vec(),
+                        size: record.value.len(),
+                        pinned: pinned.contains(&record.key),
+                    })
+                    .collect();
+                let _ = reply.send(records);
+            }
+            Command::Entries { reply } => {
+                let now = Instant::now();
+                let records: Vec<Record> = self.store().records()
+                    .filter(|record| !record.is_expired(now))
+                    .map(|record| record.into_owned())
+                    .collect();
+                let entries = records.into_iter()
+                    .filter_map(|record| {
+                        let entry = self.open_entry(record.key.as_ref(), &record.value, record.expires)?;
+                        Some((record.key.to_vec(), entry))
+                    })
+                    .collect();
+                let _ = reply.send(entries);
+            }
+            Command::Provide { key, reply } => {
+                match self.swarm.behaviour_mut().kademlia.start_providing(self.key_hashing.record_key(&key)) {
+                    Ok(query_id) => {
+                        debug!("Announcing ourselves as a provider for key: {}", key);
+                        self.pending.provides.insert(query_id, reply);
+                    }
+                    Err(e) => {
+                        let _ = reply.send(Err(e.into()));
+                    }
+                }
+            }
+            Command::Providers { key, reply } => {
+                let query_id = self.swarm.behaviour_mut().kademlia.get_providers(self.key_hashing.record_key(&key));
+                debug!("Looking up providers for key: {} (Query ID: {:?})", key, query_id);
+                self.pending.providers.insert(query_id, PendingProviders { reply, found: HashSet::new() });
+            }
+            Command::FindNode { target, reply } => {
+                let key = match target.parse::<PeerId>() {
+                    Ok(peer_id) => peer_id.to_bytes(),
+                    Err(_) => self.key_hashing.record_key(&target).to_vec(),
+                };
+                let query_id = self.swarm.behaviour_mut().kademlia.get_closest_peers(key.clone());
+                debug!("Looking up the peers closest to {} (Query ID: {:?})", target, query_id);
+                self.pending.find_nodes.insert(query_id, PendingFindNode { target: kad::KBucketKey::new(key), reply });
+            }
+            Command::Bootstrap { addr, peer_id, reply } => {
+                self.swarm.behaviour_mut().kademlia.add_address(&peer_id, addr);
+                let _ = reply.send(self.start_bootstrap());
+            }
+            Command::Dial { addr, reply } => {
+                // Let Kademlia route through this peer once we know who it is
+                if let Some(peer_id) = peer_id_of(&addr) {
+                    self.swarm.behaviour_mut().kademlia.add_address(&peer_id, addr.clone());
+                }
+                let _ = reply.send(self.swarm.dial(addr).map_err(DhtError::from));
+            }
+            Command::Ping { peer_id, reply } => {
+                if peer_id == self.local_peer_id() {
+                    let _ = reply.send(Err(DhtError::InvalidCommand("cannot ping this node".to_string())));
+                    return;
+                }
+                // A new connection is pinged straight away; an open one on its next interval
+                if !self.connected.contains_key(&peer_id) {
+                    debug!("Dialing {} to ping it", peer_id);
+                    if let Err(e) = self.swarm.dial(peer_id) {
+                        let _ = reply.send(Err(e.into()));
+                        return;
+                    }
+                }
+                self.pings.entry(peer_id).or_default().push(reply);
+            }
+            Command::Peers { reply } => {
+                let _ = reply.send(self.peers());
+            }
+            Command::Security { reply } => {
+                let mut connections: Vec<ConnectionSecurity> = self.secured.values().cloned().collect();
+                connections.sort_by_key(|connection| (connection.peer_id, connection.addr.to_string()));
+                let _ = reply.send(connections);
+            }
+            Command::Buckets { reply } => {
+                let _ = reply.send(self.buckets());
+            }
+            Command::Addrs { reply } => {
+                // Dialable as-is by another node, so each carries our peer id
+                let local_peer_id = *self.swarm.local_peer_id();
+                // Relayed addresses already end in our peer id
+                let dialable = |addr: &Multiaddr| match addr.iter().last() {
+                    Some(Protocol::P2p(_)) => addr.clone(),
+                    _ => addr.clone().with(Protocol::P2p(local_peer_id)),
+                };
+                let public_only = self.public_only;
+                let (listen, private): (Vec<_>, Vec<_>) = self.listen_addrs.iter()
+                    .map(dialable)
+                    .partition(|addr| !public_only || is_public(addr));
+                let _ = reply.send(NodeAddrs {
+                    reachability: self.reachability(),
+                    listen,
+                    private,
+                    // Confirmed by behaviours such as AutoNAT; the swarm keeps this list itself
+                    external: self.swarm.external_addresses().map(dialable).collect(),
+                });
+            }
+            Command::Stats { reply } => {
+                let (records, record_bytes) = self.store().records()
+                    .fold((0, 0), |(count, bytes), record| (count + 1, bytes + record.value.len()));
+                let _ = reply.send(NodeStats {
+                    peer_id: *self.swarm.local_peer_id(),
+                    connected_peers: self.connected.len(),
+                    records,
+                    record_bytes,
+                    listen_addrs: self.listen_addrs.len(),
+                    reachability: self.reachability(),
+                    status: NetworkStatus::of(!self.listen_addrs.is_empty(), self.connected.len()),
+                    queries_issued: self.metrics.queries_issued.get(),
+                    queries_succeeded: self.metrics.queries_succeeded.get(),
+                    queries_failed: self.metrics.queries_failed.get(),
+                    local_hits: self.metrics.local_hits.get(),
+                    cache_hits: self.metrics.cache_hits.get(),
+                    dht_lookups: self.metrics.dht_misses.get(),
+                });
+            }
+            Command::Health { reply } => {
+                let routing_table_peers = self.swarm.behaviour_mut().kademlia.kbuckets()
+                    .map(|bucket| bucket.num_entries())
+                    .sum();
+                let _ = reply.send(Health {
+                    listening: !self.listen_addrs.is_empty(),
+                    connected_peers: self.connected.len(),
+                    routing_table_peers,
+                    uptime: self.started.elapsed(),
+                });
+            }
+            Command::Reconfigure { reply } => {
+                let limits = inbound_limits(&read_settings(&self.settings).config);
+                self.swarm.behaviour_mut().kademlia.set_limits(limits);
+                let _ = reply.send(());
+            }
+            // Stops the loop, so `run` handles it before getting here
+            Command::Shutdown { reply } => {
+                let _ = reply.send(());
+            }
+        }
+    }
+
+    // Connected peers first, then routing table peers we have no connection to
+    fn peers(&mut self) -> Vec<PeerInfo> {
+        // Peers Kademlia can route to, with the addresses it knows for them
+        let mut routing_table: BTreeMap<PeerId, Vec<Multiaddr>> = BTreeMap::new();
+        for bucket in self.swarm.behaviour_mut().kademlia.kbuckets() {
+            for entry in bucket.iter() {
+                routing_table.insert(*entry.node.key.preimage(), entry.node.value.iter().cloned().collect());
+            }
+        }
+
+        let mut peers: Vec<PeerInfo> = self.connected.iter()
+            .map(|(peer_id, connections)| PeerInfo {
+                peer_id: *peer_id,
+                addrs: connections.iter().map(|(addr, _)| addr.clone()).collect(),
+                ages: connections.iter().map(|(_, opened)| opened.elapsed()).collect(),
+                connected: true,
+                routable: routing_table.contains_key(peer_id),
+                rtt: self.rtts.get(peer_id).copied(),
+            })
+            .collect();
+        peers.extend(routing_table.into_iter()
+            .filter(|(peer_id, _)| !self.connected.contains_key(peer_id))
+            .map(|(peer_id, addrs)| PeerInfo { peer_id, addrs, ages: Vec::new(), connected: false, routable: true, rtt: self.rtts.get(&peer_id).copied() }));
+        peers
+    }
+
+    // Resolve every `ping` waiting on `peer` with the round-trip time, or why there is none
+    fn answer_pings(&mut self, peer: PeerId, result: Result<Duration, String>) {
+        for reply in self.pings.remove(&peer).unwrap_or_default() {
+            let _ = reply.send(result.clone().map_err(DhtError::PingFailed));
+        }
+    }
+
+    // The routing table bucket by bucket, skipping the empty ones
+    fn buckets(&mut self) -> Vec<BucketInfo> {
+        let mut buckets = Vec::new();
+        for bucket in self.swarm.behaviour_mut().kademlia.kbuckets() {
+            // The lower bound of a bucket's distance range is 2^index
+            let Some(index) = bucket.range().0.ilog2() else {
+                continue;
+            };
+            let peers = bucket.iter()
+                .map(|entry| {
+                    let peer_id = *entry.node.key.preimage();
+                    PeerInfo {
+                        peer_id,
+                        addrs: entry.node.value.iter().cloned().collect(),
+                        ages: Vec::new(),
+                        connected: self.connected.contains_key(&peer_id),
+                        routable: true,
+                        rtt: self.rtts.get(&peer_id).copied(),
+                    }
+                })
+                .collect();
+            buckets.push(BucketInfo { index, peers });
+        }
+        buckets
+    }
+
+    // Start a lookup for every watched key that does not already have one under way
+    fn poll_watches(&mut self) {
+        let idle: Vec<RecordKey> = self.watches.iter()
+            .filter(|(_, watch)| watch.query.is_none())
+            .map(|(key, _)| key.clone())
+            .collect();
+        for key in idle {
+            self.poll_watch(key);
+        }
+    }
+
+    fn poll_watch(&mut self, key: RecordKey) {
+        self.metrics.queries_issued.inc();
+        let query_id = self.swarm.behaviour_mut().kademlia.get_record(key.clone());
+        if let Some(watch) = self.watches.get_mut(&key) {
+            watch.query = Some(query_id);
+        }
+        self.pending.watches.insert(query_id, key);
+    }
+
+    // Send a watcher the value a lookup found, if it differs from what they last saw
+    fn handle_watch(&mut self, id: QueryId, result: Result<GetRecordOk, kad::GetRecordError>) {
+        let Some(key) = self.pending.watches.get(&id).cloned() else {
+            return;
+        };
+
+        let outcome = match result {
+            Ok(GetRecordOk::FoundRecord(peer_record)) => {
+                // Like `get`, the first valid copy is the answer
+                let Some((value, _)) = self.open_value(key.as_ref(), &peer_record.record.value) else {
+                    return;
+                };
+                if let Some(mut query) = self.swarm.behaviour_mut().kademlia.query_mut(&id) {
+                    query.finish();
+                }
+                Some(Some(value))
+            }
+            Ok(GetRecordOk::FinishedWithNoAdditionalRecord { .. }) | Err(kad::GetRecordError::NotFound { .. }) => Some(None),
+            // An unanswered lookup says nothing about the value; try again next tick
+            Err(e) => {
+                debug!("Watch lookup for key {} failed: {}", display_key(key.as_ref()), e);
+                None
+            }
+        };
+        self.pending.watches.remove(&id);
+        match outcome {
+            Some(_) => self.metrics.queries_succeeded.inc(),
+            None => self.metrics.queries_failed.inc(),
+        }
+
+        // The key may have been unwatched, or watched afresh, while the lookup ran
+        let Some(watch) = self.watches.get_mut(&key).filter(|watch| watch.query == Some(id)) else {
+            return;
+        };
+        watch.query = None;
+        let Some(value) = outcome else {
+            return;
+        };
+        if watch.reported && value == watch.last {
+            return;
+        }
+        match watch.updates.try_send(value.clone()) {
+            Ok(()) => {
+                watch.last = value;
+                watch.reported = true;
+            }
+            // A watcher that is not keeping up gets the change on a later tick
+            Err(mpsc::error::TrySendError::Full(_)) => {}
+            Err(mpsc::error::TrySendError::Closed(_)) => {
+                self.watches.remove(&key);
+            }
+        }
+    }
+
+    // Gather each distinct value a `get-all` lookup turns up and resolve its caller when the query ends
+    fn handle_get_all(&mut self, id: QueryId, result: Result<GetRecordOk, kad::GetRecordError>) {
+        let Some(pending) = self.pending.get_alls.get(&id) else {
+            return;
+        };
+
+        let outcome = match result {
+            Ok(GetRecordOk::FoundRecord(peer_record)) => {
+                let record = peer_record.record;
+                // Records that fail verification are skipped like they are for a single `get`
+                if let Some(mut entry) = self.open_entry(record.key.as_ref(), &record.value, record.expires) {
+                    entry.source = peer_record.peer;
+                    self.saw_version(&record.key, entry.version);
+                    let pending = self.pending.get_alls.get_mut(&id).expect("checked above");
+                    if !pending.found.iter().any(|found| found.value == entry.value) {
+                        pending.found.push(entry);
+                    }
+                }
+                return;
+            }
+            Ok(GetRecordOk::FinishedWithNoAdditionalRecord { .. }) | Err(kad::GetRecordError::NotFound { .. }) => Ok(()),
+            // Whatever arrived before the query window closed is still the answer
+            Err(_) if !pending.found.is_empty() => Ok(()),
+            Err(kad::GetRecordError::Timeout { .. }) => Err(DhtError::Timeout),
+            Err(e) => {
+                debug!("Record lookup failed: {}", e);
+                Ok(())
+            }
+        };
+
+        if let Some(pending) = self.pending.get_alls.remove(&id) {
+            match outcome {
+                Ok(()) => {
+                    self.metrics.queries_succeeded.inc();
+                    let key = display_key(pending.key.as_ref());
+                    for entry in &pending.found {
+                        self.emit(RecordEvent::Resolved { key: key.clone(), value: entry.value.clone(), source: entry.source });
+                    }
+                    let _ = pending.reply.send(Ok(pending.found));
+                }
+                Err(e) => {
+                    self.metrics.queries_failed.inc();
+                    let _ = pending.reply.send(Err(e));
+                }
+            }
+        }
+    }
+
+    // Count the copies a DHT lookup turns up and resolve its caller once the quorum is met or the query ends
+    fn handle_get_record(&mut self, id: QueryId, result: Result<GetRecordOk, kad::GetRecordError>) {
+        let Some(pending) = self.pending.gets.get_mut(&id) else {
+            return;
+        };
+
+        let outcome = match result {
+            // Our own copy is already in hand when it is being checked for staleness, and not wanted for a remote get
+            Ok(GetRecordOk::FoundRecord(peer_record)) if peer_record.peer.is_none() && (pending.stale.is_some() || pending.remote) => return,
+            Ok(GetRecordOk::FoundRecord(peer_record)) => {
+                // Records that fail verification, or are not signed by the key's owner, are skipped;
+                // the query may still turn up a valid one
+                let record = peer_record.record;
+                let Some(mut entry) = self.open_entry(record.key.as_ref(), &record.value, record.expires) else {
+                    return;
+                };
+                entry.source = peer_record.peer;
+                let version = entry.version;
+                let pending = self.pending.gets.get_mut(&id).expect("checked above");
+                if !keys::matches_content(pending.content.as_ref(), &entry.value) {
+                    let from = peer_record.peer.map(|peer| peer.to_string()).unwrap_or_else(|| "the local store".to_string());
+                    warn!("ignoring copy of key {} from {}: its value does not match the content key", display_key(record.key.as_ref()), from);
+                    pending.tampered += 1;
+                    return;
+                }
+                self.learn_owner(&record.key, &record.value);
+                debug!("Found version {} of record in DHT for key: {}", version, display_key(record.key.as_ref()));
+                self.saw_version(&record.key, version);
+                // An expired copy also gives way to a live one of the same version, as its writer republishes it
+                let stale = self.pending.gets.get(&id).and_then(|pending| pending.stale.as_ref()).map(|stale| (stale.version, stale.stale));
+                if let Some(source) = peer_record.peer
+                    && stale.is_some_and(|(stale, expired)| version > stale || (expired && version == stale))
+                {
+                    self.refresh_local(record.clone(), version, source);
+                }
+
+                // Report the newest copy once enough have been seen; later ones for the same query are ignored
+                let pending = self.pending.gets.get_mut(&id).expect("checked above");
+                pending.found += 1;
+                if pending.best.as_ref().is_none_or(|(best, _)| version > best.version) {
+                    pending.best = Some((entry, record));
+                }
+                if pending.found < pending.needed.get() {
+                    return;
+                }
+                // Enough copies are in hand, so stop the query rather than wait for the remaining peers
+                if let Some(mut query) = self.swarm.behaviour_mut().kademlia.query_mut(&id) {
+                    query.finish();
+                }
+                let elapsed = pending.started.elapsed();
+                debug!("Resolved key {} from {} copies in {:?}, query stopped", display_key(pending.key.as_ref()), pending.found, elapsed);
+                self.metrics.get_duration.observe(elapsed.as_secs_f64());
+                self.metrics.queries_succeeded.inc();
+                let (best, record) = pending.best.take().unzip();
+                let entry = newest(best, pending.stale.take());
+                let key = pending.key.clone();
+                self.checked.insert(key.clone(), Instant::now());
+                if let Some(entry) = &entry {
+                    // Stored keys are served from the store; others are kept for the next get
+                    if self.store().get(&key).is_none()
+                        && let Some(record) = record
+                    {
+                        self.cache.insert(key.clone(), entry.clone(), record);
+                    }
+                    let _ = self.events.send(RecordEvent::Resolved {
+                        key: display_key(key.as_ref()),
+                        value: entry.value.clone(),
+                        source: entry.source,
+                    });
+                }
+                Ok(entry)
+            }
+            // Kademlia gave up before our own deadline: retry just the same
+            Err(kad::GetRecordError::Timeout { .. }) => {
+                if let Some(pending) = self.pending.gets.remove(&id) {
+                    self.get_timed_out(pending);
+                }
+                return;
+            }
+            // Still pending means too few copies were found before the query ended
+            Ok(GetRecordOk::FinishedWithNoAdditionalRecord { .. }) | Err(_) if pending.found > 0 => {
+                self.metrics.queries_failed.inc();
+                Err(DhtError::QuorumFailed { quorum: pending.needed, succeeded: pending.found })
+            }
+            // Every copy of a content-addressed key was tampered with
+            Ok(GetRecordOk::FinishedWithNoAdditionalRecord { .. }) | Err(_) if pending.tampered > 0 && pending.stale.is_none() => {
+                self.metrics.queries_failed.inc();
+                let digest = pending.content.expect("only content keys count tampered copies");
+                Err(DhtError::ContentMismatch { key: keys::digest_name(&digest) })
+            }
+            // Nobody else has the key, so an old local copy is still the newest there is
+            Ok(GetRecordOk::FinishedWithNoAdditionalRecord { .. }) if pending.stale.is_some() => {
+                self.metrics.queries_succeeded.inc();
+                self.checked.insert(pending.key.clone(), Instant::now());
+                Ok(pending.stale.take())
+            }
+            Ok(GetRecordOk::FinishedWithNoAdditionalRecord { .. }) => {
+                self.metrics.queries_failed.inc();
+                Ok(None)
+            }
+            Err(e) => {
+                self.metrics.queries_failed.inc();
+                debug!("Record not found in DHT: {}", e);
+                Ok(pending.stale.take())
+            }
+        };
+
+        if let Some(pending) = self.pending.gets.remove(&id) {
+            let _ = pending.reply.send(outcome);
+        }
+    }
+
+    // Spell out why a dial failed: each address tried and the full chain of causes,
+    // which tells DNS, TCP, noise and multistream failures apart
+    fn trace_dial_error(&self, connection_id: ConnectionId, peer_id: Option<PeerId>, error: &DialError) {
+        let peer = peer_id.map(|peer_id| peer_id.to_string()).unwrap_or_else(|| "unknown peer".to_string());
+        match error {
+            DialError::Transport(attempts) => {
+                info!("dial {:?}: all {} address(es) of {} failed", connection_id, attempts.len(), peer);
+                for (addr, error) in attempts {
+                    let stage = match error {
+                        libp2p::TransportError::MultiaddrNotSupported(_) => "no transport for this address",
+                        libp2p::TransportError::Other(_) => "while connecting or upgrading",
+                    };
+                    info!("dial {:?}: {} failed ({}): {}", connection_id, addr, stage, transport::error_chain(error));
+                }
+            }
+            DialError::WrongPeerId { obtained, endpoint } => info!(
+                "dial {:?}: {} answered as {} instead of {}",
+                connection_id, endpoint.get_remote_address(), obtained, peer,
+            ),
+            error => info!("dial {:?}: to {} not attempted: {}", connection_id, peer, transport::error_chain(error)),
+        }
+    }
+
+    // Whether `peer_id` is in the routing table
+    fn routable(&mut self, peer_id: &PeerId) -> bool {
+        self.swarm.behaviour_mut().kademlia.kbuckets()
+            .any(|bucket| bucket.iter().any(|entry| entry.node.key.preimage() == peer_id))
+    }
+
+    // Look up the peer's own id: it is the closest peer to it, so it is asked first, and it
+    // only answers if it speaks our protocol name. Peers enter the routing table from mDNS,
+    // bootstrap and dial before any of them has answered, so this keeps other networks out
+    fn probe_protocol(&mut self, peer_id: PeerId) {
+        let query_id = self.swarm.behaviour_mut().kademlia.get_closest_peers(peer_id);
+        debug!("Checking that {} speaks {} (Query ID: {:?})", peer_id, self.protocol_name, query_id);
+        self.pending.probes.insert(query_id, peer_id);
+    }
+
+    // Drop a probed peer from the routing table unless it answered
+    fn probe_finished(&mut self, peer_id: PeerId, answered: bool) {
+        if answered {
+            debug!("{} speaks {}", peer_id, self.protocol_name);
+            return;
+        }
+        if self.swarm.behaviour_mut().kademlia.remove_peer(&peer_id).is_some() {
+            self.forget_failures(&peer_id);
+            warn!(
+                "{} did not answer on {}, removed it from the routing table; nodes of one network must share --protocol-name",
+                peer_id, self.protocol_name,
+            );
+        }
+    }
+
+    // Count a failed attempt to reach `peer_id` at `addr`, removing the address from
+    // the routing table once it has failed `prune_after_failures` times in a row.
+    // Kademlia already drops a failed address unless it is the peer's last, which
+    // it keeps for good; removing that one takes the peer out of the table.
+    // Counts are only kept for addresses the table still holds, so they cannot pile up
+    fn address_failed(&mut self, peer_id: PeerId, addr: &Multiaddr) {
+        if self.prune_after_failures == 0 {
+            return;
+        }
+        let addr = without_peer_id(addr);
+        let routed = self.routed_addresses(&peer_id);
+        self.address_failures.retain(|(peer, known), _| *peer != peer_id || routed.iter().any(|(routed, _)| routed == known));
+        // Removing an address the table does not hold would still drop a peer that has only one left
+        let Some(stored) = routed.into_iter().find(|(routed, _)| *routed == addr).map(|(_, stored)| stored) else {
+            return;
+        };
+        let failures = self.address_failures.entry((peer_id, addr.clone())).or_insert(0);
+        *failures += 1;
+        let failures = *failures;
+        if failures < self.prune_after_failures {
+            return;
+        }
+        self.address_failures.remove(&(peer_id, addr.clone()));
+        if self.swarm.behaviour_mut().kademlia.remove_address(&peer_id, &stored).is_some() {
+            self.forget_failures(&peer_id);
+            info!("Pruned {} from the routing table: its last address {} failed {} times in a row", peer_id, addr, failures);
+        } else {
+            info!("Pruned address {} of {} from the routing table after {} failures in a row", addr, peer_id, failures);
+        }
+    }
+
+    // The routing table addresses of `peer_id`, each without and with its /p2p part
+    fn routed_addresses(&mut self, peer_id: &PeerId) -> Vec<(Multiaddr, Multiaddr)> {
+        self.swarm.behaviour_mut().kademlia.kbuckets()
+            .find_map(|bucket| {
+                bucket.iter()
+                    .find(|entry| entry.node.key.preimage() == peer_id)
+                    .map(|entry| entry.node.value.iter().map(|stored| (without_peer_id(stored), stored.clone())).collect())
+            })
+            .unwrap_or_default()
+    }
+
+    // Drop the failure counts of a peer that left the routing table
+    fn forget_failures(&mut self, peer_id: &PeerId) {
+        self.address_failures.retain(|(peer, _), _| peer != peer_id);
+    }
+
+    // Redial every bootstrap peer whose reconnect is due
+    fn reconnect_bootstrap_peers(&mut self) {
+        let now = Instant::now();
+        let due: Vec<(PeerId, Multiaddr, u32)> = self.bootstrap_peers.iter_mut()
+            .filter(|(_, peer)| peer.next_attempt.is_some_and(|at| at <= now))
+            .map(|(peer_id, peer)| {
+                peer.next_attempt = None;
+                (*peer_id, peer.addr.clone(), peer.failures)
+            })
+            .collect();
+        for (peer_id, addr, failures) in due {
+            if self.swarm.is_connected(&peer_id) {
+                continue;
+            }
+            info!("Reconnecting to bootstrap peer {} at {} (attempt {})", peer_id, addr, failures + 1);
+            // Addresses learned since, such as one found by mDNS, are tried as well
+            let opts = DialOpts::peer_id(peer_id).addresses(vec![addr]).extend_addresses_through_behaviour().build();
+            if let Some(peer) = self.bootstrap_peers.get_mut(&peer_id) {
+                peer.dialing = true;
+            }
+            if let Err(e) = self.swarm.dial(opts) {
+                warn!("failed to connect to {}: {}", peer_id, e);
+                self.bootstrap_dial_failed(peer_id);
+            }
+        }
+    }
+
+    // Back off before the next reconnect to a bootstrap peer, or give up after too many failures in a row
+    fn bootstrap_dial_failed(&mut self, peer_id: PeerId) {
+        let Some(peer) = self.bootstrap_peers.get_mut(&peer_id).filter(|peer| peer.dialing) else {
+            return;
+        };
+        peer.dialing = false;
+        peer.failures += 1;
+        if peer.failures >= MAX_RECONNECT_FAILURES {
+            warn!(
+                "giving up on bootstrap peer {} after {} failed reconnects; it is retried if mDNS finds it again",
+                peer_id, peer.failures,
+            );
+            return;
+        }
+        let delay = RECONNECT_MIN_DELAY.saturating_mul(1 << peer.failures.min(16)).min(RECONNECT_MAX_DELAY);
+        info!("Reconnect to bootstrap peer {} failed, retrying in {:?}", peer_id, delay);
+        peer.next_attempt = Some(Instant::now() + delay);
+    }
+
+    // Query the DHT for the key of `pending`, answering it once enough copies are found
+    fn start_get(&mut self, pending: PendingGet) {
+        self.metrics.queries_issued.inc();
+        // Kademlia drops an expired local copy as the lookup starts; keep it through the `swr` grace window
+        let now = Instant::now();
+        let swr_grace = self.swr_grace;
+        let expired = self.store().get(&pending.key)
+            .filter(|record| record.expires.is_some_and(|expires| expires <= now && expires + swr_grace > now))
+            .map(Cow::into_owned);
+        let query_id = self.swarm.behaviour_mut().kademlia.get_record(pending.key.clone());
+        if let Some(record) = expired {
+            let _ = self.store().put(record);
+        }
+        debug!("Querying DHT for key: {} (Query ID: {:?}, quorum {})", display_key(pending.key.as_ref()), query_id, pending.needed);
+        self.pending.gets.insert(query_id, pending);
+    }
+
+    // Look `key` up in the background for a get that was answered with a stale copy,
+    // so the new value is in the cache for the next one, or replaces `local`, our
+    // expired copy, in the store. A lookup already under way for the key does the same
+    fn revalidate(&mut self, key: RecordKey, content: Option<[u8; 32]>, local: Option<Entry>) {
+        if self.pending.gets.values().any(|get| get.key == key) {
+            return;
+        }
+        debug!("Refreshing stale copy of key {}", display_key(key.as_ref()));
+        // Nobody waits for the answer; finishing the lookup caches it
+        let (reply, _) = oneshot::channel();
+        let (attempt, retries) = read_settings(&self.settings).get_attempts();
+        let now = Instant::now();
+        self.start_get(PendingGet {
+            reply,
+            key,
+            needed: NonZeroUsize::MIN,
+            found: 0,
+            best: None,
+            stale: local,
+            remote: true,
+            content,
+            tampered: 0,
+            started: now,
+            deadline: deadline_after(now, attempt),
+            retries_left: retries,
+        });
+    }
+
+    // Abandon every `get` attempt whose deadline has passed
+    fn expire_gets(&mut self) {
+        let now = Instant::now();
+        let expired: Vec<QueryId> =
+            self.pending.gets.iter().filter(|(_, get)| get.deadline <= now).map(|(id, _)| *id).collect();
+        for id in expired {
+            // Stop the query so its late results are not kept around; they no longer match a pending entry
+            if let Some(mut query) = self.swarm.behaviour_mut().kademlia.query_mut(&id) {
+                query.finish();
+            }
+            if let Some(pending) = self.pending.gets.remove(&id) {
+                self.get_timed_out(pending);
+            }
+        }
+    }
+
+    // Re-issue a `get` whose attempt ran out of time, or report the timeout once no retries are left
+    fn get_timed_out(&mut self, pending: PendingGet) {
+        self.metrics.queries_failed.inc();
+        let key = display_key(pending.key.as_ref());
+        if pending.retries_left > 0 {
+            debug!("Query for key {} timed out, retrying ({} retries left)", key, pending.retries_left - 1);
+            let deadline = deadline_after(Instant::now(), read_settings(&self.settings).get_attempts().0);
+            self.start_get(PendingGet { found: 0, best: None, deadline, retries_left: pending.retries_left - 1, ..pending });
+            return;
+        }
+
+        debug!("Query for key {} timed out", key);
+        let outcome = if pending.stale.is_some() {
+            // Serve the old copy rather than nothing when the DHT cannot be reached
+            Ok(pending.stale)
+        } else if pending.found > 0 {
+            Err(DhtError::QuorumFailed { quorum: pending.needed, succeeded: pending.found })
+        } else if let Some(digest) = pending.content.filter(|_| pending.tampered > 0) {
+            Err(DhtError::ContentMismatch { key: keys::digest_name(&digest) })
+        } else {
+            Err(DhtError::Timeout)
+        };
+        let _ = pending.reply.send(outcome);
+    }
+
+    // Collect the providers a lookup reports and resolve its caller when the query ends
+    fn handle_get_providers(&mut self, id: QueryId, result: Result<GetProvidersOk, kad::GetProvidersError>) {
+        let Some(PendingProviders { found, .. }) = self.pending.providers.get_mut(&id) else {
+            return;
+        };
+
+        let outcome = match result {
+            Ok(GetProvidersOk::FoundProviders { providers, .. }) => {
+                found.extend(providers);
+                return;
+            }
+            Ok(GetProvidersOk::FinishedWithNoAdditionalRecord { .. }) => Ok(()),
+            // Whatever was found before the timeout is still worth reporting
+            Err(kad::GetProvidersError::Timeout { .. }) if !found.is_empty() => Ok(()),
+            Err(kad::GetProvidersError::Timeout { .. }) => Err(DhtError::Timeout),
+        };
+
+        if let Some(PendingProviders { reply, found }) = self.pending.providers.remove(&id) {
+            let _ = reply.send(outcome.map(|()| found.into_iter().collect()));
+        }
+    }
+
+    // Keep the newest valid copy a `describe` lookup turns up and resolve its caller when the query ends
+    fn handle_describe(&mut self, id: QueryId, result: Result<GetRecordOk, kad::GetRecordError>) {
+        let Some(pending) = self.pending.describes.get_mut(&id) else {
+            return;
+        };
+        let outcome = match result {
+            Ok(GetRecordOk::FoundRecord(peer_record)) => {
+                if let Some(description) = describe(pending.key.clone(), &peer_record.record, peer_record.peer)
+                    && pending.newest.as_ref().is_none_or(|newest| description.version > newest.version)
+                {
+                    pending.newest = Some(description);
+                }
+                return;
+            }
+            Err(kad::GetRecordError::Timeout { .. }) if pending.newest.is_none() => {
+                self.metrics.queries_failed.inc();
+                Err(DhtError::Timeout)
+            }
+            _ => {
+                self.metrics.queries_succeeded.inc();
+                Ok(pending.newest.take())
+            }
+        };
+        if let Some(pending) = self.pending.describes.remove(&id) {
+            let _ = pending.reply.send(outcome);
+        }
+    }
+
+    // Track the newest version a put's read turns up, then write the next one once the lookup ends
+    fn handle_put_read(&mut self, id: QueryId, result: Result<GetRecordOk, kad::GetRecordError>) {
+        if let Ok(GetRecordOk::FoundRecord(peer_record)) = result {
+            let record = peer_record.record;
+            // A copy that does not open says nothing about who owns the key
+            if let Some((_, version)) = self.open_value(record.key.as_ref(), &record.value) {
+                self.learn_owner(&record.key, &record.value);
+                if let Some(pending) = self.pending.put_reads.get_mut(&id)
+                    && version > pending.newest
+                {
+                    pending.newest = version;
+                    pending.newest_created = created_at(record.key.as_ref(), &record.value);
+                }
+            }
+            return;
+        }
+        // However the lookup ended, the newest version found so far is what we build on
+        let Some(pending) = self.pending.put_reads.remove(&id) else {
+            return;
+        };
+        self.metrics.queries_succeeded.inc();
+        let PendingPut { key, value, quorum, ttl, force, claim, known, newest, known_created, newest_created, reply } = pending;
+        let record_key = self.key_hashing.record_key(&key);
+        // The lookup may have turned up a claim by another peer; forcing does not override it
+        if let Err(e) = self.check_owner(&record_key) {
+            let _ = reply.send(Err(e));
+            return;
+        }
+        if newest > known && !force {
+            let _ = reply.send(Err(DhtError::Conflict { known, current: newest }));
+            return;
+        }
+        let version = known.max(newest) + 1;
+        let owned = claim || self.owners.contains_key(&record_key);
+
+        // Sign the value so readers can check who wrote it; without a TTL the configured default applies on publish.
+        // A rewrite keeps the creation time of the version it replaces
+        let now = unix_millis();
+        let created = if newest > known { newest_created } else { known_created };
+        let metadata = Metadata { created: created.unwrap_or(now), updated: now, ttl_secs: ttl.unwrap_or(self.record_ttl).as_secs() };
+        let sealed = envelope::seal(&self.keypair, record_key.as_ref(), &value, version, owned, metadata);
+        let mut record = Record::new(record_key, sealed);
+        record.expires = ttl.map(|ttl| Instant::now() + ttl);
+
+        // Store the record locally first so it is served even if publishing fails.
+        // A record the store refuses is not published either
+        if let Err(e) = self.store().put(record.clone()) {
+            self.metrics.rejected_puts.inc();
+            warn!("local store refused record for key {}: {}", key, e);
+            let error = match e {
+                kad::store::Error::MaxRecords => DhtError::StoreFull { max_records: self.max_records },
+                kad::store::Error::ValueTooLarge => DhtError::ValueTooLarge { size: value.len(), max: self.max_record_bytes },
+                e => e.into(),
+            };
+            let _ = reply.send(Err(error));
+            return;
+        }
+        debug!("Record version {} stored locally for key: {}", version, key);
+        self.checked.insert(record.key.clone(), Instant::now());
+        self.cache.remove(&record.key);
+        if owned && self.owners.insert(record.key.clone(), self.local_peer_id()).is_none() {
+            info!("Claimed ownership of key: {}", key);
+        }
+        self.emit(RecordEvent::Put { key: key.clone() });
+        // Remote peers may hold an older value we never saw, so every put is announced
+        self.publish_invalidation(&record.key.clone(), Some(&record));
+
+        match self.swarm.behaviour_mut().kademlia.put_record(record, quorum) {
+            Ok(query_id) => {
+                self.metrics.queries_issued.inc();
+                debug!("Publishing record to DHT for key: {}", key);
+                self.pending.puts.insert(query_id, reply);
+            }
+            Err(e) => {
+                let _ = reply.send(Err(e.into()));
+            }
+        }
+    }
+
+    fn handle_put_record(&mut self, id: QueryId, result: Result<PutRecordOk, kad::PutRecordError>) {
+        let Some(reply) = self.pending.puts.remove(&id) else {
+            return;
+        };
+
+        let outcome = match result {
+            Ok(PutRecordOk { .. }) => {
+                self.metrics.queries_succeeded.inc();
+                Ok(())
+            }
+            Err(kad::PutRecordError::Timeout { .. }) => {
+                self.metrics.queries_failed.inc();
+                Err(DhtError::Timeout)
+            }
+            Err(kad::PutRecordError::QuorumFailed { success, quorum, .. }) => {
+                self.metrics.queries_failed.inc();
+                Err(DhtError::QuorumFailed { quorum, succeeded: success.len() })
+            }
+        };
+        let _ = reply.send(outcome);
+    }
+
+    // Look up our own id and random ones to keep every bucket filled, unless a refresh is
+    // still under way; a node that knows no peers yet has nothing to refresh
+    fn refresh_routing_table(&mut self) {
+        if self.refresh.is_some() {
+            return;
+        }
+        let before = self.routing_table_size();
+        match self.swarm.behaviour_mut().kademlia.bootstrap() {
+            Ok(id) => {
+                info!("Refreshing routing table ({} peer(s))", before);
+                self.refresh = Some((id, before));
+            }
+            Err(kad::NoKnownPeers()) => debug!("Skipping routing table refresh: no known peers"),
+        }
+    }
+
+    fn routing_table_size(&mut self) -> usize {
+        self.swarm.behaviour_mut().kademlia.kbuckets().map(|bucket| bucket.num_entries()).sum()
+    }
+
+    // Start populating the routing table from the peers added so far
+    fn start_bootstrap(&mut self) -> Result<(), DhtError> {
+        match self.swarm.behaviour_mut().kademlia.bootstrap() {
+            Ok(_) => {
+                info!("Bootstrapping into the DHT...");
+                Ok(())
+            }
+            Err(kad::NoKnownPeers()) => Err(DhtError::NoKnownPeers),
+        }
+    }
+
+    // Unwrap a stored value along with its version, rejecting bad signatures, copies
+    // not signed by the key's owner and, if required, unsigned values
+    fn open_value(&self, key: &[u8], raw: &[u8]) -> Option<(Vec<u8>, u64)> {
+        self.open_entry(key, raw, None).map(|entry| (entry.value, entry.version))
+    }
+
+    // Like `open_value`, as an entry expiring at `expires` that also names its signer
+    fn open_entry(&self, key: &[u8], raw: &[u8], expires: Option<Instant>) -> Option<Entry> {
+        let owner = self.owners.get(&RecordKey::new(&key));
+        match envelope::open(key, raw) {
+            Ok(Opened::Signed { signer, .. }) if owner.is_some_and(|owner| *owner != signer) => {
+                debug!("ignoring record for key {} signed by {}, which does not own it", display_key(key), signer);
+                None
+            }
+            Ok(Opened::Signed { value, signer, seq, .. }) => {
+                debug!("record for key {} is signed by {}", display_key(key), signer);
+                Some(Entry { value: compression::decompress(value), version: seq, expires, signer: Some(signer), source: None, stale: false })
+            }
+            Ok(Opened::Unsigned(value)) if !self.require_signed && owner.is_none() => {
+                Some(Entry { value: compression::decompress(value), version: 0, expires, signer: None, source: None, stale: false })
+            }
+            Ok(Opened::Unsigned(_)) => {
+                warn!("ignoring unsigned record for key {}", display_key(key));
+                None
+            }
+            Err(e) => {
+                warn!("ignoring record for key {}: {}", display_key(key), e);
+                None
+            }
+        }
+    }
+
+    // Version of the valid record we hold for `key`, if any
+    fn stored_version(&mut self, key: &RecordKey) -> Option<u64> {
+        let raw = self.store().get(key)?.value.clone();
+        self.open_value(key.as_ref(), &raw).map(|(_, version)| version)
+    }
+
+    // Remember the newest version of `key` a lookup returned
+    fn saw_version(&mut self, key: &RecordKey, version: u64) {
+        let seen = self.seen_versions.entry(key.clone()).or_default();
+        *seen = (*seen).max(version);
+    }
+
+    // Take the owner a validly signed record claims for `key`, unless the key already has one
+    fn learn_owner(&mut self, key: &RecordKey, raw: &[u8]) {
+        if self.owners.contains_key(key) {
+            return;
+        }
+        if let Ok(Opened::Signed { signer, owned: true, .. }) = envelope::open(key.as_ref(), raw) {
+            info!("Key {} is owned by {}", display_key(key.as_ref()), signer);
+            self.owners.insert(key.clone(), signer);
+        }
+    }
+
+    // Store a record a peer pushed if it passes every check, or say why it was refused
+    fn accept_inbound(&mut self, record: Record) -> Result<(), Refusal> {
+        if self.readonly {
+            self.metrics.readonly_refusals.inc();
+            return Err(Refusal::Routine("the node is read-only".to_string()));
+        }
+        self.admit(record)
+    }
+
+    // Count and log what became of a record `source` pushed to us, returning whether it was stored
+    fn count_inbound(&self, key: &str, source: PeerId, outcome: Result<(), Refusal>) -> bool {
+        let stored = outcome.is_ok();
+        if stored {
+            self.metrics.inbound_accepted.inc();
+        } else {
+            self.metrics.inbound_refused.inc();
+        }
+        let (accepted, refused) = (self.metrics.inbound_accepted.get(), self.metrics.inbound_refused.get());
+        match outcome {
+            Ok(()) => debug!("stored record for key {} from {} ({} accepted, {} refused)", key, source, accepted, refused),
+            Err(Refusal::Routine(reason)) => {
+                debug!("refused record for key {} from {}: {} ({} accepted, {} refused)", key, source, reason, accepted, refused)
+            }
+            Err(Refusal::Suspect(reason)) => {
+                warn!("refused record for key {} from {}: {} ({} accepted, {} refused)", key, source, reason, accepted, refused)
+            }
+        }
+        stored
+    }
+
+    // Store a record written elsewhere, by whatever path it reached us, if it fits, comes from
+    // the key's owner, opens, is not older than our copy and passes the validator
+    fn admit(&mut self, record: Record) -> Result<(), Refusal> {
+        if record.value.len() > self.max_sealed_bytes {
+            return Err(Refusal::Suspect(format!("{} bytes is over the limit of {}", record.value.len(), self.max_sealed_bytes)));
+        }
+        if let Some(owner) = self.unauthorized(&record.key, &record.value) {
+            self.metrics.unauthorized_writes.inc();
+            return Err(Refusal::Suspect(format!("the key is owned by {}", owner)));
+        }
+        let Some((value, version)) = self.open_value(record.key.as_ref(), &record.value) else {
+            return Err(Refusal::Suspect("its envelope is invalid, unsigned or signed by the wrong peer".to_string()));
+        };
+        // Never let a stale copy replace a newer one we hold
+        if let Some(stored) = self.stored_version(&record.key) && version < stored {
+            return Err(Refusal::Routine(format!("version {} is older than our {}", version, stored)));
+        }
+        if let Err(reason) = self.validate(record.key.as_ref(), &value) {
+            self.metrics.rejected_records.inc();
+            return Err(Refusal::Suspect(reason));
+        }
+        // Only a record we accept may claim the key, or a refused one could take it from our own copy
+        self.learn_owner(&record.key, &record.value);
+        self.checked.insert(record.key.clone(), Instant::now());
+        self.cache.remove(&record.key);
+        self.store().put(record).map_err(|e| Refusal::Suspect(format!("could not store it: {}", e)))
+    }
+
+    // The owner of `key`, if it has one and `raw` is not a record signed by it
+    fn unauthorized(&self, key: &RecordKey, raw: &[u8]) -> Option<PeerId> {
+        let owner = *self.owners.get(key)?;
+        match envelope::open(key.as_ref(), raw) {
+            Ok(Opened::Signed { signer, .. }) if signer == owner => None,
+            _ => Some(owner),
+        }
+    }
+
+    // Refuse a put by this node to a key another peer owns
+    fn check_owner(&self, key: &RecordKey) -> Result<(), DhtError> {
+        match self.owners.get(key) {
+            Some(owner) if *owner != self.local_peer_id() => {
+                self.metrics.unauthorized_writes.inc();
+                warn!("refused put to key {}: it is owned by {}", display_key(key.as_ref()), owner);
+                Err(DhtError::Unauthorized { owner: *owner })
+            }
+            _ => Ok(()),
+        }
+    }
+
+    // Whether AutoNAT found us dialable from outside
+    fn reachability(&self) -> Reachability {
+        match self.swarm.behaviour().autonat.nat_status() {
+            autonat::NatStatus::Public(_) => Reachability::Public,
+            autonat::NatStatus::Private => Reachability::Private,
+            autonat::NatStatus::Unknown => Reachability::Unknown,
+        }
+    }
+
+    // Tell subscribers about a record change; nobody listening is not an error
+    fn emit(&self, event: RecordEvent) {
+        let _ = self.events.send(event);
+    }
+
+    fn store(&mut self) -> &mut MemoryStore {
+        self.swarm.behaviour_mut().kademlia.store_mut()
+    }
+
+    // Refresh the stored-records gauge from the local store
+    fn update_store_gauge(&mut self) {
+        let count = self.store().records().count();
+        self.metrics.stored_records.set(count as i64);
+    }
+
+    // Tell peers caching `key` that it changed, or was deleted when `record` is `None`
+    fn publish_invalidation(&mut self, record_key: &RecordKey, record: Option<&Record>) {
+        let ttl = record.and_then(|record| record.expires).map(|expires| expires.saturating_duration_since(Instant::now()));
+        let message = Invalidation::new(record_key.as_ref(), record.map(|record| record.value.as_slice()), ttl);
+        let key = display_key(record_key.as_ref());
+        let topic = self.invalidation_topic.clone();
+        match self.swarm.behaviour_mut().gossipsub.publish(topic, message.encode()) {
+            Ok(_) => debug!("Published invalidation for key: {}", key),
+            // No subscribed peer is connected, so none can be holding a copy from us
+            Err(gossipsub::PublishError::InsufficientPeers) => {}
+            Err(e) => warn!("could not publish invalidation for key {}: {}", key, e),
+        }
+    }
+
+    // Replace or drop our copy of a key another peer changed. Handling one never
+    // publishes another, and gossipsub drops messages it has already seen
+    fn handle_invalidation(&mut self, message: gossipsub::Message) {
+        let (Some(source), Some(invalidation)) = (message.source, Invalidation::decode(&message.data)) else {
+            debug!("ignoring malformed invalidation");
+            return;
+        };
+        let Some(key) = invalidation.record_key().map(RecordKey::from) else {
+            debug!("ignoring invalidation with a malformed key from {}", source);
+            return;
+        };
+        let name = display_key(key.as_ref());
+        // A cached copy may be what changed; the next get looks it up again
+        self.cache.remove(&key);
+        if self.readonly {
+            self.metrics.readonly_refusals.inc();
+            debug!("ignoring invalidation of key {} from {}: the node is read-only", name, source);
+            return;
+        }
+        if self.invalidations.get(&key).is_some_and(|applied| *applied >= invalidation.version) {
+            return;
+        }
+        // Only a copy we hold can be stale, and only the peer that wrote it may replace it
+        let Some(current) = self.store().get(&key).map(Cow::into_owned) else {
+            return;
+        };
+        if !written_by(&current, source) {
+            debug!("ignoring invalidation of key {} from {}, which did not write our copy", name, source);
+            return;
+        }
+        self.invalidations.insert(key.clone(), invalidation.version);
+
+        match invalidation.value() {
+            Ok(Some(value)) if value == current.value => {}
+            Ok(Some(value)) => {
+                // The TTL comes from a peer: never past our own record TTL, and never out of range for an Instant
+                let expires = match invalidation.ttl_secs {
+                    Some(ttl) => match Instant::now().checked_add(Duration::from_secs(ttl).min(self.record_ttl)) {
+                        Some(expires) => Some(expires),
+                        None => {
+                            warn!("ignoring invalidation of key {} from {}: TTL of {}s is out of range", name, source, ttl);
+                            return;
+                        }
+                    },
+                    None => current.expires,
+                };
+                let mut record = Record::new(key, value);
+                record.publisher = Some(source);
+                record.expires = expires;
+                // The writer signs its own envelopes, so a value signed by anyone else is refused
+                let outcome = match envelope::open(record.key.as_ref(), &record.value) {
+                    Ok(Opened::Signed { signer, .. }) if signer != source => {
+                        Err(Refusal::Suspect(format!("the replacement is signed by {}", signer)))
+                    }
+                    _ => self.admit(record),
+                };
+                if self.count_inbound(&name, source, outcome) {
+                    info!("Replaced record for key {} changed by {}", name, source);
+                    self.emit(RecordEvent::Put { key: name });
+                }
+            }
+            Ok(None) => {
+                self.store().remove(&key);
+                self.checked.remove(&key);
+                info!("Dropped record for key {} deleted by {}", name, source);
+                self.emit(RecordEvent::Removed { key: name });
+            }
+            Err(e) => warn!("ignoring invalidation of key {} from {}: {}", name, source, e),
+        }
+    }
+
+    // Replace our stale copy of a key with a newer version a lookup found elsewhere,
+    // or our expired copy with a live one of the same version, checked as if `source` had pushed it
+    fn refresh_local(&mut self, record: Record, version: u64, source: PeerId) {
+        let key = display_key(record.key.as_ref());
+        let live = live_record(self.store(), &record.key).is_some();
+        if self.stored_version(&record.key).is_some_and(|stored| stored > version || (stored == version && live)) {
+            return;
+        }
+        let outcome = self.admit(record);
+        if self.count_inbound(&key, source, outcome) {
+            info!("Updated local copy of key {} to version {}", key, version);
+            self.emit(RecordEvent::Put { key });
+        }
+    }
+
+    // What is known about `key` before a put or delete, without changing anything
+    fn dry_run(&mut self, key: String) -> DryRun {
+        let record_key = self.key_hashing.record_key(&key);
+        DryRun {
+            stored: self.store().get(&record_key).is_some(),
+            pinned: self.pinned.contains(&record_key),
+            owner: self.owners.get(&record_key).copied(),
+            record_key: record_key.to_vec(),
+            store_path: self.store_path.clone(),
+            key,
+            version: 0,
+            value_bytes: 0,
+            stored_bytes: 0,
+            sealed_bytes: 0,
+            signer: None,
+            owned: false,
+        }
+    }
+
+    // Make the checks a put makes before reading the DHT, and seal the value as it would,
+    // without storing or publishing anything
+    fn dry_put(&mut self, key: String, value: Vec<u8>, claim: bool) -> Result<DryRun, DhtError> {
+        let value_bytes = value.len();
+        let value = self.stored_value(&key, value)?;
+        let mut dry_run = self.dry_run(key);
+        if let Some(owner) = dry_run.owner.filter(|owner| *owner != self.local_peer_id()) {
+            return Err(DhtError::Unauthorized { owner });
+        }
+        if !dry_run.stored && self.store().records().count() >= self.max_records {
+            return Err(DhtError::StoreFull { max_records: self.max_records });
+        }
+
+        let record_key = RecordKey::new(&dry_run.record_key);
+        let known = self.stored_version(&record_key).unwrap_or(0)
+            .max(self.seen_versions.get(&record_key).copied().unwrap_or(0));
+        let owned = claim || dry_run.owner.is_some();
+        let now = unix_millis();
+        let created = self.store().get(&record_key).and_then(|record| created_at(record.key.as_ref(), &record.value));
+        let metadata = Metadata { created: created.unwrap_or(now), updated: now, ttl_secs: self.record_ttl.as_secs() };
+        let sealed = envelope::seal(&self.keypair, record_key.as_ref(), &value, known + 1, owned, metadata);
+        dry_run.signer = match envelope::open(record_key.as_ref(), &sealed) {
+            Ok(Opened::Signed { signer, .. }) => Some(signer),
+            _ => None,
+        };
+        dry_run.version = known + 1;
+        dry_run.value_bytes = value_bytes;
+        dry_run.stored_bytes = value.len();
+        dry_run.sealed_bytes = sealed.len();
+        dry_run.owned = owned;
+        Ok(dry_run)
+    }
+
+    // The value a put of `value` stores: checked against a content-addressed key
+    // and the validator, then compressed if that is on, and only then held to the size limit
+    fn stored_value(&self, key: &str, value: Vec<u8>) -> Result<Vec<u8>, DhtError> {
+        if !keys::matches_content(keys::content_digest(key).as_ref(), &value) {
+            return Err(DhtError::ContentMismatch { key: key.to_string() });
+        }
+        if let Err(reason) = self.validate(self.key_hashing.record_key(key).as_ref(), &value) {
+            warn!("refused put of key {}: {}", key, reason);
+            return Err(DhtError::Rejected { reason });
+        }
+        let value = if self.compress { compression::compress(value) } else { value };
+        if value.len() > self.max_record_bytes {
+            return Err(DhtError::ValueTooLarge { size: value.len(), max: self.max_record_bytes });
+        }
+        Ok(value)
+    }
+
+    // Whether the validator, if any, accepts `value` under record key `key`
+    fn validate(&self, key: &[u8], value: &[u8]) -> Result<(), String> {
+        self.validator.as_ref().map_or(Ok(()), |validator| validator.validate(key, value))
+    }
+
+    // Republish each pinned record with a fresh TTL once less than half the TTL is left on it,
+    // so neither our copy nor the ones on other peers ever lapse
+    fn refresh_pins(&mut self) {
+        let now = Instant::now();
+        let keys: Vec<RecordKey> = self.pinned.iter().cloned().collect();
+        for key in keys {
+            let Some(mut record) = self.store().get(&key).map(|record| record.into_owned()) else {
+                // Deleted by its writer elsewhere; the pin applies again if it is put back
+                continue;
+            };
+            // A record without an expiry was loaded from disk or never published by us, so republish it
+            if record.expires.is_some_and(|expires| expires.saturating_duration_since(now) > self.record_ttl / 2) {
+                continue;
+            }
+            record.expires = Some(now + self.record_ttl);
+            match self.swarm.behaviour_mut().kademlia.put_record(record, Quorum::One) {
+                Ok(_) => info!("Republishing pinned record for key: {}", display_key(key.as_ref())),
+                Err(e) => warn!("could not republish pinned record for key {}: {}", display_key(key.as_ref()), e),
+            }
+        }
+    }
+
+    // Remove every record whose TTL has passed from the local store. Pinned ones are kept for
+    // `refresh_pins` to republish, and with --swr others are kept until their grace window ends
+    fn sweep_expired(&mut self) {
+        let now = Instant::now();
+        let pinned = &self.pinned;
+        let swr_grace = self.swr_grace;
+        let expired: Vec<RecordKey> = self.swarm.behaviour_mut().kademlia.store_mut().records()
+            .filter(|record| record.expires.is_some_and(|expires| expires + swr_grace <= now) && !pinned.contains(&record.key))
+            .map(|record| record.key.clone())
+            .collect();
+        if expired.is_empty() {
+            return;
+        }
+        for key in &expired {
+            self.store().remove(key);
+            self.checked.remove(key);
+            self.emit(RecordEvent::Removed { key: display_key(key.as_ref()) });
+        }
+        info!("Reclaimed {} expired record(s)", expired.len());
+    }
+
+    fn save_records(&mut self) -> std::io::Result<usize> {
+        persist::save_owners(&self.owners_path, &self.owners)?;
+        persist::save_pins(&self.pins_path, &self.pinned)?;
+        let store_path = self.store_path.clone();
+        persist::save_records(&store_path, self.store())
+    }
+}
+
+// Extract the peer id from a trailing `/p2p/<peer_id>` component, if any
+// (for a relayed address, the far peer's rather than the relay's)
+fn peer_id_of(addr: &Multiaddr) -> Option<PeerId> {
+    match addr.iter().last() {
+        Some(Protocol::P2p(peer_id)) => Some(peer_id),
+        _ => None,
+    }
+}
+
+// `addr` without a trailing `/p2p/<peer_id>`, as the routing table may hold it
+fn without_peer_id(addr: &Multiaddr) -> Multiaddr {
+    let mut addr = addr.clone();
+    if peer_id_of(&addr).is_some() {
+        addr.pop();
+    }
+    addr
+}
+
+// Whether peers on other networks could dial `addr`: it is not loopback, link-local, private
+// or unspecified. Names count as public, and a relayed address goes by the relay's
+fn is_public(addr: &Multiaddr) -> bool {
+    match addr.iter().next() {
+        Some(Protocol::Ip4(ip)) => !(ip.is_loopback() || ip.is_link_local() || ip.is_private() || ip.is_unspecified()),
+        Some(Protocol::Ip6(ip)) => !(ip.is_loopback() || ip.is_unicast_link_local() || ip.is_unique_local() || ip.is_unspecified()),
+        _ => true,
+    }
+}
+
+// Whether `peer` wrote `record`: it published it, or signed its envelope
+fn written_by(record: &Record, peer: PeerId) -> bool {
+    record.publisher == Some(peer)
+        || matches!(envelope::open(record.key.as_ref(), &record.value), Ok(Opened::Signed { signer, .. }) if signer == peer)
+}
+
+// Number of copies a get must see to satisfy `quorum`, evaluated against the
+// replication factor the same way Kademlia evaluates it for puts
+fn required_copies(quorum: Quorum, total: NonZeroUsize) -> NonZeroUsize {
+    match quorum {
+        Quorum::One => NonZeroUsize::MIN,
+        Quorum::Majority => NonZeroUsize::new(total.get() / 2 + 1).expect("n / 2 + 1 != 0"),
+        Quorum::All => total,
+        Quorum::N(n) => n.min(total),
+    }
+}
+
+// The newer of two copies of a key, preferring `found` when they are the same version
+fn newest(found: Option<Entry>, stale: Option<Entry>) -> Option<Entry> {
+    match (found, stale) {
+        (Some(found), Some(stale)) if stale.version > found.version => Some(stale),
+        (found, stale) => found.or(stale),
+    }
+}
+
+// When an attempt started at `now` and allowed `attempt` runs out, falling back to
+// the longest timeout a setting may hold should that be past what an Instant can represent
+fn deadline_after(now: Instant, attempt: Duration) -> Instant {
+    now.checked_add(attempt).unwrap_or_else(|| now + Duration::from_secs(MAX_TIMEOUT_SECS))
+}
+
+// The per-peer limits on inbound Kademlia requests set in `config`
+fn inbound_limits(config: &Config) -> throttle::Limits {
+    throttle::Limits {
+        rate: config.inbound_rate,
+        burst: config.inbound_burst,
+        cooldown: config.inbound_cooldown(),
+        disconnect: config.inbound_disconnect,
+    }
+}
+
+// Look a record up in the local store, treating an expired record as missing
+fn live_record<'a>(store: &'a mut MemoryStore, key: &RecordKey) -> Option<Cow<'a, Record>> {
+    store.get(key).filter(|record| !record.is_expired(Instant::now()))
+}
+
+// What `record` says about itself, as a copy of `key` held by `source`; `None` if its signature does not verify
+fn describe(key: String, record: &Record, source: Option<PeerId>) -> Option<RecordDescription> {
+    let (value, version, signer, owned, metadata) = match envelope::open(record.key.as_ref(), &record.value) {
+        Ok(Opened::Signed { value, signer, seq, owned, metadata }) => (value, seq, Some(signer), owned, metadata),
+        Ok(Opened::Unsigned(value)) => (value, 0, None, false, None),
+        Err(e) => {
+            warn!("cannot describe copy of key {}: {}", key, e);
+            return None;
+        }
+    };
+    Some(RecordDescription {
+        key,
+        source,
+        cached: false,
+        version,
+        signer,
+        owned,
+        metadata,
+        expires_in: record.expires.map(|expires| expires.saturating_duration_since(Instant::now())),
+        stored_bytes: value.len(),
+        value_bytes: compression::decompress(value).len(),
+        sealed_bytes: record.value.len(),
+    })
+}
+
+// When the record in `raw` was first written, if its envelope says
+fn created_at(key: &[u8], raw: &[u8]) -> Option<u64> {
+    match envelope::open(key, raw) {
+        Ok(Opened::Signed { metadata, .. }) => metadata.map(|metadata| metadata.created),
+        _ => None,
+    }
+}
+
+// Milliseconds since the Unix epoch, the unit of envelope metadata
+fn unix_millis() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|since| since.as_millis() as u64).unwrap_or(0)
+}
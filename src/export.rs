@@ -0,0 +1,50 @@
+//! The line format used by the `export` and `import` commands.
+//!
+//! Each record is one line of `key<TAB>base64(value)`. Blank lines and lines
+//! starting with `#` are ignored, so exported files can be annotated by hand.
+//! The `putmany` command reads a simpler hand-written form, [`decode_pair`].
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+
+/// Encode one record as a line, without the trailing newline.
+pub fn encode_line(key: &str, value: &[u8]) -> String {
+    format!("{}\t{}", key, STANDARD.encode(value))
+}
+
+/// What a single line of an export file holds.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Line<'a> {
+    Record { key: &'a str, value: Vec<u8> },
+    /// A blank or comment line.
+    Skip,
+    /// Anything else; importers count these rather than failing the whole file.
+    Malformed,
+}
+
+/// Decode one line of an export file.
+pub fn decode_line(line: &str) -> Line<'_> {
+    let line = line.trim_end_matches(['\r', '\n']);
+    if line.trim().is_empty() || line.starts_with('#') {
+        return Line::Skip;
+    }
+    let Some((key, encoded)) = line.split_once('\t') else {
+        return Line::Malformed;
+    };
+    match STANDARD.decode(encoded.trim()) {
+        Ok(value) if !key.is_empty() => Line::Record { key, value },
+        _ => Line::Malformed,
+    }
+}
+
+/// Decode one line of a `putmany` file: a key, whitespace, and the rest of the
+/// line as the value. Blank and `#` lines are skipped as in export files.
+pub fn decode_pair(line: &str) -> Line<'_> {
+    let line = line.trim();
+    if line.is_empty() || line.starts_with('#') {
+        return Line::Skip;
+    }
+    match line.split_once(char::is_whitespace) {
+        Some((key, value)) if !value.trim().is_empty() => Line::Record { key, value: value.trim().as_bytes().to_vec() },
+        _ => Line::Malformed,
+    }
+}
@@ -0,0 +1,405 @@
+//! Syntax, examples and aliases for the interactive commands.
+
+/// Help for one command, as printed by `help <command>`.
+pub struct CommandHelp {
+    pub name: &'static str,
+    /// One line per form the command takes.
+    pub usage: &'static [&'static str],
+    pub summary: &'static str,
+    pub details: &'static str,
+    pub examples: &'static [&'static str],
+}
+
+/// Every command, in the order the usage text lists them.
+pub const COMMANDS: &[CommandHelp] = &[
+    CommandHelp {
+        name: "put",
+        usage: &[
+            "put <key> <value>|@file|- [ttl_secs] [quorum] [--force|--own]",
+            "put <name> A|AAAA|TXT|CNAME <data>|@file|- [ttl_secs] [quorum] [--force|--own]",
+            "put <name> SRV <priority> <weight> <port> <target>|@file|- [ttl_secs] [quorum] [--force|--own]",
+            "put <name> MX <preference> <exchange>|@file|- [ttl_secs] [quorum] [--force|--own]",
+        ],
+        summary: "store a record locally and publish it to the DHT",
+        details: "The value is taken literally, read from a file with @file, or read from the following \
+            lines up to a blank one with -. A record type stores a typed DNS record instead of raw bytes; \
+            SRV and MX data read from a file or stdin may list several records, one per line, which are \
+            resolved lowest priority or preference first. \
+            The quorum is all, majority or a number of peers. A put that would overwrite a newer version \
+            than this node has seen is refused unless --force is given. --own claims the key for this node: \
+            the first claim wins, and from then on nodes that enforce ownership refuse writes by anyone else. \
+            A key of 0x and hex digits, or b64: and base64, is stored under exactly those bytes, as is every \
+            key with --key-encoding hex or base64; get and the other commands take keys the same way.",
+        examples: &[
+            "put example.com hello",
+            "put example.com A 192.0.2.1 3600",
+            "put _sip._tcp.example.com SRV 10 60 5060 sip.example.com",
+            "put example.com MX @mx.txt",
+            "put notes @notes.txt 60 majority",
+        ],
+    },
+    CommandHelp {
+        name: "put-cas",
+        usage: &["put-cas <value>|@file|- [ttl_secs] [quorum]"],
+        summary: "store a value under the SHA-256 hash of its contents",
+        details: "Prints the resulting key, the digest in hex split in two labels under .cas. Anyone can \
+            check a value against such a key, so get refuses copies that do not hash to it, and a put of \
+            any other value under it is rejected.",
+        examples: &["put-cas hello", "put-cas @image.png 86400"],
+    },
+    CommandHelp {
+        name: "get",
+        usage: &["get <key> [quorum]"],
+        summary: "look a record up, locally first and then in the DHT",
+        details: "A local copy is served straight away while it is fresh; an older one is checked against \
+            the DHT first. Values found for keys this node does not store are cached until they expire or \
+            their writer changes them (see --cache-capacity). A quorum above one always asks other peers and \
+            returns the newest version found. A name holding DNS records prints its addresses, preferring A \
+            records, then AAAA, then following a CNAME to the addresses of its target.",
+        examples: &["get example.com", "get example.com majority"],
+    },
+    CommandHelp {
+        name: "resolve",
+        usage: &["resolve <name>"],
+        summary: "look a name up, following CNAME records",
+        details: "Prints every name in the chain and the record it ends on. A name holding addresses \
+            answers with its A records, or its AAAA records if it has no A, before any other record. Loops and chains deeper than \
+            the limit are errors. A name with no record of its own falls back to the nearest wildcard \
+            above it, e.g. *.example.com then *.com for a.example.com, trying at most 8; an exact record \
+            always wins over a wildcard. Store a wildcard with put *.example.com ... With trust_anchor \
+            set, every record along the way must be signed by the key of its zone, see delegate.",
+        examples: &["resolve www.example.com", "resolve anything.example.com"],
+    },
+    CommandHelp {
+        name: "resolve-json",
+        usage: &["resolve-json <name>"],
+        summary: "print every typed record of a name as one JSON document",
+        details: "Prints {\"name\": ..., \"records\": [{\"type\": \"A\", \"data\": \"192.0.2.1\", \"ttl\": 300, \"source\": ...}]}. \
+            A name holds one record set under its own key; records of other types can be stored beside it \
+            under <name>|<type>, e.g. put www.example.com|AAAA AAAA 2001:db8::1. All of these keys are looked up \
+            at once, those held locally answering straight away, and their records merged. The TTL is the \
+            time the record has left, or 300 seconds if it never expires; source is the peer it came from, \
+            null for this node's copy. A key that is missing or times out adds nothing, and records under \
+            <name>|<type> that are not of that type are left out. CNAMEs and wildcards are not followed, see \
+            resolve. get, resolve and the DNS frontend (--dns-addr) only read the name's own key, so records stored \
+            under <name>|<type> are seen by resolve-json alone.",
+        examples: &["resolve-json www.example.com"],
+    },
+    CommandHelp {
+        name: "delegate",
+        usage: &["delegate <parent> <child> <peer_id>"],
+        summary: "hand a child zone to the key that signs it",
+        details: "Stores a record under _delegation.<child>, signed by this node and claimed so only it can \
+            change it, naming the peer id whose key signs the child zone. Run it on the node holding the \
+            parent zone's key. With trust_anchor set, resolve follows these delegations down from the anchor \
+            and only accepts a record signed by the key of the zone it is in; the records of a zone are put by \
+            the node with that zone's key.",
+        examples: &["delegate example.com eu.example.com 12D3KooW..."],
+    },
+    CommandHelp {
+        name: "get-all",
+        usage: &["get-all <key>"],
+        summary: "list every distinct value the DHT holds for a key",
+        details: "Waits for the lookup to finish rather than stopping at the first copy, so conflicting \
+            writes show up together with their versions.",
+        examples: &["get-all example.com"],
+    },
+    CommandHelp {
+        name: "watch",
+        usage: &["watch <key>"],
+        summary: "print the value of a key whenever it changes",
+        details: "The key is looked up periodically (see --watch-interval) and reported once straight \
+            away, even if it is missing.",
+        examples: &["watch example.com"],
+    },
+    CommandHelp {
+        name: "unwatch",
+        usage: &["unwatch <key>"],
+        summary: "stop watching a key",
+        details: "Stops the periodic lookups started by watch.",
+        examples: &["unwatch example.com"],
+    },
+    CommandHelp {
+        name: "delete",
+        usage: &["delete <key>"],
+        summary: "remove a record and tell peers to drop their copies",
+        details: "Stops republishing the record. Peers that miss the notice keep their copy until its TTL runs out.",
+        examples: &["delete example.com"],
+    },
+    CommandHelp {
+        name: "dry",
+        usage: &["dry put <key> ...", "dry delete <key>"],
+        summary: "show what a put or delete would do without doing it",
+        details: "Normalizes the key and shows the record key it maps to in hex, checks the value against the \
+            size limit, the key's owner and the room left in the local store, and seals it in a signed \
+            envelope that is verified and discarded. Nothing is stored or sent; the DHT read a real put \
+            starts with is skipped too, so a conflict with a newer version elsewhere is not detected. \
+            --dry-run makes every put and delete a dry one.",
+        examples: &["dry put Example.COM. A 192.0.2.1", "dry delete example.com"],
+    },
+    CommandHelp {
+        name: "describe",
+        usage: &["describe <key> [dht]"],
+        summary: "show a record's version, signer, times, TTL and size",
+        details: "Describes the local copy, or with dht the newest valid copy a lookup finds, naming the peer \
+            it came from. A key this node does not store is described from the copy an earlier get cached, \
+            with the peer it was last fetched from. The creation and update times and the TTL are signed by the writer; a rewrite \
+            keeps the creation time of the version it replaces. Records written by older nodes have no \
+            such metadata.",
+        examples: &["describe example.com", "describe example.com dht"],
+    },
+    CommandHelp {
+        name: "list",
+        usage: &["list"],
+        summary: "list the records stored locally",
+        details: "Shows each key with the size of its stored value, and whether it is pinned. With \
+            --key-hashing sha256 the keys are hashes, shown in hex.",
+        examples: &["list"],
+    },
+    CommandHelp {
+        name: "pin",
+        usage: &["pin <key>"],
+        summary: "keep a locally stored record alive in the DHT indefinitely",
+        details: "A pinned record is republished with a fresh TTL once less than half of it is left, so it \
+            never lapses while this node runs. Pins are saved next to the records and survive restarts; \
+            deleting the key unpins it.",
+        examples: &["pin example.com"],
+    },
+    CommandHelp {
+        name: "unpin",
+        usage: &["unpin <key>"],
+        summary: "stop keeping a record alive",
+        details: "The record stays stored until its TTL runs out.",
+        examples: &["unpin example.com"],
+    },
+    CommandHelp {
+        name: "expired",
+        usage: &["expired"],
+        summary: "list expired records not yet removed",
+        details: "Expired records are never served and are reclaimed by a periodic sweep.",
+        examples: &["expired"],
+    },
+    CommandHelp {
+        name: "export",
+        usage: &["export <file>"],
+        summary: "write the local records to a file",
+        details: "One tab-separated key and base64 value per line, in the format import reads. Records stored \
+            under hashed keys are skipped, as their names cannot be recovered.",
+        examples: &["export records.tsv"],
+    },
+    CommandHelp {
+        name: "import",
+        usage: &["import <file>"],
+        summary: "put every record in a file written by export",
+        details: "Malformed lines are skipped and reported. Records stored locally but not yet replicated \
+            are republished later.",
+        examples: &["import records.tsv"],
+    },
+    CommandHelp {
+        name: "import-zone",
+        usage: &["import-zone <file> [origin]"],
+        summary: "put the records of an RFC 1035 zone file",
+        details: "A, AAAA, TXT, CNAME, SRV and MX records are stored under their fully-qualified names, all \
+            the records of a name together as one value; other types such as SOA and NS are skipped and \
+            counted. Names are relative to the origin given, unless the file sets $ORIGIN. Zone TTLs are \
+            not carried over: the records live for the record TTL like any put.",
+        examples: &["import-zone example.com.zone", "import-zone db.example example.com"],
+    },
+    CommandHelp {
+        name: "putmany",
+        usage: &["putmany <file>"],
+        summary: "put every key and value listed in a file",
+        details: "Each line is a key, whitespace and the value; blank and # lines are skipped. Up to \
+            --put-concurrency puts (16 by default) run at once, so one slow put holds up only its own slot: \
+            500 records between two local nodes take a fraction of a second, against seconds or more one \
+            at a time. Progress is printed as the puts complete.",
+        examples: &["putmany records.txt"],
+    },
+    CommandHelp {
+        name: "provide",
+        usage: &["provide <key>"],
+        summary: "announce this node as a provider of a key",
+        details: "Other nodes find it with providers.",
+        examples: &["provide example.com"],
+    },
+    CommandHelp {
+        name: "providers",
+        usage: &["providers <key>"],
+        summary: "list the peers providing a key",
+        details: "Queries the DHT for provider records.",
+        examples: &["providers example.com"],
+    },
+    CommandHelp {
+        name: "find-node",
+        usage: &["find-node <peer_id>|<key>"],
+        summary: "list the peers the DHT finds closest to a peer id or key",
+        details: "Runs the Kademlia lookup a get or dial starts with and prints the peers it ends on, nearest \
+            first, with the bucket of each one's XOR distance to the target. Shows why a get did or did not \
+            reach the peers holding a record; the peers asked and answered stand in for a hop count.",
+        examples: &["find-node 12D3KooW...", "find-node example.com"],
+    },
+    CommandHelp {
+        name: "benchmark",
+        usage: &["benchmark <n> [value_bytes]"],
+        summary: "time puts and gets of n random keys",
+        details: "Puts n values of value_bytes (64 by default) under fresh keys with the default quorum, then \
+            gets each back from other peers, ignoring this node's own copies, keeping put_concurrency operations \
+            in flight. Prints the min, median, p95 and max latency, success rate and throughput of both; run it \
+            again with other settings to compare them. The keys are left in the DHT to expire.",
+        examples: &["benchmark 100", "benchmark 500 1024"],
+    },
+    CommandHelp {
+        name: "bootstrap",
+        usage: &["bootstrap <multiaddr> <peer_id>"],
+        summary: "add a known peer and join the DHT through it",
+        details: "The peer is added to the routing table before the bootstrap query starts.",
+        examples: &["bootstrap /ip4/192.0.2.1/tcp/4001 12D3KooW..."],
+    },
+    CommandHelp {
+        name: "dial",
+        usage: &["dial <multiaddr>"],
+        summary: "open a connection to an address",
+        details: "Returns once the dial has started; peers shows whether it connected.",
+        examples: &["dial /ip4/192.0.2.1/tcp/4001/p2p/12D3KooW..."],
+    },
+    CommandHelp {
+        name: "ping",
+        usage: &["ping <peer_id>"],
+        summary: "measure the round-trip time to a peer",
+        details: "Dials the peer first if it is not connected. On an open connection this waits for the next \
+            scheduled ping, sent every 15 seconds.",
+        examples: &["ping 12D3KooW..."],
+    },
+    CommandHelp {
+        name: "peers",
+        usage: &["peers"],
+        summary: "list connected and routing table peers",
+        details: "Connected peers show whether they are also in the routing table, and their average \
+            round-trip time once they have answered a ping. Each open connection shows how long it has \
+            been up; idle ones close after --idle-timeout seconds.",
+        examples: &["peers"],
+    },
+    CommandHelp {
+        name: "security",
+        usage: &["security"],
+        summary: "show how each open connection is encrypted and multiplexed",
+        details: "TCP, WebSocket and relayed connections use noise or TLS, as --security allows, and yamux, \
+            over the pre-shared-key handshake in a private network; QUIC uses its built-in TLS 1.3. A connection that negotiated \
+            no encryption is closed as soon as it is established.",
+        examples: &["security"],
+    },
+    CommandHelp {
+        name: "buckets",
+        usage: &["buckets"],
+        summary: "show the routing table bucket by bucket",
+        details: "Only non-empty buckets are listed, nearest first.",
+        examples: &["buckets"],
+    },
+    CommandHelp {
+        name: "addrs",
+        usage: &["addrs [all]"],
+        summary: "show the addresses other nodes can dial",
+        details: "Lists listen addresses and any external addresses confirmed by AutoNAT. With --public-only, \
+            loopback, link-local and private addresses are left out unless all is given.",
+        examples: &["addrs", "addrs all"],
+    },
+    CommandHelp {
+        name: "stats",
+        usage: &["stats"],
+        summary: "show peer, record and query counts",
+        details: "Query counts cover every DHT get and put since startup.",
+        examples: &["stats"],
+    },
+    CommandHelp {
+        name: "config",
+        usage: &["config get [setting]", "config set <setting> <value>"],
+        summary: "show or change settings while the node runs",
+        details: "Without a setting, get lists the ones set can change: log_level, default_quorum, \
+            get_timeout_secs, get_retries, inbound_rate, inbound_burst, inbound_cooldown_secs, command_rate \
+            and command_burst. Changes apply to later requests and last until the node stops; other \
+            settings need a restart. none unsets an optional setting.",
+        examples: &["config get", "config set log_level debug", "config set default_quorum majority", "config get listen"],
+    },
+    CommandHelp {
+        name: "ready",
+        usage: &["ready"],
+        summary: "report whether the node can serve lookups",
+        details: "Ready once the node is listening, has a connected peer and a non-empty routing table.",
+        examples: &["ready"],
+    },
+    CommandHelp {
+        name: "version",
+        usage: &["version"],
+        summary: "show the version, build and peer id of this node",
+        details: "The same details are logged at startup; include them in bug reports.",
+        examples: &["version"],
+    },
+    CommandHelp {
+        name: "help",
+        usage: &["help [command]"],
+        summary: "list the commands, or describe one",
+        details: "Aliases are accepted in place of command names.",
+        examples: &["help", "help put"],
+    },
+    CommandHelp {
+        name: "exit",
+        usage: &["exit"],
+        summary: "save the local store and stop the node",
+        details: "Ctrl-C does the same, as does the end of piped input unless --daemon is given; commands \
+            still running then are finished first.",
+        examples: &["exit"],
+    },
+];
+
+/// Short names accepted in place of a command.
+pub const ALIASES: &[(&str, &str)] = &[("p", "put"), ("g", "get"), ("ls", "list"), ("rm", "delete"), ("quit", "exit")];
+
+/// The command an alias stands for, or `word` itself.
+pub fn canonical(word: &str) -> &str {
+    ALIASES.iter().find(|(alias, _)| *alias == word).map_or(word, |(_, name)| name)
+}
+
+/// Help for a command, looked up by name or alias.
+pub fn find(word: &str) -> Option<&'static CommandHelp> {
+    let name = canonical(word);
+    COMMANDS.iter().find(|command| command.name == name)
+}
+
+/// The aliases of a command.
+pub fn aliases(name: &str) -> impl Iterator<Item = &'static str> + '_ {
+    ALIASES.iter().filter(move |(_, command)| *command == name).map(|(alias, _)| *alias)
+}
+
+/// The command closest to an unknown word, if any is within a couple of edits.
+pub fn suggest(word: &str) -> Option<&'static str> {
+    // Short words are a single edit from many commands, so allow fewer edits for them
+    let max_distance = if word.chars().count() <= 3 { 1 } else { 2 };
+    COMMANDS.iter()
+        .map(|command| (edit_distance(word, command.name), command.name))
+        .filter(|(distance, _)| *distance <= max_distance)
+        .min_by_key(|(distance, _)| *distance)
+        .map(|(_, name)| name)
+}
+
+// Edits needed to turn `a` into `b`, counting an insertion, deletion, substitution
+// or swap of two neighbouring characters as one each
+fn edit_distance(a: &str, b: &str) -> usize {
+    let (a, b): (Vec<char>, Vec<char>) = (a.chars().collect(), b.chars().collect());
+    // distances[i][j] is the distance between the first i characters of `a` and the first j of `b`
+    let mut distances = vec![vec![0; b.len() + 1]; a.len() + 1];
+    distances[0] = (0..=b.len()).collect();
+    for (i, row) in distances.iter_mut().enumerate() {
+        row[0] = i;
+    }
+    for i in 1..=a.len() {
+        for j in 1..=b.len() {
+            let substitution = distances[i - 1][j - 1] + usize::from(a[i - 1] != b[j - 1]);
+            let mut distance = substitution.min(distances[i - 1][j] + 1).min(distances[i][j - 1] + 1);
+            if i > 1 && j > 1 && a[i - 1] == b[j - 2] && a[i - 2] == b[j - 1] {
+                distance = distance.min(distances[i - 2][j - 2] + 1);
+            }
+            distances[i][j] = distance;
+        }
+    }
+    distances[a.len()][b.len()]
+}
@@ -0,0 +1,82 @@
+//! Optional HTTP API for driving the node from scripts and other services.
+
+use crate::{DhtError, DhtNode, Health};
+use axum::{
+    body::Bytes,
+    extract::{Path, State},
+    http::StatusCode,
+    routing::{get, put},
+    Router,
+};
+use std::net::SocketAddr;
+
+/// Serve the API on `addr` until the process exits.
+pub async fn serve(addr: SocketAddr, node: DhtNode) -> std::io::Result<()> {
+    let app = Router::new()
+        .route("/records/{key}", put(put_record).get(get_record).delete(delete_record))
+        .route("/healthz", get(healthz))
+        .route("/readyz", get(readyz))
+        .with_state(node);
+
+    let listener = tokio::net::TcpListener::bind(addr).await?;
+    tracing::info!("HTTP API listening on http://{}", listener.local_addr()?);
+    axum::serve(listener, app).await
+}
+
+async fn put_record(State(node): State<DhtNode>, Path(key): Path<String>, body: Bytes) -> (StatusCode, String) {
+    match node.put(&key, body.to_vec(), node.default_quorum()).await {
+        Ok(()) => (StatusCode::OK, format!("stored {}\n", key)),
+        Err(e @ (DhtError::InvalidKey(_) | DhtError::ContentMismatch { .. })) => (StatusCode::BAD_REQUEST, format!("{}\n", e)),
+        Err(e @ DhtError::Conflict { .. }) => (StatusCode::CONFLICT, format!("{}\n", e)),
+        Err(e @ (DhtError::Unauthorized { .. } | DhtError::ReadOnly | DhtError::Rejected { .. })) => (StatusCode::FORBIDDEN, format!("{}\n", e)),
+        Err(e @ DhtError::ValueTooLarge { .. }) => (StatusCode::PAYLOAD_TOO_LARGE, format!("{}\n", e)),
+        Err(e @ (DhtError::StoreFull { .. } | DhtError::Store(_))) => (StatusCode::INSUFFICIENT_STORAGE, format!("{}\n", e)),
+        Err(DhtError::Timeout) | Err(DhtError::NodeStopped) => (StatusCode::GATEWAY_TIMEOUT, "DHT publish timed out\n".to_string()),
+        Err(e) => (StatusCode::BAD_GATEWAY, format!("stored locally, DHT publish failed: {}\n", e)),
+    }
+}
+
+async fn get_record(State(node): State<DhtNode>, Path(key): Path<String>) -> (StatusCode, Vec<u8>) {
+    match node.get(&key).await {
+        Ok(Some(value)) => (StatusCode::OK, value),
+        Ok(None) => (StatusCode::NOT_FOUND, b"not found\n".to_vec()),
+        Err(e @ DhtError::InvalidKey(_)) => (StatusCode::BAD_REQUEST, format!("{}\n", e).into_bytes()),
+        Err(e @ DhtError::ContentMismatch { .. }) => (StatusCode::BAD_GATEWAY, format!("{}\n", e).into_bytes()),
+        Err(_) => (StatusCode::GATEWAY_TIMEOUT, b"DHT lookup timed out\n".to_vec()),
+    }
+}
+
+async fn delete_record(State(node): State<DhtNode>, Path(key): Path<String>) -> StatusCode {
+    match node.delete(&key).await {
+        Ok(true) => StatusCode::NO_CONTENT,
+        Ok(false) => StatusCode::NOT_FOUND,
+        Err(DhtError::InvalidKey(_)) => StatusCode::BAD_REQUEST,
+        Err(DhtError::ReadOnly) => StatusCode::FORBIDDEN,
+        Err(_) => StatusCode::GATEWAY_TIMEOUT,
+    }
+}
+
+// Liveness: the node is listening, whether or not it has found any peers yet
+async fn healthz(State(node): State<DhtNode>) -> (StatusCode, String) {
+    health_response(&node, Health::healthy).await
+}
+
+// Readiness: the node also has connected peers to send lookups to
+async fn readyz(State(node): State<DhtNode>) -> (StatusCode, String) {
+    health_response(&node, Health::ready).await
+}
+
+async fn health_response(node: &DhtNode, check: fn(&Health) -> bool) -> (StatusCode, String) {
+    match node.health().await {
+        Ok(health) => {
+            let status = if check(&health) { StatusCode::OK } else { StatusCode::SERVICE_UNAVAILABLE };
+            let body = format!(
+                "{}\nlistening: {}\nconnected_peers: {}\nrouting_table_peers: {}\nuptime_secs: {}\n",
+                if status == StatusCode::OK { "ok" } else { "unavailable" },
+                health.listening, health.connected_peers, health.routing_table_peers, health.uptime.as_secs(),
+            );
+            (status, body)
+        }
+        Err(e) => (StatusCode::SERVICE_UNAVAILABLE, format!("unavailable\n{}\n", e)),
+    }
+}
@@ -0,0 +1,74 @@
+//! Gossipsub messages telling peers that a record changed or was deleted.
+//!
+//! Peers holding an older copy of the key would otherwise serve it until its
+//! TTL runs out. Each message names the key, a version that only grows for a
+//! given writer, and the new sealed value (absent for a delete), so receivers
+//! can replace or drop their copy without another lookup.
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+use serde::{Deserialize, Serialize};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// The gossipsub topic every node publishes and subscribes to.
+pub const TOPIC: &str = "dht-invalidations";
+
+/// A change to one key, as published on [`TOPIC`].
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Invalidation {
+    key: String,
+    /// Whether `key` is hex encoded, for record keys that are not UTF-8 such as hashed names.
+    #[serde(default)]
+    hex: bool,
+    /// Milliseconds since the Unix epoch when the writer made the change.
+    pub version: u64,
+    /// The new record value, still sealed in its envelope, base64 encoded.
+    value: Option<String>,
+    /// Seconds left before the new record expires, which receivers cap at their
+    /// own record TTL; when absent, they keep the expiry of the copy they hold.
+    pub ttl_secs: Option<u64>,
+}
+
+impl Invalidation {
+    /// A change made now: the new sealed value, or `None` for a delete.
+    pub fn new(key: &[u8], value: Option<&[u8]>, ttl: Option<Duration>) -> Self {
+        let version = SystemTime::now().duration_since(UNIX_EPOCH).map(|since| since.as_millis() as u64).unwrap_or(0);
+        let (key, hex) = match std::str::from_utf8(key) {
+            Ok(key) => (key.to_string(), false),
+            Err(_) => (key.iter().map(|b| format!("{:02x}", b)).collect(), true),
+        };
+        Invalidation {
+            key,
+            hex,
+            version,
+            value: value.map(|value| STANDARD.encode(value)),
+            ttl_secs: ttl.map(|ttl| ttl.as_secs()),
+        }
+    }
+
+    /// The record key the change applies to, or `None` if its hex encoding is malformed.
+    pub fn record_key(&self) -> Option<Vec<u8>> {
+        if !self.hex {
+            return Some(self.key.as_bytes().to_vec());
+        }
+        if !self.key.len().is_multiple_of(2) {
+            return None;
+        }
+        (0..self.key.len())
+            .step_by(2)
+            .map(|i| self.key.get(i..i + 2).and_then(|byte| u8::from_str_radix(byte, 16).ok()))
+            .collect()
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        serde_json::to_vec(self).expect("invalidation serialization does not fail")
+    }
+
+    pub fn decode(data: &[u8]) -> Option<Self> {
+        serde_json::from_slice(data).ok()
+    }
+
+    /// The new sealed value, `Ok(None)` for a delete, or `Err` if it is not valid base64.
+    pub fn value(&self) -> Result<Option<Vec<u8>>, base64::DecodeError> {
+        self.value.as_deref().map(|value| STANDARD.decode(value)).transpose()
+    }
+}
@@ -0,0 +1,142 @@
+//! How record names map to the Kademlia keys they are stored under.
+//!
+//! By default a name is used as its key byte for byte, so the key is readable
+//! by anyone routing or storing it. Hashing the name with SHA-256 hides it and
+//! spreads keys evenly over the key space regardless of name length, but a
+//! node hashing keys cannot find records written by one using raw keys, and
+//! the other way round: every node of a network must use the same setting.
+//!
+//! Content-addressed names, as written by `put-cas`, are derived from the value
+//! instead: its SHA-256 digest in hex, split in two labels to keep within the
+//! DNS label limit, under `.cas`. Such a name can only ever hold that value.
+//!
+//! A binary key, such as a hash produced elsewhere, is given as `0x` and hex
+//! or `b64:` and base64, or in either encoding throughout with
+//! [`KeyEncoding`]. It is carried as `0x` and lowercase hex, the way
+//! [`display_key`](crate::display_key) prints keys that are not UTF-8, and
+//! used as the record key exactly, without hashing.
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+use libp2p::kad::RecordKey;
+use sha2::{Digest, Sha256};
+
+// The last label of every content-addressed name
+const CONTENT_SUFFIX: &str = ".cas";
+// Prefixes marking a key given as hex or base64 bytes rather than a name
+const HEX_PREFIX: &str = "0x";
+const BASE64_PREFIX: &str = "b64:";
+
+/// The transform applied to a name before it is used as a record key.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum KeyHashing {
+    /// The UTF-8 bytes of the name.
+    #[default]
+    Raw,
+    /// The SHA-256 digest of the name.
+    Sha256,
+}
+
+impl KeyHashing {
+    /// The record key `name` is stored under; a binary key is its own bytes.
+    pub fn record_key(&self, name: &str) -> RecordKey {
+        if let Some(bytes) = binary_key(name) {
+            return RecordKey::new(&bytes);
+        }
+        match self {
+            KeyHashing::Raw => RecordKey::new(&name.as_bytes()),
+            KeyHashing::Sha256 => RecordKey::new(&Sha256::digest(name.as_bytes()).as_slice()),
+        }
+    }
+}
+
+/// How keys given to commands are written.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum KeyEncoding {
+    /// Names, except that ones starting `0x` followed by hex, or `b64:`,
+    /// are binary keys.
+    #[default]
+    Utf8,
+    /// Hex bytes, with or without `0x`.
+    Hex,
+    /// Standard base64 bytes, with or without `b64:`.
+    Base64,
+}
+
+impl KeyEncoding {
+    /// The bytes of `key` if it is a binary key, or `None` if it is a name.
+    pub fn decode(&self, key: &str) -> Result<Option<Vec<u8>>, String> {
+        let bytes = match self {
+            KeyEncoding::Utf8 => match (key.strip_prefix(HEX_PREFIX), key.strip_prefix(BASE64_PREFIX)) {
+                (Some(hex), _) => match decode_hex(hex) {
+                    Some(bytes) => bytes,
+                    // Like 0xygen.example.com, a name that merely starts the same way
+                    None => return Ok(None),
+                },
+                (_, Some(base64)) => decode_base64(base64)?,
+                _ => return Ok(None),
+            },
+            KeyEncoding::Hex => {
+                let hex = key.strip_prefix(HEX_PREFIX).unwrap_or(key);
+                decode_hex(hex).ok_or_else(|| format!("'{}' is not a hex key", key))?
+            }
+            KeyEncoding::Base64 => decode_base64(key.strip_prefix(BASE64_PREFIX).unwrap_or(key))?,
+        };
+        if bytes.is_empty() {
+            return Err("key must not be empty".to_string());
+        }
+        Ok(Some(bytes))
+    }
+}
+
+/// The form a binary key is carried in, e.g. `0xdeadbeef`.
+pub fn hex_key(bytes: &[u8]) -> String {
+    let hex: String = bytes.iter().map(|b| format!("{:02x}", b)).collect();
+    format!("{}{}", HEX_PREFIX, hex)
+}
+
+/// The bytes of a key in the form [`hex_key`] gives, or `None` for a name.
+pub fn binary_key(key: &str) -> Option<Vec<u8>> {
+    decode_hex(key.strip_prefix(HEX_PREFIX)?).filter(|bytes| !bytes.is_empty())
+}
+
+// Pairs of hex digits in either case, as bytes
+fn decode_hex(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) || !hex.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return None;
+    }
+    (0..hex.len()).step_by(2).map(|i| u8::from_str_radix(&hex[i..i + 2], 16).ok()).collect()
+}
+
+fn decode_base64(base64: &str) -> Result<Vec<u8>, String> {
+    STANDARD.decode(base64).map_err(|e| format!("'{}' is not a base64 key: {}", base64, e))
+}
+
+/// The content-addressed name of `value`, e.g. `2cf24dba5fb0a30e26e83b2ac5b9e29e.1b161e5c1fa7425e73043362938b9824.cas`.
+pub fn content_name(value: &[u8]) -> String {
+    digest_name(&Sha256::digest(value).into())
+}
+
+/// The content-addressed name for a SHA-256 digest, the inverse of [`content_digest`].
+pub fn digest_name(digest: &[u8; 32]) -> String {
+    let hex: String = digest.iter().map(|b| format!("{:02x}", b)).collect();
+    format!("{}.{}{}", &hex[..32], &hex[32..], CONTENT_SUFFIX)
+}
+
+/// The SHA-256 digest a content-addressed name stands for, or `None` for any other name.
+pub fn content_digest(name: &str) -> Option<[u8; 32]> {
+    let (first, second) = name.strip_suffix(CONTENT_SUFFIX)?.split_once('.')?;
+    if first.len() != 32 || second.len() != 32 {
+        return None;
+    }
+    let hex = [first, second].concat();
+    let mut digest = [0; 32];
+    for (i, byte) in digest.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(hex.get(2 * i..2 * i + 2)?, 16).ok()?;
+    }
+    Some(digest)
+}
+
+/// Whether `value` hashes to `digest`; without a digest every value matches.
+pub fn matches_content(digest: Option<&[u8; 32]>, value: &[u8]) -> bool {
+    digest.is_none_or(|digest| Sha256::digest(value).as_slice() == digest)
+}
@@ -0,0 +1,62 @@
+//! A minimal DHT-based DNS replacement in Rust using libp2p.
+//!
+//! [`DhtNode::new`] builds a node from a [`Config`] and returns a handle
+//! alongside the [`EventLoop`] that owns the swarm. Spawn the event loop and
+//! use the handle (or any clone of it) to put and get records:
+//!
+//! ```no_run
+//! # async fn example() -> Result<(), dht::DhtError> {
+//! use dht::{Config, DhtNode, Quorum};
+//!
+//! let (node, event_loop) = DhtNode::new(&Config::default()).await?;
+//! tokio::spawn(event_loop.run());
+//!
+//! node.put("example.com", b"hello".to_vec(), Quorum::One).await?;
+//! let value = node.get("example.com").await?;
+//! # Ok(())
+//! # }
+//! ```
+
+pub mod benchmark;
+pub mod config;
+pub mod control;
+pub mod delegation;
+pub mod dns;
+pub mod dns_record;
+pub mod export;
+pub mod help;
+pub mod http;
+pub mod keys;
+pub mod metrics;
+pub mod rate_limit;
+pub mod validator;
+pub mod version;
+pub mod zone;
+
+mod cache;
+mod compression;
+mod envelope;
+mod error;
+mod event_log;
+mod event_loop;
+mod invalidation;
+mod node;
+mod persist;
+mod seed_list;
+mod throttle;
+mod transport;
+
+pub use config::Config;
+pub use envelope::Metadata;
+pub use error::DhtError;
+pub use event_loop::EventLoop;
+pub use libp2p::kad::Quorum;
+pub use node::{BucketInfo, ClosestPeers, ConnectionSecurity, DhtNode, DryRun, Entry, Health, NetworkStatus, NodeAddrs, NodeStats, PeerInfo, Reachability, RecordDescription, RecordEvent, RecordSummary};
+
+/// Render a record key as UTF-8, falling back to hex for binary keys.
+pub fn display_key(key: &[u8]) -> String {
+    match std::str::from_utf8(key) {
+        Ok(key_string) => key_string.to_string(),
+        Err(_) => format!("0x{}", key.iter().map(|b| format!("{:02x}", b)).collect::<String>()),
+    }
+}
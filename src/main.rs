@@ -1,163 +1,1705 @@
-//! A minimal DHT-based DNS replacement in Rust using libp2p.
+//! Command-line front end for a DHT node.
 
-use tokio::{io::{self, AsyncBufReadExt}, sync::mpsc};
+use clap::{Parser, Subcommand};
+use dht::benchmark::{self, Benchmark, Phase};
+use dht::config::{self, parse_quorum, take_switch, ConfigArgs, Output, RUNTIME_SETTINGS};
+use dht::control::{self, ControlSocket};
+use dht::delegation;
+use dht::dns_record::{self, DnsRecord, Natural};
+use dht::export::{self, Line};
+use dht::help;
+use dht::keys;
+use dht::rate_limit::TokenBucket;
+use dht::version;
+use dht::zone;
+use dht::{display_key, dns, http, metrics, Config, DhtError, DhtNode, DryRun, Entry, NetworkStatus, Quorum, RecordDescription};
 use futures::StreamExt;
-use libp2p::{
-    identity, kad::{record::store::{MemoryStore, RecordStore}, Kademlia, KademliaConfig, Quorum, Record, RecordKey},
-    swarm::{Swarm, SwarmEvent},
-    tcp, noise, yamux, Transport,
-    PeerId,
-};
+use libp2p::{Multiaddr, PeerId};
+use rustyline::error::ReadlineError;
+use rustyline::DefaultEditor;
+use serde_json::{json, Value};
+use std::collections::{BTreeMap, HashMap};
 use std::error::Error;
-use std::time::Duration;
+use std::io::{self, BufRead, IsTerminal};
+use std::net::IpAddr;
+use std::path::Path;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::sync::mpsc;
+use tokio::task::JoinSet;
+use tracing::{error, info};
+use std::sync::OnceLock;
+use tracing_subscriber::{layer::SubscriberExt, reload, util::SubscriberInitExt, EnvFilter, Registry};
 
-#[tokio::main]
-async fn main() -> Result<(), Box<dyn Error>> {
-    // Create a random PeerId
-    let local_key = identity::Keypair::generate_ed25519();
-    let local_peer_id = PeerId::from(local_key.public());
-    println!("Local peer id: {:?}", local_peer_id);
-
-    // Create a Kademlia behavior
-    let store = MemoryStore::new(local_peer_id);
-    let mut kad_config = KademliaConfig::default();
-    kad_config.set_query_timeout(Duration::from_secs(10));
-    let kademlia = Kademlia::with_config(local_peer_id, store, kad_config);
-
-    // Create a Swarm using SwarmBuilder
-    let mut swarm = libp2p::SwarmBuilder::with_new_identity()
-        .with_tokio()
-        .with_tcp(tcp::Config::default(), noise::Config::new, yamux::Config::default)
-        .expect("Failed to create TCP transport")
-        .with_behaviour(|_| kademlia)
-        .expect("Failed to create behavior")
-        .build();
-
-    // Listen on all interfaces and a random port
-    swarm.listen_on("/ip4/0.0.0.0/tcp/0".parse()?)?;
+// Records a batch put completes between progress reports
+const PROGRESS_INTERVAL: usize = 1000;
+
+// Swaps the log filter when `config set log_level` changes it
+static LOG_FILTER: OnceLock<reload::Handle<EnvFilter, Registry>> = OnceLock::new();
+
+tokio::task_local! {
+    // Where the output of a command from the control socket goes, back to its connection
+    static REPLY: mpsc::UnboundedSender<String>;
+}
+
+// Print a line of command output, or send it back to the control socket connection the command came from
+macro_rules! outln {
+    ($($arg:tt)*) => {
+        respond(REPLY.try_with(Clone::clone).ok().as_ref(), format!($($arg)*))
+    };
+}
+
+// A command read from stdin or the control socket, with the value that followed
+// it for `put <key> -` and, from the socket, where its output goes
+struct Input {
+    line: String,
+    value: Option<Vec<u8>>,
+    reply: Option<mpsc::UnboundedSender<String>>,
+}
+
+/// Run a DHT node, carry out an optional command, then read commands from stdin until it ends.
+#[derive(Parser)]
+#[command(name = "DHT", version, after_help = "Any other command typed at the prompt can be given too, e.g. `DHT stats`; \
+    type `help` there to list them. Options go before such a command.")]
+struct Cli {
+    #[command(flatten)]
+    config: ConfigArgs,
+    /// Print a config file holding every default, then exit
+    #[arg(long)]
+    print_default_config: bool,
+    /// Only show what each put and delete would do, storing and sending nothing
+    #[arg(long, global = true)]
+    dry_run: bool,
+    /// Keep serving once stdin ends, instead of exiting when piped input runs out
+    #[arg(long)]
+    daemon: bool,
+    #[command(subcommand)]
+    command: Option<CliCommand>,
+}
+
+#[derive(Subcommand)]
+enum CliCommand {
+    /// Store a record locally and publish it to the DHT
+    #[command(visible_alias = "p")]
+    Put {
+        key: String,
+        /// The value, @file or - for stdin; or a record type (A, AAAA, TXT, CNAME, SRV, MX) and its data.
+        /// Optionally followed by ttl_secs and a quorum
+        #[arg(required = true, num_args = 1..=7, value_name = "VALUE")]
+        value: Vec<String>,
+        /// Overwrite a newer version written elsewhere
+        #[arg(long, conflicts_with = "own")]
+        force: bool,
+        /// Claim the key for this node; the first claim wins
+        #[arg(long)]
+        own: bool,
+    },
+    /// Look a record up, locally first and then in the DHT
+    #[command(visible_alias = "g")]
+    Get {
+        key: String,
+        /// all, majority or a number of peers
+        #[arg(value_parser = quorum_arg)]
+        quorum: Option<String>,
+    },
+    /// Remove a record and tell peers to drop their copies
+    #[command(visible_alias = "rm")]
+    Delete { key: String },
+    /// Add a known peer and join the DHT through it
+    Bootstrap { addr: Multiaddr, peer_id: PeerId },
+    /// Only run the node and read commands from stdin, as with no command, and keep serving once stdin ends
+    Daemon,
+    #[command(external_subcommand)]
+    Other(Vec<String>),
+}
 
+impl CliCommand {
+    // The command as it would be typed at the prompt, so both take the same path; `None` for daemon mode
+    fn into_words(self) -> Option<Vec<String>> {
+        let words = match self {
+            CliCommand::Put { key, value, force, own } => {
+                let mut words = vec!["put".to_string(), key];
+                words.extend(value);
+                words.extend(force.then(|| "--force".to_string()));
+                words.extend(own.then(|| "--own".to_string()));
+                words
+            }
+            CliCommand::Get { key, quorum } => std::iter::once("get".to_string()).chain([key]).chain(quorum).collect(),
+            CliCommand::Delete { key } => vec!["delete".to_string(), key],
+            CliCommand::Bootstrap { addr, peer_id } => vec!["bootstrap".to_string(), addr.to_string(), peer_id.to_string()],
+            CliCommand::Daemon => return None,
+            CliCommand::Other(words) => words,
+        };
+        Some(words)
+    }
+}
+
+// Check a quorum argument while keeping it as text
+fn quorum_arg(quorum: &str) -> Result<String, String> {
+    parse_quorum(quorum).map(|_| quorum.to_string())
+}
+
+#[tokio::main]
+async fn main() -> Result<(), DhtError> {
     // Process initial command line arguments
-    let args: Vec<String> = std::env::args().collect();
-    
-    // Print usage if no arguments provided
-    if args.len() <= 1 {
-        println!("Usage:");
-        println!("  put <key> <value>");
-        println!("  get <key>");
-        println!("  exit");
-        println!("DHT node is running and will continue processing requests...");
-    } else {
-        // Process the initial command if provided
-        process_command(&args, &mut swarm).await?;
+    let cli = Cli::parse();
+    if cli.print_default_config {
+        print!("{}", config::DEFAULT_CONFIG_TEMPLATE);
+        return Ok(());
     }
-    
+    let config = Config::from_args(&cli.config)?;
+    let output = config.output()?;
+
+    // Diagnostics go to stderr so stdout carries only command responses
+    let level = config.log_level.as_deref();
+    let filter = log_filter(level).map_err(|e| DhtError::Config(format!("invalid log_level '{}': {}", level.unwrap_or_default(), e)))?;
+    let (filter, reload_handle) = reload::Layer::new(filter);
+    tracing_subscriber::registry().with(filter).with(tracing_subscriber::fmt::layer().with_writer(std::io::stderr)).init();
+    let _ = LOG_FILTER.set(reload_handle);
+
+    // Build the node and run its event loop in the background
+    let (node, event_loop) = DhtNode::new(&config).await?;
+    tokio::spawn(event_loop.run());
+    info!("{}", version::describe());
+
+    // Without a command just run; in JSON mode stdout carries only responses
+    let dry_run = cli.dry_run;
+    if dry_run {
+        info!("Dry run: puts and deletes are checked and reported, never carried out");
+    }
+    let daemon = cli.daemon || matches!(cli.command, Some(CliCommand::Daemon));
+    match cli.command.and_then(CliCommand::into_words) {
+        None if output == Output::Json => {}
+        None => {
+            println!("Commands:");
+            print_commands();
+            println!("Run with --help for the command line options");
+            println!("DHT node is running and will continue processing requests...");
+        }
+        // `put <key> -` takes its value from stdin first
+        Some(mut words) => {
+            words[0] = help::canonical(&words[0]).to_string();
+            if dry_run {
+                mark_dry(&mut words);
+            }
+            let args: Vec<String> = std::iter::once("program".to_string()).chain(words).collect();
+            let value = reads_value(&args[1..]).then(|| read_value(&mut io::stdin().lock()));
+            run_command(&node, &args, output, value).await?;
+        }
+    }
+
     // Main event loop - keep the node running and process commands
-    println!("DHT node is running. Enter commands or wait for network events...");
-    println!("Type 'exit' to quit");
+    if output == Output::Text {
+        println!("DHT node is running. Enter commands or wait for network events...");
+        println!("Type 'exit' to quit");
+    }
     
     // Create channels for user input
-    let (tx, mut rx) = mpsc::channel::<String>(100);
+    let (tx, mut rx) = mpsc::channel::<Input>(100);
     
-    // Spawn a task to read from stdin
-    tokio::spawn(async move {
-        let mut reader = io::BufReader::new(io::stdin());
-        let mut line = String::new();
-        
-        loop {
-            line.clear();
-            if reader.read_line(&mut line).await.is_ok() {
-                if let Err(_) = tx.send(line.trim().to_string()).await {
-                    break;
-                }
-            } else {
-                break;
-            }
-        }
+    // Read stdin on a plain thread: a blocked read there cannot be cancelled,
+    // but unlike a runtime task it does not keep the process alive on shutdown.
+    // A terminal gets a prompt with line editing and history; JSON output keeps
+    // plain lines so stdout carries nothing but results
+    let history = (output == Output::Text && io::stdin().is_terminal()).then(|| config.history_path());
+    std::thread::spawn(move || match history {
+        Some(history) => read_prompt(&tx, &history),
+        None => read_lines(&tx),
     });
     
-    // Main event loop
+    if let Some(http_addr) = config.http_addr {
+        let node = node.clone();
+        tokio::spawn(async move {
+            if let Err(e) = http::serve(http_addr, node).await {
+                error!("HTTP API stopped: {}", e);
+            }
+        });
+    }
+
+    if let Some(dns_addr) = config.dns_addr {
+        let node = node.clone();
+        tokio::spawn(async move {
+            if let Err(e) = dns::serve(dns_addr, node).await {
+                error!("DNS frontend stopped: {}", e);
+            }
+        });
+    }
+
+    if let Some(metrics_addr) = config.metrics_addr {
+        let metrics = node.metrics();
+        tokio::spawn(async move {
+            if let Err(e) = metrics::serve(metrics_addr, metrics).await {
+                error!("metrics endpoint stopped: {}", e);
+            }
+        });
+    }
+
+    // Commands from the control socket are handled like those from stdin, their output sent back
+    let (control_tx, mut control_rx) = mpsc::channel::<control::Request>(100);
+    let control_socket = match &config.control_socket {
+        Some(path) => {
+            let socket = ControlSocket::open(path, |line| reads_value(&line.split_whitespace().map(str::to_string).collect::<Vec<_>>()), control_tx)
+                .await
+                .map_err(|e| DhtError::Setup(format!("control socket {}: {}", path.display(), e)))?;
+            info!("Control socket listening on {}", path.display());
+            Some(socket)
+        }
+        None => None,
+    };
+
+    // One bucket per command name, so a burst of puts does not hold back a get
+    let mut limiters: HashMap<String, TokenBucket> = HashMap::new();
+    // The limits the buckets were made with, as `config set` may change them
+    let mut command_limits = (config.command_rate, config.command_burst);
+    // Commands still running, so those piped in before the end of input can finish
+    let mut commands = JoinSet::new();
+    let mut reading = true;
     loop {
-        tokio::select! {
-            Some(line) = rx.recv() => {
-                let args: Vec<String> = line.split_whitespace().map(|s| s.to_string()).collect();
-                
-                if !args.is_empty() {
-                    if args[0] == "exit" {
-                        println!("Exiting...");
-                        break Ok(());
-                    }
-                    
-                    // Process the command
-                    let cmd_args = std::iter::once("program".to_string())
-                        .chain(args.into_iter())
-                        .collect::<Vec<_>>();
-                    
-                    if let Err(e) = process_command(&cmd_args, &mut swarm).await {
-                        println!("Error processing command: {}", e);
+        let Input { line, value, reply } = tokio::select! {
+            input = rx.recv(), if reading => match input {
+                Some(input) => input,
+                None if daemon => {
+                    info!("Input ended, serving as a daemon until Ctrl-C");
+                    reading = false;
+                    continue;
+                }
+                None => {
+                    // Wait for the last commands before exiting
+                    while commands.join_next().await.is_some() {}
+                    if output == Output::Text {
+                        println!("Input ended, exiting...");
                     }
+                    break;
                 }
             },
-            event = swarm.select_next_some() => {
-                match event {
-                    SwarmEvent::NewListenAddr { address, .. } => {
-                        println!("Listening on {:?}", address);
-                    }
-                    SwarmEvent::Behaviour(event) => {
-                        println!("Network event received: {:?}", event);
-                    }
-                    _ => {}
+            Some(request) = control_rx.recv() => Input { line: request.line, value: request.value, reply: Some(request.output) },
+            Some(_) = commands.join_next(), if !commands.is_empty() => continue,
+            _ = tokio::signal::ctrl_c() => {
+                if output == Output::Text {
+                    println!("Received Ctrl-C, exiting...");
                 }
+                break;
             }
+        };
+        let mut args: Vec<String> = line.split_whitespace().map(|s| s.to_string()).collect();
+        if args.is_empty() {
+            continue;
+        }
+        args[0] = help::canonical(&args[0]).to_string();
+        if dry_run {
+            mark_dry(&mut args);
+        }
+        if args[0] == "exit" {
+            if output == Output::Text {
+                respond(reply.as_ref(), "Exiting...".to_string());
+            }
+            break;
+        }
+
+        let current = node.config();
+        if (current.command_rate, current.command_burst) != command_limits {
+            limiters.clear();
+            command_limits = (current.command_rate, current.command_burst);
+        }
+        let (rate, burst) = command_limits;
+        if rate > 0.0 {
+            let limiter = limiters.entry(args[0].clone())
+                .or_insert_with(|| TokenBucket::new(rate, burst));
+            if !limiter.try_acquire() {
+                let refusal = match output {
+                    Output::Text => format!("Rate limited, retry: {}", line),
+                    Output::Json => json!({ "error": "rate limited, retry", "line": line }).to_string(),
+                };
+                respond(reply.as_ref(), refusal);
+                continue;
+            }
+        }
+
+        // Process the command
+        let cmd_args = std::iter::once("program".to_string())
+            .chain(args.into_iter())
+            .collect::<Vec<_>>();
+
+        // Commands may wait on the network, so run each on its own task to keep reading input
+        let node = node.clone();
+        commands.spawn(replying(reply, async move {
+            if let Err(e) = run_command(&node, &cmd_args, output, value).await {
+                outln!("Error processing command: {}", e);
+            }
+        }));
+    }
+
+    // Stop the stdin reader: its next send fails once the receiver is gone
+    drop(rx);
+    // Removes the socket file
+    drop(control_socket);
+
+    // Saves the local store before the event loop exits
+    node.shutdown().await?;
+    Ok(())
+}
+
+// Print `line` to stdout, or send it to `reply` when the command came from the control socket
+fn respond(reply: Option<&mpsc::UnboundedSender<String>>, line: String) {
+    match reply {
+        Some(reply) => {
+            let _ = reply.send(line);
         }
+        None => println!("{}", line),
+    }
+}
+
+// Run `command` with its output going to `reply`, or to stdout without one
+async fn replying(reply: Option<mpsc::UnboundedSender<String>>, command: impl Future<Output = ()>) {
+    match reply {
+        Some(reply) => REPLY.scope(reply, command).await,
+        None => command.await,
+    }
+}
+
+// The log filter for `level`, in RUST_LOG syntax. Without one RUST_LOG is used,
+// and by default only this crate's info logs show
+fn log_filter(level: Option<&str>) -> Result<EnvFilter, tracing_subscriber::filter::ParseError> {
+    match level {
+        Some(level) => EnvFilter::try_new(level),
+        None => Ok(EnvFilter::try_from_default_env()
+            .unwrap_or_else(|_| EnvFilter::new(format!("warn,dht=info,{}=info", env!("CARGO_CRATE_NAME"))))),
+    }
+}
+
+// Change a runtime setting, applying those the node leaves to its caller
+async fn set_config(node: &DhtNode, key: &str, value: &str) -> Result<(), DhtError> {
+    let filter = match key {
+        "log_level" => {
+            let level = Some(value).filter(|value| *value != "none");
+            let filter = log_filter(level).map_err(|e| DhtError::InvalidCommand(format!("invalid log_level '{}': {}", value, e)))?;
+            Some(filter)
+        }
+        _ => None,
+    };
+    node.set_config(key, value).await?;
+    if let Some(filter) = filter
+        && let Some(handle) = LOG_FILTER.get()
+    {
+        handle.reload(filter).map_err(|e| DhtError::InvalidCommand(format!("could not change the log filter: {}", e)))?;
+    }
+    Ok(())
+}
+
+// A setting's value as the config file writes it
+fn setting_value(value: &Value) -> String {
+    match value {
+        Value::Null => "(not set)".to_string(),
+        value => value.to_string(),
     }
 }
 
 // Process a command based on the provided arguments
-async fn process_command(args: &[String], swarm: &mut Swarm<Kademlia<MemoryStore>>) -> Result<(), Box<dyn Error>> {
-    if args.len() > 3 && args[1] == "put" {
-        let key_string = args[2].clone();
-        let value = args[3].clone().into_bytes();
-        
-        // Convert string to bytes for the key
-        let key_bytes = key_string.as_bytes().to_vec();
-        let record_key = RecordKey::new(&key_bytes);
-        
-        // Create a record for local storage
-        let record = Record::new(record_key.clone(), value.clone());
-        
-        // Store the record locally without requiring a quorum
-        swarm.behaviour_mut().store_mut().put(record)?;
-        println!("Record stored locally for key: {}", key_string);
-        
-        // Also publish to the DHT
-        let record_for_dht = Record::new(record_key, value);
-        match swarm.behaviour_mut().put_record(record_for_dht, Quorum::One) {
-            Ok(_) => println!("Publishing record to DHT for key: {}", key_string),
-            Err(e) => println!("Error publishing to DHT: {}", e),
-        };
+// `stdin_value` is what followed the command on stdin when it was `put <key> -`
+async fn run_command(node: &DhtNode, args: &[String], output: Output, stdin_value: Option<Vec<u8>>) -> Result<(), DhtError> {
+    if output == Output::Json {
+        // Errors are responses too, so every command prints exactly one line
+        let response = json_command(node, args, stdin_value).await.unwrap_or_else(|e| json!({ "error": e.to_string() }));
+        outln!("{}", response);
+        return Ok(());
+    }
+
+    if args.len() > 3 && args[1] == "dry" {
+        let args = undry(args);
+        match dry_command(node, &args, stdin_value).await {
+            Ok((dry_run, Some(put))) => print_dry_put(&dry_run, &put),
+            Ok((dry_run, None)) => print_dry_delete(&dry_run),
+            Err(e @ (DhtError::Unauthorized { .. } | DhtError::Rejected { .. } | DhtError::StoreFull { .. } | DhtError::ValueTooLarge { .. })) => {
+                outln!("Dry run: {} {} would be refused: {}", args[1], args[2], e);
+            }
+            Err(e) => return Err(e),
+        }
+    } else if args.len() > 3 && args[1] == "put" {
+        note_local_only(node).await?;
+        let result = put(node, &args[2], put_args(node, args, stdin_value)?).await;
+        report_put(&args[2], result)?;
+    } else if args.len() > 2 && args[1] == "put-cas" {
+        let (key, put_args) = cas_args(node, args, stdin_value)?;
+        outln!("Content key: {}", key);
+        note_local_only(node).await?;
+        let result = put(node, &key, put_args).await;
+        report_put(&key, result)?;
+    } else if args.len() > 4 && args[1] == "delegate" {
+        let key = parse_peer_id(&args[4])?;
+        outln!("Delegating zone {} from {} to {}", args[3], args[2], key);
+        let result = node.delegate(&args[2], &args[3], key).await;
+        report_put(&delegation_key(&args[3])?, result)?;
     } else if args.len() > 2 && args[1] == "get" {
+        let quorum = match args.get(3) {
+            Some(quorum) => parse_quorum(quorum).map_err(DhtError::InvalidCommand)?,
+            None => node.default_quorum(),
+        };
+        note_local_only(node).await?;
+        lookup(node, args[2].clone(), quorum).await?;
+    } else if args.len() > 2 && args[1] == "resolve" {
+        let resolution = resolve(node, &args[2]).await?;
+        match resolution.answer {
+            Some(answer) => {
+                let verified = node.trust_anchor().map(|anchor| format!(" (signed through delegations from {})", anchor.zone));
+                outln!("Resolved: {} -> {}{}{}", resolution.chain.join(" -> "), answer, from_peer(resolution.source), verified.unwrap_or_default());
+            }
+            None => outln!("Resolution failed: {} -> (not found)", resolution.chain.join(" -> ")),
+        }
+    } else if args.len() > 2 && args[1] == "resolve-json" {
+        let records = record_set(node, &args[2]).await?;
+        outln!("{}", record_set_json(&args[2], &records));
+    } else if args.len() > 2 && args[1] == "get-all" {
+        let entries = node.get_all(&args[2]).await?;
+        if entries.is_empty() {
+            outln!("Record not found for key: {}", args[2]);
+        }
+        for entry in &entries {
+            outln!("Found record: {} => {} (version {}){}", args[2], dns_record::format_value(&entry.value), entry.version, from_peer(entry.source));
+        }
+        if entries.len() > 1 {
+            outln!("{} distinct values for key: {}", entries.len(), args[2]);
+        }
+    } else if args.len() > 2 && args[1] == "watch" {
         let key_string = args[2].clone();
-        
-        // Convert string to bytes for the key
-        let key_bytes = key_string.as_bytes().to_vec();
-        let record_key = RecordKey::new(&key_bytes);
-        
-        // Try to get the record from the local store
-        if let Some(record) = swarm.behaviour_mut().store_mut().get(&record_key) {
-            println!("Found record locally: {} => {}", 
-                key_string,
-                String::from_utf8_lossy(&record.value));
+        let mut updates = node.watch(&key_string).await?;
+        outln!("Watching key: {}", key_string);
+        // Report changes until the watch is replaced, unwatched or the node stops
+        tokio::spawn(replying(REPLY.try_with(Clone::clone).ok(), async move {
+            while let Some(update) = updates.recv().await {
+                match update {
+                    Some(value) => outln!("Watch: {} => {}", key_string, dns_record::format_value(&value)),
+                    None => outln!("Watch: {} not found", key_string),
+                }
+            }
+        }));
+    } else if args.len() > 2 && args[1] == "unwatch" {
+        if node.unwatch(&args[2]).await? {
+            outln!("Stopped watching key: {}", args[2]);
         } else {
-            println!("Record not found locally for key: {}", key_string);
-            // Fall back to DHT query
-            let query_id = swarm.behaviour_mut().get_record(record_key);
-            println!("Querying DHT for key: {} (Query ID: {:?})", key_string, query_id);
+            outln!("Not watching key: {}", args[2]);
+        }
+    } else if args.len() > 2 && args[1] == "delete" {
+        let key_string = &args[2];
+        if node.delete(key_string).await? {
+            outln!("Deleted record for key: {} (peers holding a copy are told to drop it)", key_string);
+        } else {
+            outln!("No such key: {}", key_string);
+        }
+    } else if args.len() > 2 && args[1] == "pin" {
+        if node.pin(&args[2]).await? {
+            outln!("Pinned key: {} (republished before it expires)", args[2]);
+        } else {
+            outln!("No such key stored locally: {}", args[2]);
+        }
+    } else if args.len() > 2 && args[1] == "unpin" {
+        if node.unpin(&args[2]).await? {
+            outln!("Unpinned key: {}", args[2]);
+        } else {
+            outln!("Key not pinned: {}", args[2]);
+        }
+    } else if args.len() > 2 && args[1] == "describe" {
+        match node.describe(&args[2], describe_remote(args)?).await? {
+            Some(description) => print_description(&description),
+            None => outln!("Record not found for key: {}", args[2]),
+        }
+    } else if args.len() > 1 && args[1] == "list" {
+        let records = node.records().await?;
+        for record in &records {
+            let pinned = if record.pinned { ", pinned" } else { "" };
+            outln!("  {} ({} bytes{})", display_key(&record.key), record.size, pinned);
+        }
+        outln!("{} record(s) stored locally", records.len());
+    } else if args.len() > 1 && args[1] == "expired" {
+        let records = node.expired().await?;
+        for record in &records {
+            outln!("  {} ({} bytes)", display_key(&record.key), record.size);
+        }
+        outln!("{} expired record(s) awaiting removal", records.len());
+    } else if args.len() > 2 && args[1] == "export" {
+        let mut lines = String::new();
+        let (mut exported, mut skipped) = (0, 0);
+        for (key, entry) in node.entries().await? {
+            // Keys must survive as one text field to be put again on import
+            match std::str::from_utf8(&key) {
+                Ok(key) if !key.contains(['\t', '\n']) => {
+                    lines.push_str(&export::encode_line(key, &entry.value));
+                    lines.push('\n');
+                    exported += 1;
+                }
+                _ => skipped += 1,
+            }
+        }
+        std::fs::write(&args[2], lines)?;
+        outln!("Exported {} record(s) to {}", exported, args[2]);
+        if skipped > 0 {
+            outln!("Skipped {} record(s) whose keys are not plain text", skipped);
+        }
+    } else if args.len() > 2 && args[1] == "import" {
+        let text = std::fs::read_to_string(&args[2])?;
+        let mut records = Vec::new();
+        let mut malformed = 0;
+        for (index, line) in text.lines().enumerate() {
+            match export::decode_line(line) {
+                Line::Record { key, value } => records.push((key.to_string(), value)),
+                Line::Skip => {}
+                Line::Malformed => {
+                    outln!("Skipping malformed line {}", index + 1);
+                    malformed += 1;
+                }
+            }
+        }
+
+        let PutTally { stored: imported, unreplicated, rejected, .. } = put_batch(node, records).await;
+        outln!("Imported {} record(s) from {}", imported, args[2]);
+        if unreplicated > 0 {
+            outln!("{} of them are stored locally but not yet replicated to the DHT", unreplicated);
+        }
+        if malformed > 0 || !rejected.is_empty() {
+            outln!("Skipped {} malformed line(s) and {} rejected record(s)", malformed, rejected.len());
+        }
+    } else if args.len() > 2 && args[1] == "import-zone" {
+        let text = std::fs::read_to_string(&args[2])?;
+        let zone = zone::parse(&text, Path::new(&args[2]), args.get(3).map(String::as_str)).map_err(DhtError::InvalidCommand)?;
+        for (record_type, count) in &zone.skipped {
+            outln!("Skipping {} {} record(s): type not supported", count, record_type);
+        }
+        let records = zone.names.iter().map(|(name, records)| (name.clone(), DnsRecord::encode_all(records))).collect();
+        let tally = put_batch(node, records).await;
+
+        // Count the records of each type under the names that were stored
+        let mut imported: BTreeMap<&str, usize> = BTreeMap::new();
+        for (_, records) in zone.names.iter().filter(|(name, _)| !tally.rejected.contains(name)) {
+            for record in records {
+                *imported.entry(zone::type_name(record)).or_default() += 1;
+            }
+        }
+        let counts: Vec<String> = imported.iter().map(|(record_type, count)| format!("{} {}", count, record_type)).collect();
+        outln!(
+            "Imported {} record(s) under {} name(s) from zone {}{}{}",
+            imported.values().sum::<usize>(), tally.stored, zone.origin,
+            if counts.is_empty() { "" } else { ": " }, counts.join(", "),
+        );
+        if tally.unreplicated > 0 {
+            outln!("{} of the names are stored locally but not yet replicated to the DHT", tally.unreplicated);
+        }
+        if !tally.rejected.is_empty() {
+            outln!("Skipped {} rejected name(s)", tally.rejected.len());
+        }
+    } else if args.len() > 2 && args[1] == "putmany" {
+        let text = std::fs::read_to_string(&args[2])?;
+        let mut records = Vec::new();
+        let mut malformed = 0;
+        for (index, line) in text.lines().enumerate() {
+            match export::decode_pair(line) {
+                Line::Record { key, value } => records.push((key.to_string(), value)),
+                Line::Skip => {}
+                Line::Malformed => {
+                    outln!("Skipping malformed line {}", index + 1);
+                    malformed += 1;
+                }
+            }
+        }
+
+        let started = std::time::Instant::now();
+        let tally = put_batch(node, records).await;
+        let elapsed = started.elapsed().as_secs_f64();
+        outln!(
+            "Stored {} of {} record(s) from {} in {:.1}s ({:.0} puts/s, {} in flight at most)",
+            tally.stored, tally.total, args[2], elapsed, tally.total as f64 / elapsed.max(0.001), node.put_concurrency(),
+        );
+        if tally.unreplicated > 0 {
+            outln!("{} of them are stored locally but not yet replicated to the DHT", tally.unreplicated);
+        }
+        if malformed > 0 || !tally.rejected.is_empty() {
+            outln!("Skipped {} malformed line(s) and {} rejected record(s)", malformed, tally.rejected.len());
+        }
+    } else if args.len() > 2 && args[1] == "provide" {
+        node.provide(&args[2]).await?;
+        outln!("Providing key: {}", args[2]);
+    } else if args.len() > 2 && args[1] == "providers" {
+        let providers = node.providers(&args[2]).await?;
+        outln!("Providers for {} ({}):", args[2], providers.len());
+        for peer_id in providers {
+            if peer_id == node.local_peer_id() {
+                outln!("  {} (this node)", peer_id);
+            } else {
+                outln!("  {}", peer_id);
+            }
+        }
+    } else if args.len() > 3 && args[1] == "bootstrap" {
+        let addr = parse_multiaddr(&args[2])?;
+        let peer_id = parse_peer_id(&args[3])?;
+
+        outln!("Added bootstrap peer {} at {}", peer_id, addr);
+        match node.bootstrap(addr, peer_id).await {
+            Ok(()) => outln!("Bootstrapping into the DHT..."),
+            Err(DhtError::NoKnownPeers) => outln!("Cannot bootstrap: {}", DhtError::NoKnownPeers),
+            Err(e) => return Err(e),
+        }
+    } else if args.len() > 2 && args[1] == "dial" {
+        let addr = parse_multiaddr(&args[2])?;
+        node.dial(addr.clone()).await?;
+        outln!("Dialing {}", addr);
+    } else if args.len() > 2 && args[1] == "ping" {
+        let peer_id = parse_peer_id(&args[2])?;
+        let rtt = node.ping(peer_id).await?;
+        outln!("Pong from {}: {:.1} ms", peer_id, rtt.as_secs_f64() * 1000.0);
+    } else if args.len() > 1 && args[1] == "peers" {
+        let (connected, disconnected): (Vec<_>, Vec<_>) = node.peers().await?
+            .into_iter()
+            .partition(|peer| peer.connected);
+
+        outln!("Connected peers ({}):", connected.len());
+        for peer in &connected {
+            let location = if peer.routable { "routing table" } else { "connected only" };
+            match peer.rtt {
+                Some(rtt) => outln!("  {} [{}, rtt {:.1} ms]", peer.peer_id, location, rtt.as_secs_f64() * 1000.0),
+                None => outln!("  {} [{}]", peer.peer_id, location),
+            }
+            for (addr, age) in peer.addrs.iter().zip(&peer.ages) {
+                outln!("    {} (open {}s)", addr, age.as_secs());
+            }
+        }
+
+        outln!("Routing table peers not connected ({}):", disconnected.len());
+        for peer in &disconnected {
+            outln!("  {}", peer.peer_id);
+            for addr in &peer.addrs {
+                outln!("    {}", addr);
+            }
+        }
+    } else if args.len() > 1 && args[1] == "security" {
+        let connections = node.security().await?;
+        if connections.is_empty() {
+            outln!("No open connections");
+            return Ok(());
+        }
+        for connection in &connections {
+            let network = if connection.private_network { ", private network" } else { "" };
+            outln!("{} at {}", connection.peer_id, connection.addr);
+            outln!("  security {}, muxer {}{}", connection.security, connection.muxer, network);
+        }
+    } else if args.len() > 2 && args[1] == "find-node" {
+        let closest = node.find_node(&args[2]).await?;
+        let elapsed = closest.duration.unwrap_or_default().as_secs_f64() * 1000.0;
+        outln!(
+            "Closest peers to {} ({}), {} asked, {} answered in {:.1} ms{}:",
+            args[2], closest.peers.len(), closest.asked, closest.answered, elapsed,
+            if closest.timed_out { ", timed out" } else { "" },
+        );
+        for (peer_id, distance) in &closest.peers {
+            match distance {
+                Some(index) => outln!("  {} [distance bucket {}]", peer_id, index),
+                None => outln!("  {} [the target]", peer_id),
+            }
+        }
+    } else if args.len() > 2 && args[1] == "benchmark" {
+        let (count, value_bytes) = benchmark_args(args)?;
+        outln!("Benchmarking {} put(s) and get(s) of {} byte values, {} at a time...", count, value_bytes, node.put_concurrency());
+        print_benchmark(&benchmark::run(node, count, value_bytes).await);
+    } else if args.len() > 1 && args[1] == "buckets" {
+        let buckets = node.buckets().await?;
+        if buckets.is_empty() {
+            outln!("Routing table empty (bootstrap or wait for mDNS to find peers)");
+            return Ok(());
+        }
+        for bucket in &buckets {
+            outln!("Bucket {} ({} peer(s)):", bucket.index, bucket.peers.len());
+            for peer in &bucket.peers {
+                let status = if peer.connected { "connected" } else { "disconnected" };
+                outln!("  {} [{}]", peer.peer_id, status);
+            }
+        }
+        let total: usize = buckets.iter().map(|bucket| bucket.peers.len()).sum();
+        outln!("{} peer(s) in {} bucket(s)", total, buckets.len());
+    } else if args.len() > 1 && args[1] == "addrs" {
+        let addrs = node.addrs().await?;
+        outln!("Reachability: {}", addrs.reachability);
+        outln!("Listen addresses ({}):", addrs.listen.len());
+        for addr in &addrs.listen {
+            outln!("  {}", addr);
+        }
+        if args.get(2).is_some_and(|arg| arg == "all") && !addrs.private.is_empty() {
+            outln!("Listen addresses not shared, as --public-only is on ({}):", addrs.private.len());
+            for addr in &addrs.private {
+                outln!("  {}", addr);
+            }
+        }
+        if !addrs.external.is_empty() {
+            outln!("External addresses ({}):", addrs.external.len());
+            for addr in &addrs.external {
+                outln!("  {}", addr);
+            }
+        }
+    } else if args.len() > 1 && args[1] == "stats" {
+        let stats = node.stats().await?;
+        outln!("Peer id: {}", stats.peer_id);
+        outln!("Connected peers: {}", stats.connected_peers);
+        outln!("Records stored: {} ({} bytes)", stats.records, stats.record_bytes);
+        outln!("Listen addresses: {}", stats.listen_addrs);
+        outln!("Reachability: {}", stats.reachability);
+        outln!("Status: {}", stats.status);
+        outln!("DHT queries: {} issued, {} succeeded, {} failed",
+            stats.queries_issued, stats.queries_succeeded, stats.queries_failed);
+        outln!("Gets: {} from the local store, {} from the cache, {} looked up in the DHT",
+            stats.local_hits, stats.cache_hits, stats.dht_lookups);
+    } else if args.len() > 1 && args[1] == "version" {
+        outln!("{}", version::describe());
+        outln!("Peer id: {}", node.local_peer_id());
+    } else if args.len() > 1 && args[1] == "help" {
+        match args.get(2) {
+            None => {
+                outln!("Commands:");
+                for command in help::COMMANDS {
+                    outln!("  {:<10} {}", command.name, command.summary);
+                }
+                outln!("Type 'help <command>' for its syntax and examples");
+            }
+            Some(word) => match help::find(word) {
+                Some(command) => print_help(command),
+                None => match help::suggest(word) {
+                    Some(name) => outln!("No such command '{}'. Did you mean '{}'?", word, name),
+                    None => outln!("No such command '{}'", word),
+                },
+            },
+        }
+    } else if args.len() > 4 && args[1] == "config" && args[2] == "set" {
+        let value = args[4..].join(" ");
+        set_config(node, &args[3], &value).await?;
+        let config = node.config();
+        outln!("Set {} = {}", args[3], config.get(&args[3]).map(|value| setting_value(&value)).unwrap_or_default());
+    } else if args.len() > 2 && args[1] == "config" && args[2] == "get" {
+        let config = node.config();
+        match args.get(3) {
+            Some(key) => match config.get(key) {
+                Some(value) => outln!("{} = {}", key, setting_value(&value)),
+                None => outln!("No such setting '{}'", key),
+            },
+            None => {
+                outln!("Settings that can be changed with config set:");
+                for key in RUNTIME_SETTINGS {
+                    outln!("  {} = {}", key, config.get(key).map(|value| setting_value(&value)).unwrap_or_default());
+                }
+            }
         }
+    } else if args.len() > 1 && args[1] == "ready" {
+        let health = node.health().await?;
+        outln!("Ready: {}", if health.ready() { "yes" } else { "no" });
+        outln!("Status: {}", health.status());
+        outln!("Listening: {}", if health.listening { "yes" } else { "no" });
+        outln!("Connected peers: {}", health.connected_peers);
+        outln!("Routing table peers: {}", health.routing_table_peers);
+        outln!("Uptime: {}s", health.uptime.as_secs());
     } else {
-        println!("Usage:");
-        println!("  put <key> <value>");
-        println!("  get <key>");
+        let command = &args[1];
+        match help::find(command) {
+            // A known command with missing arguments
+            Some(help) => print_usage(help),
+            None => {
+                match help::suggest(command) {
+                    Some(name) => outln!("Unknown command '{}'. Did you mean '{}'?", command, name),
+                    None => outln!("Unknown command '{}'", command),
+                }
+                outln!("Type 'help' to list the commands");
+            }
+        }
     }
     
     Ok(())
 }
+
+// The syntax of every command, as listed in the usage text
+fn print_commands() {
+    for command in help::COMMANDS {
+        for usage in command.usage {
+            outln!("  {}", usage);
+        }
+    }
+}
+
+fn print_usage(command: &help::CommandHelp) {
+    outln!("Usage:");
+    for usage in command.usage {
+        outln!("  {}", usage);
+    }
+}
+
+// Everything `help <command>` knows about a command
+fn print_help(command: &help::CommandHelp) {
+    print_usage(command);
+    outln!("{}", command.summary);
+    outln!("{}", command.details);
+    let aliases: Vec<&str> = help::aliases(command.name).collect();
+    if !aliases.is_empty() {
+        outln!("Aliases: {}", aliases.join(", "));
+    }
+    outln!("Examples:");
+    for example in command.examples {
+        outln!("  {}", example);
+    }
+}
+
+// Say so before a put or get when the DHT cannot be reached, so that a local
+// success is not taken for one the network saw
+async fn note_local_only(node: &DhtNode) -> Result<(), DhtError> {
+    if node.health().await?.status() == NetworkStatus::Offline {
+        outln!("Note: operating in local-only mode; DHT unavailable");
+    }
+    Ok(())
+}
+
+// Print how a put went, or return an error that is not about the record itself
+fn report_put(key: &str, result: Result<(), DhtError>) -> Result<(), DhtError> {
+    match result {
+        Ok(()) => {
+            outln!("Record stored locally for key: {}", key);
+            outln!("Record replicated to DHT for key: {} (quorum reached)", key);
+        }
+        // The record is kept locally and republished later even when the network put fails
+        Err(e @ (DhtError::QuorumFailed { .. } | DhtError::Timeout)) => {
+            outln!("Record stored locally for key: {}", key);
+            outln!("Failed to store record in DHT for key: {}: {}", key, e);
+        }
+        Err(e @ DhtError::Conflict { .. }) => {
+            outln!("Conflict, record not stored for key: {}: {}", key, e);
+        }
+        Err(e @ DhtError::Unauthorized { .. }) => {
+            outln!("Unauthorized, record not stored for key: {}: {}", key, e);
+        }
+        Err(e @ (DhtError::Rejected { .. } | DhtError::StoreFull { .. } | DhtError::ValueTooLarge { .. } | DhtError::ContentMismatch { .. } | DhtError::Store(_))) => {
+            outln!("Record not stored for key: {}: {}", key, e);
+        }
+        Err(e) => return Err(e),
+    }
+    Ok(())
+}
+
+// Run one of the commands that have a JSON form and build its response
+async fn json_command(node: &DhtNode, args: &[String], stdin_value: Option<Vec<u8>>) -> Result<Value, DhtError> {
+    let command = args.get(1).map(String::as_str).unwrap_or_default();
+    let response = if args.len() > 3 && command == "dry" {
+        let args = undry(args);
+        let (dry_run, put) = dry_command(node, &args, stdin_value).await?;
+        json!({
+            "cmd": "dry", "action": args[1], "key": dry_run.key,
+            "record_key": hex(&dry_run.record_key),
+            "store_path": dry_run.store_path.display().to_string(),
+            "stored": dry_run.stored, "pinned": dry_run.pinned,
+            "owner": dry_run.owner.map(|owner| owner.to_string()),
+            "version": put.as_ref().map(|_| dry_run.version),
+            "value_bytes": put.as_ref().map(|_| dry_run.value_bytes),
+            "stored_bytes": put.as_ref().map(|_| dry_run.stored_bytes),
+            "sealed_bytes": put.as_ref().map(|_| dry_run.sealed_bytes),
+            "signer": dry_run.signer.map(|signer| signer.to_string()),
+            "owned": put.as_ref().map(|_| dry_run.owned),
+            "quorum": put.as_ref().map(|put| quorum_name(put.quorum)),
+            "ttl_secs": put.as_ref().and_then(|put| put.ttl).map(|ttl| ttl.as_secs()),
+        })
+    } else if args.len() > 3 && command == "put" {
+        let status = node.health().await?.status().to_string();
+        let result = put(node, &args[2], put_args(node, args, stdin_value)?).await;
+        match result {
+            Ok(()) => json!({ "cmd": "put", "key": args[2], "stored": true, "replicated": true, "status": status }),
+            // As in text mode the record is kept locally and republished later
+            Err(e @ (DhtError::QuorumFailed { .. } | DhtError::Timeout)) => json!({
+                "cmd": "put", "key": args[2], "stored": true, "replicated": false, "reason": e.to_string(), "status": status,
+            }),
+            Err(e) => return Err(e),
+        }
+    } else if args.len() > 2 && command == "put-cas" {
+        let (key, put_args) = cas_args(node, args, stdin_value)?;
+        let status = node.health().await?.status().to_string();
+        match put(node, &key, put_args).await {
+            Ok(()) => json!({ "cmd": "put-cas", "key": key, "stored": true, "replicated": true, "status": status }),
+            Err(e @ (DhtError::QuorumFailed { .. } | DhtError::Timeout)) => json!({
+                "cmd": "put-cas", "key": key, "stored": true, "replicated": false, "reason": e.to_string(), "status": status,
+            }),
+            Err(e) => return Err(e),
+        }
+    } else if args.len() > 4 && command == "delegate" {
+        let key = parse_peer_id(&args[4])?;
+        let (parent, child, record_key) = (&args[2], &args[3], delegation_key(&args[3])?);
+        match node.delegate(parent, child, key).await {
+            Ok(()) => json!({
+                "cmd": "delegate", "parent": parent, "child": child, "to": key.to_string(), "key": record_key,
+                "stored": true, "replicated": true,
+            }),
+            Err(e @ (DhtError::QuorumFailed { .. } | DhtError::Timeout)) => json!({
+                "cmd": "delegate", "parent": parent, "child": child, "to": key.to_string(), "key": record_key,
+                "stored": true, "replicated": false, "reason": e.to_string(),
+            }),
+            Err(e) => return Err(e),
+        }
+    } else if args.len() > 2 && command == "get" {
+        let quorum = match args.get(3) {
+            Some(quorum) => parse_quorum(quorum).map_err(DhtError::InvalidCommand)?,
+            None => node.default_quorum(),
+        };
+        let status = node.health().await?.status().to_string();
+        // CNAMEs are not followed; the target is in the value for the caller to look up
+        match node.get_entry(&args[2], quorum).await? {
+            Some(entry) => {
+                let addresses = match DnsRecord::decode_all(&entry.value).map(|records| dns_record::natural(&records)) {
+                    Some(Natural::Addresses(addrs)) => addrs.iter().map(IpAddr::to_string).collect(),
+                    _ => Vec::new(),
+                };
+                json!({
+                    "cmd": "get", "key": args[2], "found": true,
+                    "value": dns_record::format_value(&entry.value), "version": entry.version,
+                    "addresses": addresses, "source": entry.source.map(|source| source.to_string()),
+                    "stale": entry.stale, "status": status,
+                })
+            }
+            None => json!({ "cmd": "get", "key": args[2], "found": false, "status": status }),
+        }
+    } else if args.len() > 2 && command == "resolve" {
+        let resolution = resolve(node, &args[2]).await?;
+        json!({
+            "cmd": "resolve", "name": args[2], "found": resolution.answer.is_some(),
+            "chain": resolution.chain, "answer": resolution.answer,
+            "source": resolution.source.map(|source| source.to_string()),
+            "verified": resolution.answer.is_some() && node.trust_anchor().is_some(),
+        })
+    } else if args.len() > 2 && command == "resolve-json" {
+        let records = record_set(node, &args[2]).await?;
+        let mut response = record_set_json(&args[2], &records);
+        response["cmd"] = json!("resolve-json");
+        response
+    } else if args.len() > 2 && command == "describe" {
+        match node.describe(&args[2], describe_remote(args)?).await? {
+            Some(d) => json!({
+                "cmd": "describe", "key": d.key, "found": true,
+                "source": d.source.map(|source| source.to_string()), "cached": d.cached,
+                "version": d.version,
+                "signer": d.signer.map(|signer| signer.to_string()), "owned": d.owned,
+                "created_ms": d.metadata.map(|metadata| metadata.created),
+                "updated_ms": d.metadata.map(|metadata| metadata.updated),
+                "ttl_secs": d.metadata.map(|metadata| metadata.ttl_secs),
+                "expires_in_secs": d.expires_in.map(|left| left.as_secs()),
+                "value_bytes": d.value_bytes, "stored_bytes": d.stored_bytes, "sealed_bytes": d.sealed_bytes,
+            }),
+            None => json!({ "cmd": "describe", "key": args[2], "found": false }),
+        }
+    } else if command == "list" {
+        let records: Vec<Value> = node.records().await?
+            .iter()
+            .map(|record| json!({ "key": display_key(&record.key), "bytes": record.size, "pinned": record.pinned }))
+            .collect();
+        json!({ "cmd": "list", "count": records.len(), "records": records })
+    } else if args.len() > 2 && command == "ping" {
+        let peer_id = parse_peer_id(&args[2])?;
+        let rtt = node.ping(peer_id).await?;
+        json!({ "cmd": "ping", "peer_id": peer_id.to_string(), "rtt_ms": rtt.as_secs_f64() * 1000.0 })
+    } else if command == "peers" {
+        let peers: Vec<Value> = node.peers().await?
+            .iter()
+            .map(|peer| json!({
+                "peer_id": peer.peer_id.to_string(),
+                "connected": peer.connected,
+                "routable": peer.routable,
+                "rtt_ms": peer.rtt.map(|rtt| rtt.as_secs_f64() * 1000.0),
+                "addrs": peer.addrs.iter().map(|addr| addr.to_string()).collect::<Vec<_>>(),
+                "ages_secs": peer.ages.iter().map(|age| age.as_secs()).collect::<Vec<_>>(),
+            }))
+            .collect();
+        json!({ "cmd": "peers", "peers": peers })
+    } else if args.len() > 2 && command == "find-node" {
+        let closest = node.find_node(&args[2]).await?;
+        let peers: Vec<Value> = closest.peers.iter()
+            .map(|(peer_id, distance)| json!({ "peer_id": peer_id.to_string(), "distance_bucket": distance }))
+            .collect();
+        json!({
+            "cmd": "find-node",
+            "target": args[2],
+            "peers": peers,
+            "asked": closest.asked,
+            "answered": closest.answered,
+            "duration_ms": closest.duration.map(|duration| duration.as_secs_f64() * 1000.0),
+            "timed_out": closest.timed_out,
+        })
+    } else if args.len() > 2 && command == "benchmark" {
+        let (count, value_bytes) = benchmark_args(args)?;
+        let result = benchmark::run(node, count, value_bytes).await;
+        json!({
+            "cmd": "benchmark",
+            "value_bytes": result.value_bytes,
+            "concurrency": node.put_concurrency(),
+            "puts": phase_json(&result.puts),
+            "gets": phase_json(&result.gets),
+        })
+    } else if command == "security" {
+        let connections: Vec<Value> = node.security().await?
+            .iter()
+            .map(|connection| json!({
+                "peer_id": connection.peer_id.to_string(),
+                "addr": connection.addr.to_string(),
+                "security": connection.security,
+                "muxer": connection.muxer,
+                "private_network": connection.private_network,
+            }))
+            .collect();
+        json!({ "cmd": "security", "connections": connections })
+    } else if command == "stats" {
+        let stats = node.stats().await?;
+        json!({
+            "cmd": "stats",
+            "peer_id": stats.peer_id.to_string(),
+            "connected_peers": stats.connected_peers,
+            "records": stats.records,
+            "record_bytes": stats.record_bytes,
+            "listen_addrs": stats.listen_addrs,
+            "reachability": stats.reachability.to_string(),
+            "status": stats.status.to_string(),
+            "queries_issued": stats.queries_issued,
+            "queries_succeeded": stats.queries_succeeded,
+            "queries_failed": stats.queries_failed,
+            "local_hits": stats.local_hits,
+            "cache_hits": stats.cache_hits,
+            "dht_lookups": stats.dht_lookups,
+        })
+    } else if command == "version" {
+        json!({
+            "cmd": "version",
+            "version": version::VERSION,
+            "commit": version::GIT_COMMIT,
+            "rustc": version::RUSTC_VERSION,
+            "features": version::features(),
+            "peer_id": node.local_peer_id().to_string(),
+        })
+    } else if args.len() > 4 && command == "config" && args[2] == "set" {
+        set_config(node, &args[3], &args[4..].join(" ")).await?;
+        json!({ "cmd": "config", "key": args[3], "value": node.config().get(&args[3]) })
+    } else if args.len() > 2 && command == "config" && args[2] == "get" {
+        let config = node.config();
+        match args.get(3) {
+            Some(key) => match config.get(key) {
+                Some(value) => json!({ "cmd": "config", "key": key, "value": value }),
+                None => return Err(DhtError::InvalidCommand(format!("no such setting '{}'", key))),
+            },
+            None => {
+                let settings: serde_json::Map<String, Value> = RUNTIME_SETTINGS.iter()
+                    .filter_map(|key| Some((key.to_string(), config.get(key)?)))
+                    .collect();
+                json!({ "cmd": "config", "settings": settings })
+            }
+        }
+    } else if command == "ready" {
+        let health = node.health().await?;
+        json!({
+            "cmd": "ready",
+            "ready": health.ready(),
+            "status": health.status().to_string(),
+            "listening": health.listening,
+            "connected_peers": health.connected_peers,
+            "routing_table_peers": health.routing_table_peers,
+            "uptime_secs": health.uptime.as_secs(),
+        })
+    } else {
+        return Err(DhtError::InvalidCommand(format!(
+            "unknown or incomplete command '{}'; JSON output supports put, put-cas, delegate, get, resolve, resolve-json, describe, list, ping, peers, find-node, benchmark, stats, config, ready and version",
+            args[1..].join(" "),
+        )));
+    };
+    Ok(response)
+}
+
+// The bytes a value argument stands for: the contents of `@file`, what was read from stdin for `-`, or itself
+fn value_arg(arg: &str, stdin_value: Option<Vec<u8>>) -> Result<Vec<u8>, DhtError> {
+    if arg == "-" {
+        return stdin_value.ok_or_else(|| DhtError::InvalidCommand("no value was read from stdin".to_string()));
+    }
+    match arg.strip_prefix('@') {
+        Some(path) => std::fs::read(path)
+            .map_err(|e| DhtError::InvalidCommand(format!("could not read value from {}: {}", path, e))),
+        None => Ok(arg.as_bytes().to_vec()),
+    }
+}
+
+// Send each line piped to stdin on to the main loop, until the end of input
+fn read_lines(tx: &mpsc::Sender<Input>) {
+    let mut reader = io::stdin().lock();
+    let mut line = String::new();
+
+    loop {
+        line.clear();
+        match reader.read_line(&mut line) {
+            // End of input; dropping the sender tells the main loop
+            Ok(0) => break,
+            Err(e) => {
+                error!("could not read stdin: {}", e);
+                break;
+            }
+            // Blank lines would only take up room in the channel
+            Ok(_) if line.trim().is_empty() => {}
+            Ok(_) => {
+                let line = line.trim().to_string();
+                // The lines after `put <key> -` are its value, not commands
+                let words: Vec<String> = line.split_whitespace().map(|s| s.to_string()).collect();
+                let value = reads_value(&words).then(|| read_value(&mut reader));
+                if tx.blocking_send(Input { line, value, reply: None }).is_err() {
+                    break;
+                }
+            }
+        }
+    }
+}
+
+// Read commands typed at a terminal behind a `dht> ` prompt, keeping their history in `history`
+fn read_prompt(tx: &mpsc::Sender<Input>, history: &Path) {
+    let mut editor = match DefaultEditor::new() {
+        Ok(editor) => editor,
+        Err(e) => {
+            error!("could not set up the prompt, reading plain lines: {}", e);
+            return read_lines(tx);
+        }
+    };
+    // There is no history yet on the first run
+    let _ = editor.load_history(history);
+
+    loop {
+        let line = match editor.readline("dht> ") {
+            Ok(line) => line.trim().to_string(),
+            // The terminal is in raw mode while reading, so Ctrl-C arrives here instead of as a signal
+            Err(ReadlineError::Interrupted) => "exit".to_string(),
+            Err(ReadlineError::Eof) => break,
+            Err(e) => {
+                error!("could not read stdin: {}", e);
+                break;
+            }
+        };
+        if line.is_empty() {
+            continue;
+        }
+        let _ = editor.add_history_entry(line.as_str());
+        if let Err(e) = save_history(&mut editor, history) {
+            error!("could not save history to {}: {}", history.display(), e);
+        }
+        let words: Vec<String> = line.split_whitespace().map(|s| s.to_string()).collect();
+        let value = reads_value(&words).then(|| read_prompted_value(&mut editor));
+        // Stop reading before the node shuts down, so the terminal is not left mid-prompt
+        let exit = help::canonical(&words[0]) == "exit";
+        if tx.blocking_send(Input { line, value, reply: None }).is_err() || exit {
+            break;
+        }
+    }
+}
+
+fn save_history(editor: &mut DefaultEditor, history: &Path) -> Result<(), Box<dyn Error>> {
+    if let Some(dir) = history.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    editor.save_history(history)?;
+    Ok(())
+}
+
+// As `read_value`, behind a `... ` prompt; the value's lines stay out of the history
+fn read_prompted_value(editor: &mut DefaultEditor) -> Vec<u8> {
+    let mut lines = Vec::new();
+    while let Ok(line) = editor.readline("... ") {
+        if line.is_empty() {
+            break;
+        }
+        lines.push(line);
+    }
+    lines.join("\n").into_bytes()
+}
+
+// The operation count and value size of `benchmark <n> [value_bytes]`
+fn benchmark_args(args: &[String]) -> Result<(usize, usize), DhtError> {
+    let count = match args[2].parse::<usize>() {
+        Ok(count) if count > 0 => count,
+        _ => return Err(DhtError::InvalidCommand(format!("'{}' is not a positive number of operations", args[2]))),
+    };
+    let value_bytes = match args.get(3) {
+        Some(bytes) => bytes.parse().map_err(|_| DhtError::InvalidCommand(format!("'{}' is not a value size in bytes", bytes)))?,
+        None => benchmark::DEFAULT_VALUE_BYTES,
+    };
+    Ok((count, value_bytes))
+}
+
+fn print_benchmark(result: &Benchmark) {
+    outln!("{:<4} {:>6} {:>8} {:>9} {:>9} {:>9} {:>9} {:>10}", "", "ops", "success", "min ms", "median ms", "p95 ms", "max ms", "ops/s");
+    for (name, phase) in [("put", &result.puts), ("get", &result.gets)] {
+        outln!(
+            "{:<4} {:>6} {:>7.1}% {:>9.1} {:>9.1} {:>9.1} {:>9.1} {:>10.1}",
+            name, phase.count(), phase.success_rate() * 100.0, millis(phase.min()), millis(phase.median()),
+            millis(phase.p95()), millis(phase.max()), phase.throughput(),
+        );
+    }
+}
+
+fn phase_json(phase: &Phase) -> Value {
+    json!({
+        "ops": phase.count(), "succeeded": phase.succeeded,
+        "min_ms": millis(phase.min()), "median_ms": millis(phase.median()),
+        "p95_ms": millis(phase.p95()), "max_ms": millis(phase.max()),
+        "elapsed_ms": millis(phase.elapsed), "ops_per_sec": phase.throughput(),
+    })
+}
+
+fn millis(duration: Duration) -> f64 {
+    duration.as_secs_f64() * 1000.0
+}
+
+// Whether the command `words` (without the program name) is a `put` taking its value from stdin
+fn reads_value(words: &[String]) -> bool {
+    match words {
+        [dry, rest @ ..] if dry == "dry" => reads_value(rest),
+        [put, _, value, ..] if help::canonical(put) == "put" && value == "-" => true,
+        [put_cas, value, ..] if put_cas == "put-cas" && value == "-" => true,
+        [put, _, record_type, data, ..] => help::canonical(put) == "put" && DnsRecord::is_type(record_type) && data == "-",
+        _ => false,
+    }
+}
+
+// Read a value from stdin up to a blank line or the end of input, keeping the newlines between its lines
+fn read_value(reader: &mut impl BufRead) -> Vec<u8> {
+    let mut value = Vec::new();
+    let mut line = Vec::new();
+    loop {
+        line.clear();
+        match reader.read_until(b'\n', &mut line) {
+            Ok(0) | Err(_) => break,
+            Ok(_) => {}
+        }
+        let content = line.strip_suffix(b"\n").unwrap_or(&line);
+        let content = content.strip_suffix(b"\r").unwrap_or(content);
+        if content.is_empty() {
+            break;
+        }
+        if !value.is_empty() {
+            value.push(b'\n');
+        }
+        value.extend_from_slice(content);
+    }
+    value
+}
+
+fn parse_multiaddr(addr: &str) -> Result<Multiaddr, DhtError> {
+    addr.parse().map_err(|e| DhtError::InvalidCommand(format!("invalid multiaddr '{}': {}", addr, e)))
+}
+
+// The key the delegation of zone `child` is stored under
+fn delegation_key(child: &str) -> Result<String, DhtError> {
+    let child = dns_record::normalize_key(child).map_err(DhtError::InvalidKey)?;
+    Ok(delegation::record_key(&child))
+}
+
+fn parse_peer_id(peer_id: &str) -> Result<PeerId, DhtError> {
+    peer_id.parse().map_err(|e| DhtError::InvalidCommand(format!("invalid peer id '{}': {}", peer_id, e)))
+}
+
+// What `put` stores and how
+struct PutArgs {
+    value: Vec<u8>,
+    ttl: Option<Duration>,
+    quorum: Quorum,
+    // Overwrite a newer version written elsewhere instead of reporting a conflict
+    force: bool,
+    // Claim the key for this node
+    own: bool,
+}
+
+// Split the arguments of `put` into the value to store, its TTL and quorum, and whether `--force` or `--own` was given
+fn put_args(node: &DhtNode, args: &[String], stdin_value: Option<Vec<u8>>) -> Result<PutArgs, DhtError> {
+    let mut args = args.to_vec();
+    let force = take_switch(&mut args, "--force");
+    let own = take_switch(&mut args, "--own");
+    if args.len() < 4 {
+        return Err(DhtError::InvalidCommand("usage: put <key> <value>|@file|- [ttl_secs] [quorum] [--force|--own]".to_string()));
+    }
+    if force && own {
+        return Err(DhtError::InvalidCommand("--force and --own cannot be combined".to_string()));
+    }
+
+    // `put <name> <type> <data> ...` stores a typed DNS record, `put <key> <value> ...` raw bytes.
+    // Either may be given as `@file` or `-` for stdin; the size limit applies the same
+    let (value, options) = if args.len() > 4 && DnsRecord::is_type(&args[3]) {
+        // SRV and MX data spans several words, or one record per line when read from a file or stdin
+        let fields = DnsRecord::field_count(&args[3]);
+        let (data, rest) = if args[4] == "-" || args[4].starts_with('@') {
+            let data = String::from_utf8(value_arg(&args[4], stdin_value)?)
+                .map_err(|_| DhtError::InvalidCommand("record data must be UTF-8 text".to_string()))?;
+            (data, 5)
+        } else if args.len() >= 4 + fields {
+            (args[4..4 + fields].join(" "), 4 + fields)
+        } else {
+            return Err(DhtError::InvalidCommand(format!("{} data needs {} fields", args[3].to_ascii_uppercase(), fields)));
+        };
+        let records = DnsRecord::parse_all(&args[3], &data).map_err(DhtError::InvalidCommand)?;
+        (DnsRecord::encode_all(&records), &args[rest..])
+    } else {
+        (value_arg(&args[3], stdin_value)?, &args[4..])
+    };
+
+    let (ttl, quorum) = put_options(node, options)?;
+    Ok(PutArgs { value, ttl, quorum, force, own })
+}
+
+// Parse `[ttl_secs] [quorum]`; `all` and `majority` cannot be TTLs, so they may also stand alone
+fn put_options(node: &DhtNode, options: &[String]) -> Result<(Option<Duration>, Quorum), DhtError> {
+    let (ttl, quorum_arg) = match options.first() {
+        Some(ttl) if ttl.parse::<u64>().is_ok() => (ttl.parse().ok().map(Duration::from_secs), options.get(1)),
+        _ => (None, options.first()),
+    };
+    let quorum = match quorum_arg {
+        Some(quorum) => parse_quorum(quorum).map_err(DhtError::InvalidCommand)?,
+        None => node.default_quorum(),
+    };
+    Ok((ttl, quorum))
+}
+
+// The content-addressed key of the value given to `put-cas`, and how to store it
+fn cas_args(node: &DhtNode, args: &[String], stdin_value: Option<Vec<u8>>) -> Result<(String, PutArgs), DhtError> {
+    let value = value_arg(&args[2], stdin_value)?;
+    let (ttl, quorum) = put_options(node, &args[3..])?;
+    Ok((keys::content_name(&value), PutArgs { value, ttl, quorum, force: false, own: false }))
+}
+
+// Store a record the way the switches given to `put` ask for
+async fn put(node: &DhtNode, key: &str, args: PutArgs) -> Result<(), DhtError> {
+    let PutArgs { value, ttl, quorum, force, own } = args;
+    if force {
+        node.force_put(key, value, quorum, ttl).await
+    } else if own {
+        node.claim(key, value, quorum, ttl).await
+    } else {
+        node.put_with_ttl(key, value, quorum, ttl).await
+    }
+}
+
+// With --dry-run, a put or delete becomes `dry put` or `dry delete`
+fn mark_dry(words: &mut Vec<String>) {
+    if words.first().is_some_and(|word| word == "put" || word == "delete") {
+        words.insert(0, "dry".to_string());
+    }
+}
+
+// `dry <command> ...` as the command alone, with `dry` standing in for the program name
+fn undry(args: &[String]) -> Vec<String> {
+    let mut args = args[1..].to_vec();
+    args[1] = help::canonical(&args[1]).to_string();
+    args
+}
+
+// Check a put or delete without carrying it out; a put also returns its parsed arguments
+async fn dry_command(node: &DhtNode, args: &[String], stdin_value: Option<Vec<u8>>) -> Result<(DryRun, Option<PutArgs>), DhtError> {
+    match args[1].as_str() {
+        "put" if args.len() > 3 => {
+            let mut put = put_args(node, args, stdin_value)?;
+            let value = std::mem::take(&mut put.value);
+            let dry_run = node.dry_put(&args[2], value, put.own).await?;
+            Ok((dry_run, Some(put)))
+        }
+        "delete" => Ok((node.dry_delete(&args[2]).await?, None)),
+        _ => Err(DhtError::InvalidCommand("usage: dry put <key> ... | dry delete <key>".to_string())),
+    }
+}
+
+fn print_dry_put(dry_run: &DryRun, put: &PutArgs) {
+    outln!("Dry run, nothing stored or sent: put {}", dry_run.key);
+    outln!("  Record key: {} (hex)", hex(&dry_run.record_key));
+    let replaces = if dry_run.stored { "replacing the local copy" } else { "as a new record" };
+    outln!("  Would be stored in {}, {}", dry_run.store_path.display(), replaces);
+    let signer = dry_run.signer.map_or_else(|| "nobody (signature did not verify)".to_string(), |signer| signer.to_string());
+    outln!(
+        "  Value of {} bytes{}, sealed as version {} in a {} byte envelope signed by {}",
+        dry_run.value_bytes, compressed(dry_run.value_bytes, dry_run.stored_bytes), dry_run.version, dry_run.sealed_bytes, signer,
+    );
+    match (dry_run.owner, put.own) {
+        (Some(_), _) => outln!("  Key owned by this node"),
+        (None, true) => outln!("  Would claim the key for this node"),
+        (None, false) => outln!("  Key not claimed by anyone"),
+    }
+    let ttl = put.ttl.map_or_else(|| "the default TTL".to_string(), |ttl| format!("{}s", ttl.as_secs()));
+    let conflict = if put.force { "overwritten (--force)" } else { "a conflict" };
+    outln!(
+        "  Would be published with quorum {}, expiring after {}; the DHT is read first, and a newer version there is {}",
+        quorum_name(put.quorum), ttl, conflict,
+    );
+}
+
+fn print_dry_delete(dry_run: &DryRun) {
+    outln!("Dry run, nothing removed or sent: delete {}", dry_run.key);
+    outln!("  Record key: {} (hex)", hex(&dry_run.record_key));
+    match (dry_run.stored, dry_run.pinned) {
+        (true, true) => outln!("  Would be removed from {} and unpinned", dry_run.store_path.display()),
+        (true, false) => outln!("  Would be removed from {}", dry_run.store_path.display()),
+        (false, _) => outln!("  Not stored in {}", dry_run.store_path.display()),
+    }
+    outln!("  Peers holding a copy would be told to drop it");
+}
+
+// Whether `describe` was asked to look in the DHT rather than the local store
+fn describe_remote(args: &[String]) -> Result<bool, DhtError> {
+    match args.get(3).map(String::as_str) {
+        None => Ok(false),
+        Some("dht") => Ok(true),
+        Some(_) => Err(DhtError::InvalidCommand("usage: describe <key> [dht]".to_string())),
+    }
+}
+
+fn print_description(description: &RecordDescription) {
+    let source = match description.source {
+        Some(source) if description.cached => format!("cached copy, last fetched from {}", source),
+        Some(source) => format!("copy from {}", source),
+        None => "local copy".to_string(),
+    };
+    outln!("Record: {} ({})", description.key, source);
+    match description.signer {
+        Some(signer) if description.owned => outln!("  Version {}, signed by {}, who owns the key", description.version, signer),
+        Some(signer) => outln!("  Version {}, signed by {}", description.version, signer),
+        None => outln!("  Version {}, unsigned", description.version),
+    }
+    match description.metadata {
+        Some(metadata) => {
+            outln!("  Created {}", since(metadata.created));
+            outln!("  Last updated {}", since(metadata.updated));
+            outln!("  TTL {}s", metadata.ttl_secs);
+        }
+        None => outln!("  No metadata; it was written by an older node"),
+    }
+    match description.expires_in {
+        Some(left) => outln!("  Expires in {}s", left.as_secs()),
+        None => outln!("  Does not expire"),
+    }
+    outln!("  Value of {} bytes{}, in a {} byte envelope", description.value_bytes, compressed(description.value_bytes, description.stored_bytes), description.sealed_bytes);
+}
+
+// Where a value was found, for appending to the line reporting it; nothing for our own copy
+fn from_peer(source: Option<PeerId>) -> String {
+    source.map(|source| format!(" (from {})", source)).unwrap_or_default()
+}
+
+// How a value of `value_bytes` is stored, when compression changed its size
+fn compressed(value_bytes: usize, stored_bytes: usize) -> String {
+    if stored_bytes == value_bytes {
+        return String::new();
+    }
+    format!(" stored compressed in {} bytes", stored_bytes)
+}
+
+// A time in milliseconds since the Unix epoch, as Unix seconds and how long ago that was
+fn since(millis: u64) -> String {
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+    let ago = now.saturating_sub(Duration::from_millis(millis));
+    format!("at {} (Unix time), {}s ago", millis / 1000, ago.as_secs())
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+// A quorum as `put` takes it
+fn quorum_name(quorum: Quorum) -> String {
+    match quorum {
+        Quorum::One => "1".to_string(),
+        Quorum::Majority => "majority".to_string(),
+        Quorum::All => "all".to_string(),
+        Quorum::N(n) => n.to_string(),
+    }
+}
+
+// How the puts of a batch turned out
+struct PutTally {
+    total: usize,
+    // Stored locally, whether or not they reached the DHT
+    stored: usize,
+    // Stored locally but short of their quorum; republishing keeps trying
+    unreplicated: usize,
+    // Keys that were not stored at all
+    rejected: Vec<String>,
+}
+
+// Put every record with the default quorum, keeping at most `put_concurrency` puts in
+// flight so the event loop is not flooded, and reporting progress as they complete
+async fn put_batch(node: &DhtNode, records: Vec<(String, Vec<u8>)>) -> PutTally {
+    let quorum = node.default_quorum();
+    let mut tally = PutTally { total: records.len(), stored: 0, unreplicated: 0, rejected: Vec::new() };
+    let mut outcomes = futures::stream::iter(records)
+        .map(|(key, value)| async move {
+            let result = node.put(&key, value, quorum).await;
+            (key, result)
+        })
+        .buffer_unordered(node.put_concurrency());
+
+    let mut done = 0;
+    while let Some((key, result)) = outcomes.next().await {
+        match result {
+            Ok(()) => tally.stored += 1,
+            Err(DhtError::QuorumFailed { .. } | DhtError::Timeout) => {
+                tally.stored += 1;
+                tally.unreplicated += 1;
+            }
+            Err(e) => {
+                outln!("Skipping record {}: {}", key, e);
+                tally.rejected.push(key);
+            }
+        }
+        done += 1;
+        if done % PROGRESS_INTERVAL == 0 && done < tally.total {
+            outln!("Put {} of {} record(s)...", done, tally.total);
+        }
+    }
+    tally
+}
+
+// Where `resolve` ended up: every name visited in order, and the terminal
+// record's data, or `None` if the last name does not exist, with the peer it came from
+struct Resolution {
+    chain: Vec<String>,
+    answer: Option<String>,
+    source: Option<PeerId>,
+}
+
+// The nearest wildcard holding a record for `name`, which has none of its own, and its record
+async fn wildcard(node: &DhtNode, name: &str) -> Result<Option<(String, Entry)>, DhtError> {
+    for wildcard in dns_record::wildcards(name) {
+        if let Some(entry) = node.get_entry(&wildcard, node.default_quorum()).await? {
+            return Ok(Some((wildcard, entry)));
+        }
+    }
+    Ok(None)
+}
+
+// Follow `name` through its CNAMEs to a terminal record, one lookup per hop,
+// failing on a loop or a chain of more than MAX_CNAME_DEPTH hops. A name with no
+// record of its own is answered by its nearest wildcard, which joins the chain.
+// With a trust anchor, a record not signed through its chain of delegations is an error
+async fn resolve(node: &DhtNode, name: &str) -> Result<Resolution, DhtError> {
+    let mut chain: Vec<String> = Vec::new();
+    let mut name = dns_record::normalize_key(name).map_err(DhtError::InvalidKey)?;
+    // CNAMEs followed so far; a wildcard joins the chain without adding a hop
+    let mut hops = 0;
+    loop {
+        chain.push(name.clone());
+
+        let (owner, entry) = match node.get_entry(&name, node.default_quorum()).await? {
+            Some(entry) => (name.clone(), entry),
+            None => match wildcard(node, &name).await? {
+                Some((wildcard, entry)) => {
+                    chain.push(wildcard.clone());
+                    (wildcard, entry)
+                }
+                None => return Ok(Resolution { chain, answer: None, source: None }),
+            },
+        };
+        // With a trust anchor, every record along the way must be signed by the key of its zone
+        if let Some(anchor) = node.trust_anchor() {
+            delegation::verify(node, anchor, &owner, entry.signer).await?;
+        }
+        let value = &entry.value;
+        let answer = match DnsRecord::decode_all(value) {
+            // A name holding addresses answers with them, as a stub resolver would, ahead of its other records
+            Some(records) => match dns_record::natural(&records) {
+                Natural::Addresses(addrs) => addrs.iter().map(IpAddr::to_string).collect::<Vec<_>>().join(", "),
+                Natural::Alias(target) => {
+                    let target = dns_record::normalize_key(&target).map_err(DhtError::InvalidKey)?;
+                    dns_record::follow_cname(&chain, hops, &target)?;
+                    hops += 1;
+                    name = target;
+                    continue;
+                }
+                // SRV and MX targets come in the order clients should try them
+                Natural::Other => records.iter().map(DnsRecord::data).collect::<Vec<_>>().join(", "),
+            },
+            // Raw values end the chain like any other terminal record
+            None => String::from_utf8_lossy(value).into_owned(),
+        };
+        return Ok(Resolution { chain, answer: Some(answer), source: entry.source });
+    }
+}
+
+// Every typed record of `name` with its remaining TTL and the peer it came
+// from: the name's own records merged with the ones stored beside them under
+// `<name>|<type>`, of that type only. The keys are looked up together, so ones
+// held locally answer at once while the rest are fetched from the DHT. A key
+// that is missing, times out or holds a raw value adds nothing; only when every
+// lookup fails is the error returned
+async fn record_set(node: &DhtNode, name: &str) -> Result<Vec<(DnsRecord, u32, Option<PeerId>)>, DhtError> {
+    let name = dns_record::normalize_key(name).map_err(DhtError::InvalidKey)?;
+    let keys: Vec<(String, Option<&str>)> = std::iter::once((name.clone(), None))
+        .chain(dns_record::RECORD_TYPES.iter().map(|record_type| (dns_record::typed_key(&name, record_type), Some(*record_type))))
+        .collect();
+    let lookups = keys.iter().map(|(key, _)| node.get_entry(key, node.default_quorum()));
+    let mut records: Vec<(DnsRecord, u32, Option<PeerId>)> = Vec::new();
+    let mut failure = None;
+    let mut answered = false;
+    for (found, (_, record_type)) in futures::future::join_all(lookups).await.into_iter().zip(&keys) {
+        let entry = match found {
+            Ok(entry) => {
+                answered = true;
+                entry
+            }
+            Err(e) => {
+                failure.get_or_insert(e);
+                None
+            }
+        };
+        let Some(entry) = entry else {
+            continue;
+        };
+        // Records beside the name belong to its zone as much as its own do
+        if let Some(anchor) = node.trust_anchor() {
+            delegation::verify(node, anchor, &name, entry.signer).await?;
+        }
+        let ttl = dns_record::ttl_secs(entry.expires);
+        for record in DnsRecord::decode_all(&entry.value).unwrap_or_default() {
+            if record_type.is_none_or(|record_type| record.record_type() == record_type)
+                && !records.iter().any(|(known, ..)| *known == record)
+            {
+                records.push((record, ttl, entry.source));
+            }
+        }
+    }
+    match failure {
+        Some(e) if !answered => Err(e),
+        _ => Ok(records),
+    }
+}
+
+// The document `resolve-json` prints for the records `record_set` found
+fn record_set_json(name: &str, records: &[(DnsRecord, u32, Option<PeerId>)]) -> Value {
+    let records: Vec<Value> = records.iter()
+        .map(|(record, ttl, source)| {
+            // Data is plain text here, so TXT needs no quotes around it
+            let data = match record {
+                DnsRecord::Txt(text) => text.clone(),
+                record => record.data(),
+            };
+            json!({ "type": record.record_type(), "data": data, "ttl": ttl, "source": source.map(|source| source.to_string()) })
+        })
+        .collect();
+    json!({ "name": name, "records": records })
+}
+
+// Look `key_string` up and print what is found, following CNAMEs to their
+// target while refusing loops and overly long chains
+async fn lookup(node: &DhtNode, mut key_string: String, quorum: Quorum) -> Result<(), DhtError> {
+    let mut chain: Vec<String> = Vec::new();
+    loop {
+        let Some(Entry { value, source, stale, .. }) = node.get_entry(&key_string, quorum).await? else {
+            outln!("Record not found for key: {}", key_string);
+            return Ok(());
+        };
+        let stale = if stale { " (stale, refreshing)" } else { "" };
+        outln!("Found record: {} => {}{}{}", key_string, dns_record::format_value(&value), from_peer(source), stale);
+
+        // Typed records are read the way a stub resolver would: addresses first, then an alias
+        let target = match DnsRecord::decode_all(&value).map(|records| dns_record::natural(&records)) {
+            Some(Natural::Addresses(addrs)) => {
+                let name = chain.first().unwrap_or(&key_string);
+                outln!("Address: {} -> {}", name, addrs.iter().map(IpAddr::to_string).collect::<Vec<_>>().join(", "));
+                return Ok(());
+            }
+            Some(Natural::Alias(target)) => target,
+            Some(Natural::Other) | None => return Ok(()),
+        };
+        // Every name in the chain but the first was reached through one CNAME
+        chain.push(key_string);
+        if let Err(e) = dns_record::follow_cname(&chain, chain.len() - 1, &target) {
+            outln!("Error: {}", e);
+            return Ok(());
+        }
+        key_string = target;
+    }
+}
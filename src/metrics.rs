@@ -0,0 +1,129 @@
+//! Prometheus metrics describing the node's activity, served over HTTP.
+
+use axum::{extract::State, routing::get, Router};
+use prometheus::{exponential_buckets, Encoder, Histogram, HistogramOpts, IntCounter, IntGauge, Registry, TextEncoder};
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+/// Counters and gauges updated by the command handlers and the event loop.
+pub struct Metrics {
+    registry: Registry,
+    pub puts: IntCounter,
+    pub gets: IntCounter,
+    pub local_hits: IntCounter,
+    pub cache_hits: IntCounter,
+    pub dht_misses: IntCounter,
+    pub queries_issued: IntCounter,
+    pub queries_succeeded: IntCounter,
+    pub queries_failed: IntCounter,
+    pub unauthorized_writes: IntCounter,
+    pub rejected_puts: IntCounter,
+    pub readonly_refusals: IntCounter,
+    pub rejected_records: IntCounter,
+    pub inbound_accepted: IntCounter,
+    pub inbound_refused: IntCounter,
+    pub throttled_peers: IntCounter,
+    pub throttled_requests: IntCounter,
+    pub event_log_dropped: IntCounter,
+    pub get_duration: Histogram,
+    pub connected_peers: IntGauge,
+    pub stored_records: IntGauge,
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        let registry = Registry::new_custom(Some("dht".to_string()), None)
+            .expect("static registry prefix is valid");
+
+        let counter = |name: &str, help: &str| {
+            let counter = IntCounter::new(name, help).expect("static metric definition is valid");
+            registry.register(Box::new(counter.clone())).expect("metric names are unique");
+            counter
+        };
+        let puts = counter("puts_total", "Put commands issued");
+        let gets = counter("gets_total", "Get commands issued");
+        let local_hits = counter("local_hits_total", "Gets answered from the local store");
+        let cache_hits = counter("result_cache_hits_total", "Gets answered from the cache of earlier DHT lookups");
+        let dht_misses = counter(
+            "cache_misses_total",
+            "Gets that missed the local store and the result cache, or found a stale copy, and queried the DHT",
+        );
+        let queries_issued = counter("queries_issued_total", "DHT get/put queries started");
+        let queries_succeeded = counter("queries_succeeded_total", "DHT get/put queries that succeeded");
+        let queries_failed = counter("queries_failed_total", "DHT get/put queries that failed");
+        let unauthorized_writes = counter("unauthorized_writes_total", "Writes refused because another peer owns the key");
+        let rejected_puts = counter("rejected_puts_total", "Puts refused because the value is too large or the local store is full");
+        let readonly_refusals = counter(
+            "readonly_refusals_total",
+            "Writes refused because the node is read-only, local ones and records or providers pushed by peers",
+        );
+        let rejected_records = counter("rejected_records_total", "Records pushed by peers that the record validator refused");
+        let inbound_accepted = counter("inbound_records_accepted_total", "Records pushed by peers that passed every check and were stored");
+        let inbound_refused = counter("inbound_records_refused_total", "Records pushed by peers that failed a check or could not be stored");
+        let throttled_peers = counter("throttled_peers_total", "Times a peer went over its inbound Kademlia request limit");
+        let throttled_requests = counter("throttled_requests_total", "Inbound Kademlia requests refused from peers over their limit");
+        let event_log_dropped = counter("event_log_dropped_total", "Event log lines dropped because the writer fell behind");
+
+        // 5 ms up to about 20 s, doubling
+        let buckets = exponential_buckets(0.005, 2.0, 13).expect("static buckets are valid");
+        let get_duration = Histogram::with_opts(
+            HistogramOpts::new("get_duration_seconds", "Time for a get to resolve from the DHT, across retries").buckets(buckets),
+        )
+        .expect("static metric definition is valid");
+        registry.register(Box::new(get_duration.clone())).expect("metric names are unique");
+
+        let gauge = |name: &str, help: &str| {
+            let gauge = IntGauge::new(name, help).expect("static metric definition is valid");
+            registry.register(Box::new(gauge.clone())).expect("metric names are unique");
+            gauge
+        };
+        let connected_peers = gauge("connected_peers", "Peers with at least one open connection");
+        let stored_records = gauge("stored_records", "Records held in the local store");
+
+        Metrics {
+            registry,
+            puts,
+            gets,
+            local_hits,
+            cache_hits,
+            dht_misses,
+            queries_issued,
+            queries_succeeded,
+            queries_failed,
+            unauthorized_writes,
+            rejected_puts,
+            readonly_refusals,
+            rejected_records,
+            inbound_accepted,
+            inbound_refused,
+            throttled_peers,
+            throttled_requests,
+            event_log_dropped,
+            get_duration,
+            connected_peers,
+            stored_records,
+        }
+    }
+}
+
+impl Metrics {
+    /// Render all metrics in the Prometheus text exposition format.
+    pub fn encode(&self) -> String {
+        let mut buffer = Vec::new();
+        TextEncoder::new()
+            .encode(&self.registry.gather(), &mut buffer)
+            .expect("encoding into a Vec cannot fail");
+        String::from_utf8(buffer).expect("Prometheus text format is UTF-8")
+    }
+}
+
+/// Serve `GET /metrics` on `addr` until the process exits.
+pub async fn serve(addr: SocketAddr, metrics: Arc<Metrics>) -> std::io::Result<()> {
+    let app = Router::new()
+        .route("/metrics", get(|State(metrics): State<Arc<Metrics>>| async move { metrics.encode() }))
+        .with_state(metrics);
+
+    let listener = tokio::net::TcpListener::bind(addr).await?;
+    tracing::info!("Metrics available on http://{}/metrics", listener.local_addr()?);
+    axum::serve(listener, app).await
+}
@@ -0,0 +1,844 @@
+//! The public handle for driving a node from other code.
+
+use crate::config::{Config, MAX_GET_RETRIES, MAX_TIMEOUT_SECS};
+use crate::delegation::{self, Delegation, TrustAnchor};
+use crate::dns_record::normalize_key;
+use crate::envelope::Metadata;
+use crate::error::DhtError;
+use crate::event_log::EventLog;
+use crate::event_loop::{EventLoop, PING_INTERVAL, PING_TIMEOUT};
+use crate::keys::{self, KeyEncoding};
+use crate::metrics::Metrics;
+use crate::validator::RecordValidator;
+use libp2p::{kad::Quorum, Multiaddr, PeerId};
+use serde_json::{json, Value};
+use std::fmt;
+use std::future::Future;
+use std::path::PathBuf;
+use std::sync::{Arc, RwLock, RwLockReadGuard};
+use std::time::{Duration, Instant};
+use tokio::sync::{broadcast, mpsc, oneshot};
+use tracing::warn;
+
+// Events buffered per subscriber before the slowest one starts missing them
+const EVENT_CAPACITY: usize = 256;
+
+// Extra time given to the event loop beyond the Kademlia query timeout, so a
+// DHT timeout is normally reported by the query itself
+const REPLY_GRACE: Duration = Duration::from_secs(1);
+
+/// A change to the local store or a resolved lookup, as reported by [`DhtNode::subscribe`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RecordEvent {
+    /// A record was stored locally, by a `put` on this node or one pushed by a peer.
+    Put { key: String },
+    /// A record was deleted from the local store, or removed once expired.
+    Removed { key: String },
+    /// A `get` or `get_all` found a value, locally or in the DHT; `source` is
+    /// the peer it came from, or `None` for this node's own copy.
+    Resolved { key: String, value: Vec<u8>, source: Option<PeerId> },
+}
+
+/// A value found by [`DhtNode::get_entry`], with the time it stops being valid.
+#[derive(Debug, Clone)]
+pub struct Entry {
+    pub value: Vec<u8>,
+    /// The version its writer gave it; higher is newer. Unsigned records, and
+    /// ones written before records were versioned, are version 0.
+    pub version: u64,
+    pub expires: Option<Instant>,
+    /// Who signed it; `None` for unsigned values.
+    pub signer: Option<PeerId>,
+    /// The peer it was found on, also for a value served from the cache of
+    /// earlier lookups; `None` for this node's own copy.
+    pub source: Option<PeerId>,
+    /// Whether it had expired and was served within the `swr` grace window,
+    /// while a lookup in the background refreshes it.
+    pub stale: bool,
+}
+
+/// A record held in the local store, as reported by [`DhtNode::records`].
+#[derive(Debug, Clone)]
+pub struct RecordSummary {
+    pub key: Vec<u8>,
+    pub size: usize,
+    /// Whether the record is pinned, and so republished for as long as this node runs.
+    pub pinned: bool,
+}
+
+/// What a `put` or `delete` would do, as reported by [`DhtNode::dry_put`] and [`DhtNode::dry_delete`].
+#[derive(Debug, Clone)]
+pub struct DryRun {
+    /// The key after normalization.
+    pub key: String,
+    /// The record key it is stored under, after any key hashing.
+    pub record_key: Vec<u8>,
+    /// The local store file the record is saved to.
+    pub store_path: PathBuf,
+    /// Whether a record is stored locally under the key now.
+    pub stored: bool,
+    pub pinned: bool,
+    /// The peer the key is claimed by, if any.
+    pub owner: Option<PeerId>,
+    /// For a put, the version from what this node has seen. The put itself reads
+    /// the DHT first, which may turn up a newer version and raise it or conflict.
+    pub version: u64,
+    /// For a put, the size of the value, of the value as stored, which is
+    /// smaller if it is compressed, and of the signed envelope holding it.
+    pub value_bytes: usize,
+    pub stored_bytes: usize,
+    pub sealed_bytes: usize,
+    /// For a put, the signer the sealed envelope verified as, which is this node.
+    pub signer: Option<PeerId>,
+    /// For a put, whether the record claims the key for this node.
+    pub owned: bool,
+}
+
+/// What is known about one copy of a record, as reported by [`DhtNode::describe`].
+#[derive(Debug, Clone)]
+pub struct RecordDescription {
+    pub key: String,
+    /// The peer the copy came from, or `None` for this node's own store.
+    pub source: Option<PeerId>,
+    /// Whether the copy is the one an earlier `get` fetched from `source` and
+    /// cached, described in place of a local copy this node does not store.
+    pub cached: bool,
+    pub version: u64,
+    /// The peer whose key signed the record, and whether it claims the key as its owner.
+    /// Unsigned records have no signer.
+    pub signer: Option<PeerId>,
+    pub owned: bool,
+    /// The metadata its writer signed; envelopes from older nodes carry none.
+    pub metadata: Option<Metadata>,
+    /// Time left before the copy expires, if it expires at all.
+    pub expires_in: Option<Duration>,
+    /// The size of the value, of the value as stored, which is smaller if it
+    /// is compressed, and of the signed envelope holding it.
+    pub value_bytes: usize,
+    pub stored_bytes: usize,
+    pub sealed_bytes: usize,
+}
+
+/// A peer we are connected to or can route to, as reported by [`DhtNode::peers`].
+#[derive(Debug, Clone)]
+pub struct PeerInfo {
+    pub peer_id: PeerId,
+    /// Remote address of each open connection when connected, otherwise the routing table addresses.
+    pub addrs: Vec<Multiaddr>,
+    /// How long each connection in `addrs` has been open; empty when not connected.
+    pub ages: Vec<Duration>,
+    pub connected: bool,
+    /// Whether the peer is in the Kademlia routing table.
+    pub routable: bool,
+    /// Rolling average round-trip time, once the peer has answered a ping.
+    pub rtt: Option<Duration>,
+}
+
+/// How one open connection is secured, as reported by [`DhtNode::security`].
+#[derive(Debug, Clone)]
+pub struct ConnectionSecurity {
+    pub peer_id: PeerId,
+    pub addr: Multiaddr,
+    /// The protocol that authenticated and encrypted the connection, e.g. `/noise`.
+    pub security: &'static str,
+    /// The stream multiplexer on top of it, e.g. `/yamux/1.0.0`.
+    pub muxer: &'static str,
+    /// Whether the connection also passed the pre-shared-key handshake of a private network.
+    pub private_network: bool,
+}
+
+/// A non-empty k-bucket of the Kademlia routing table, as reported by [`DhtNode::buckets`].
+#[derive(Debug, Clone)]
+pub struct BucketInfo {
+    /// Bucket `i` holds peers whose XOR distance from us lies in `[2^i, 2^(i+1))`.
+    pub index: u32,
+    pub peers: Vec<PeerInfo>,
+}
+
+/// The peers a [`DhtNode::find_node`] lookup found closest to its target.
+#[derive(Debug, Clone)]
+pub struct ClosestPeers {
+    /// Nearest first, each with the bucket index of its XOR distance to the target as in
+    /// [`BucketInfo::index`]; `None` when the peer is the target itself.
+    pub peers: Vec<(PeerId, Option<u32>)>,
+    /// Peers the lookup asked and how many answered. Kademlia asks several peers at once
+    /// rather than hop by hop, so this is the nearest it has to a hop count.
+    pub asked: u32,
+    pub answered: u32,
+    pub duration: Option<Duration>,
+    /// Whether the lookup timed out, leaving `peers` what it had found by then.
+    pub timed_out: bool,
+}
+
+/// Whether other nodes can dial this one, as last determined by AutoNAT probes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Reachability {
+    Public,
+    /// Probes failed, most likely because the node is behind NAT or a firewall.
+    Private,
+    /// Not enough probes have completed yet.
+    Unknown,
+}
+
+impl fmt::Display for Reachability {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Reachability::Public => write!(f, "public"),
+            Reachability::Private => write!(f, "private"),
+            Reachability::Unknown => write!(f, "unknown"),
+        }
+    }
+}
+
+/// Whether DHT operations can reach the network at all.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NetworkStatus {
+    /// Listening and connected to at least one peer.
+    Online,
+    /// No listener is bound or no peer is connected, so the node works as a
+    /// local key-value store alone: puts stay local and gets only find local copies.
+    Offline,
+}
+
+impl NetworkStatus {
+    pub(crate) fn of(listening: bool, connected_peers: usize) -> Self {
+        if listening && connected_peers > 0 { NetworkStatus::Online } else { NetworkStatus::Offline }
+    }
+}
+
+impl fmt::Display for NetworkStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            NetworkStatus::Online => write!(f, "online"),
+            NetworkStatus::Offline => write!(f, "offline"),
+        }
+    }
+}
+
+/// Addresses other nodes can reach us on, as reported by [`DhtNode::addrs`].
+///
+/// Each ends in `/p2p/<peer_id>`, ready to pass to `dial` or `bootstrap` on another node.
+#[derive(Debug, Clone)]
+pub struct NodeAddrs {
+    pub reachability: Reachability,
+    /// Addresses our listeners are bound to.
+    pub listen: Vec<Multiaddr>,
+    /// Listen addresses left out of `listen` in public-only mode, such as loopback and private ones.
+    pub private: Vec<Multiaddr>,
+    /// Addresses confirmed reachable from outside, for example behind NAT.
+    pub external: Vec<Multiaddr>,
+}
+
+/// A snapshot of the node's health, as reported by [`DhtNode::stats`].
+#[derive(Debug, Clone)]
+pub struct NodeStats {
+    pub peer_id: PeerId,
+    pub connected_peers: usize,
+    pub records: usize,
+    /// Total size of the stored values in bytes.
+    pub record_bytes: usize,
+    pub listen_addrs: usize,
+    pub reachability: Reachability,
+    pub status: NetworkStatus,
+    /// DHT get/put queries started, and how many of them succeeded or failed, since startup.
+    pub queries_issued: u64,
+    pub queries_succeeded: u64,
+    pub queries_failed: u64,
+    /// Gets answered from the local store, from the cache of earlier lookups, and by querying the DHT.
+    pub local_hits: u64,
+    pub cache_hits: u64,
+    pub dht_lookups: u64,
+}
+
+/// Whether the node is up and connected, as reported by [`DhtNode::health`].
+#[derive(Debug, Clone)]
+pub struct Health {
+    /// Whether at least one listener is bound.
+    pub listening: bool,
+    pub connected_peers: usize,
+    /// Peers in the Kademlia routing table.
+    pub routing_table_peers: usize,
+    /// Time since the event loop started.
+    pub uptime: Duration,
+}
+
+impl Health {
+    /// The node is alive once it is listening.
+    pub fn healthy(&self) -> bool {
+        self.listening
+    }
+
+    /// Whether the node can reach the DHT or only serves its local store.
+    pub fn status(&self) -> NetworkStatus {
+        NetworkStatus::of(self.listening, self.connected_peers)
+    }
+
+    /// The node can serve lookups once it also has a connected peer and a
+    /// non-empty routing table.
+    pub fn ready(&self) -> bool {
+        self.healthy() && self.connected_peers > 0 && self.routing_table_peers > 0
+    }
+}
+
+// A request for the event loop to carry out; each carries the channel its answer is sent on
+pub(crate) enum Command {
+    Put {
+        key: String,
+        value: Vec<u8>,
+        quorum: Quorum,
+        ttl: Option<Duration>,
+        // Overwrite even a newer version than this node has seen
+        force: bool,
+        // Claim the key for this node, so other peers may no longer write it
+        claim: bool,
+        reply: oneshot::Sender<Result<(), DhtError>>,
+    },
+    // `remote` skips the local store and cache, so the answer always comes from other peers
+    Get { key: String, quorum: Quorum, remote: bool, reply: oneshot::Sender<Result<Option<Entry>, DhtError>> },
+    GetAll { key: String, reply: oneshot::Sender<Result<Vec<Entry>, DhtError>> },
+    Watch { key: String, updates: mpsc::Sender<Option<Vec<u8>>>, reply: oneshot::Sender<()> },
+    Unwatch { key: String, reply: oneshot::Sender<bool> },
+    Delete { key: String, reply: oneshot::Sender<bool> },
+    DryPut { key: String, value: Vec<u8>, claim: bool, reply: oneshot::Sender<Result<DryRun, DhtError>> },
+    DryDelete { key: String, reply: oneshot::Sender<DryRun> },
+    Describe { key: String, remote: bool, reply: oneshot::Sender<Result<Option<RecordDescription>, DhtError>> },
+    Pin { key: String, reply: oneshot::Sender<bool> },
+    Unpin { key: String, reply: oneshot::Sender<bool> },
+    Records { reply: oneshot::Sender<Vec<RecordSummary>> },
+    Expired { reply: oneshot::Sender<Vec<RecordSummary>> },
+    Entries { reply: oneshot::Sender<Vec<(Vec<u8>, Entry)>> },
+    Provide { key: String, reply: oneshot::Sender<Result<(), DhtError>> },
+    Providers { key: String, reply: oneshot::Sender<Result<Vec<PeerId>, DhtError>> },
+    // A peer id in base58, or a key that is looked up as its record key
+    FindNode { target: String, reply: oneshot::Sender<Result<ClosestPeers, DhtError>> },
+    Bootstrap { addr: Multiaddr, peer_id: PeerId, reply: oneshot::Sender<Result<(), DhtError>> },
+    Dial { addr: Multiaddr, reply: oneshot::Sender<Result<(), DhtError>> },
+    Peers { reply: oneshot::Sender<Vec<PeerInfo>> },
+    Security { reply: oneshot::Sender<Vec<ConnectionSecurity>> },
+    Ping { peer_id: PeerId, reply: oneshot::Sender<Result<Duration, DhtError>> },
+    Buckets { reply: oneshot::Sender<Vec<BucketInfo>> },
+    Addrs { reply: oneshot::Sender<NodeAddrs> },
+    Stats { reply: oneshot::Sender<NodeStats> },
+    Health { reply: oneshot::Sender<Health> },
+    // Apply the shared settings again after `config set` changed them
+    Reconfigure { reply: oneshot::Sender<()> },
+    Shutdown { reply: oneshot::Sender<()> },
+}
+
+// The settings that can change while the node runs, shared by every handle and the event loop
+pub(crate) struct Settings {
+    pub(crate) config: Config,
+    // `config.default_quorum`, parsed
+    pub(crate) default_quorum: Quorum,
+}
+
+impl Settings {
+    // How long one attempt of a `get` waits, and how many times it is re-issued after timing out
+    pub(crate) fn get_attempts(&self) -> (Duration, u32) {
+        let most = Duration::from_secs(MAX_TIMEOUT_SECS);
+        (self.config.get_timeout().min(most), self.config.get_retries.min(MAX_GET_RETRIES))
+    }
+
+    // How long a `put` may take: it reads the current version before writing, so it may take two queries
+    pub(crate) fn put_timeout(&self) -> Duration {
+        self.config.query_timeout().saturating_mul(2)
+    }
+}
+
+pub(crate) fn read_settings(settings: &RwLock<Settings>) -> RwLockReadGuard<'_, Settings> {
+    settings.read().unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+/// Handle to a DHT node.
+///
+/// `DhtNode` only sends requests; the swarm itself lives in the [`EventLoop`]
+/// returned alongside it, which must be driven with [`EventLoop::run`] for
+/// any request to complete. Handles are cheap to clone and every clone talks
+/// to the same node.
+///
+/// Values are signed with the node's key on `put` and verified on `get`.
+/// Keys are treated as DNS names: every method normalizes them with
+/// [`normalize_key`], so `Example.COM.` and `example.com` are the same key,
+/// and rejects invalid names with [`DhtError::InvalidKey`].
+#[derive(Clone)]
+pub struct DhtNode {
+    commands: mpsc::Sender<Command>,
+    local_peer_id: PeerId,
+    metrics: Arc<Metrics>,
+    events: broadcast::Sender<RecordEvent>,
+    // Longest a provider request waits for the network before giving up
+    query_timeout: Duration,
+    // The settings `config set` changes, shared with the event loop
+    settings: Arc<RwLock<Settings>>,
+    put_concurrency: usize,
+    trust_anchor: Option<TrustAnchor>,
+    readonly: bool,
+    // How keys given to requests are written
+    key_encoding: KeyEncoding,
+    // Where puts, gets and deletes are recorded, along with the event loop's queries and connections
+    event_log: EventLog,
+}
+
+impl DhtNode {
+    /// Build a node from `config`: start listening, restore persisted records
+    /// and begin bootstrapping into the configured peers.
+    ///
+    /// Must be called from within a Tokio runtime.
+    pub async fn new(config: &Config) -> Result<(DhtNode, EventLoop), DhtError> {
+        let validator = config.suffix_validator().map_err(|e| DhtError::Setup(e.to_string()))?;
+        Self::build(config, validator.map(|validator| Arc::new(validator) as Arc<dyn RecordValidator>)).await
+    }
+
+    /// Like [`new`](DhtNode::new), but every record this node stores, put
+    /// locally or pushed by a peer, must first pass `validator`, which takes
+    /// the place of any configured `allow_suffixes`.
+    pub async fn with_validator(config: &Config, validator: impl RecordValidator + 'static) -> Result<(DhtNode, EventLoop), DhtError> {
+        Self::build(config, Some(Arc::new(validator))).await
+    }
+
+    async fn build(config: &Config, validator: Option<Arc<dyn RecordValidator>>) -> Result<(DhtNode, EventLoop), DhtError> {
+        let default_quorum = config.quorum().map_err(|e| DhtError::Setup(e.to_string()))?;
+        let trust_anchor = config.trust_anchor().map_err(|e| DhtError::Setup(e.to_string()))?;
+        let key_encoding = config.key_encoding().map_err(|e| DhtError::Setup(e.to_string()))?;
+        let (commands, command_rx) = mpsc::channel(100);
+        let metrics = Arc::new(Metrics::default());
+        let (events, _) = broadcast::channel(EVENT_CAPACITY);
+        let event_log = match &config.event_log {
+            Some(path) => EventLog::open(path, config.event_log_max_bytes, config.event_log_keep, metrics.event_log_dropped.clone())
+                .map_err(|e| DhtError::Setup(format!("event log {}: {}", path.display(), e)))?,
+            None => EventLog::default(),
+        };
+        let settings = Arc::new(RwLock::new(Settings { config: config.clone(), default_quorum }));
+        let event_loop = EventLoop::new(config, command_rx, metrics.clone(), events.clone(), event_log.clone(), validator, settings.clone()).await?;
+        event_log.record("start", json!({ "peer_id": event_loop.local_peer_id().to_string() }));
+
+        let node = DhtNode {
+            commands,
+            local_peer_id: event_loop.local_peer_id(),
+            metrics,
+            events,
+            query_timeout: config.query_timeout() + REPLY_GRACE,
+            settings,
+            put_concurrency: config.put_concurrency.max(1),
+            trust_anchor,
+            readonly: config.readonly,
+            key_encoding,
+            event_log,
+        };
+        Ok((node, event_loop))
+    }
+
+    pub fn local_peer_id(&self) -> PeerId {
+        self.local_peer_id
+    }
+
+    /// The quorum used when a request does not name one, from the `default_quorum` setting.
+    pub fn default_quorum(&self) -> Quorum {
+        read_settings(&self.settings).default_quorum
+    }
+
+    /// The node's settings, with any changes made by [`set_config`](DhtNode::set_config).
+    pub fn config(&self) -> Config {
+        read_settings(&self.settings).config.clone()
+    }
+
+    /// Change one of the [`RUNTIME_SETTINGS`](crate::config::RUNTIME_SETTINGS) in place; requests started
+    /// from now on use the new value. Other settings fail with
+    /// [`DhtError::InvalidCommand`] saying they need a restart.
+    ///
+    /// The node only applies what it uses itself: a caller reading `log_level`
+    /// or the command rate limits from [`config`](DhtNode::config) applies those.
+    pub async fn set_config(&self, key: &str, value: &str) -> Result<(), DhtError> {
+        {
+            let mut settings = self.settings.write().unwrap_or_else(|poisoned| poisoned.into_inner());
+            let mut config = settings.config.clone();
+            config.set(key, value).map_err(|e| DhtError::InvalidCommand(e.to_string()))?;
+            settings.default_quorum = config.quorum().map_err(|e| DhtError::InvalidCommand(e.to_string()))?;
+            settings.config = config;
+        }
+        self.request(|reply| Command::Reconfigure { reply }).await
+    }
+
+    /// How many puts a batch keeps in flight at once, from the `put_concurrency` setting.
+    pub fn put_concurrency(&self) -> usize {
+        self.put_concurrency
+    }
+
+    /// The zone and key that `resolve` checks records against, from the `trust_anchor` setting.
+    pub fn trust_anchor(&self) -> Option<&TrustAnchor> {
+        self.trust_anchor.as_ref()
+    }
+
+    /// Counters and gauges describing the node's activity.
+    pub fn metrics(&self) -> Arc<Metrics> {
+        self.metrics.clone()
+    }
+
+    /// Receive a [`RecordEvent`] whenever the local store changes or a lookup resolves.
+    ///
+    /// Only events after the call are delivered. A subscriber that falls more
+    /// than a few hundred events behind skips the oldest ones and sees
+    /// [`RecvError::Lagged`](broadcast::error::RecvError::Lagged).
+    ///
+    /// Every record a peer pushes that this node stores is reported as a
+    /// [`RecordEvent::Put`], like a local put.
+    pub fn subscribe(&self) -> broadcast::Receiver<RecordEvent> {
+        self.events.subscribe()
+    }
+
+    /// Store `value` under `key` locally and publish it to the DHT, using the configured default record TTL.
+    ///
+    /// Resolves once `quorum` peers hold a copy. On [`DhtError::QuorumFailed`]
+    /// or [`DhtError::Timeout`] the record is still stored locally and
+    /// republished later.
+    pub async fn put(&self, key: &str, value: Vec<u8>, quorum: Quorum) -> Result<(), DhtError> {
+        self.put_with_ttl(key, value, quorum, None).await
+    }
+
+    /// Like [`put`](DhtNode::put), but the record expires after `ttl` when given.
+    ///
+    /// The DHT is read first so the new record gets a version above any
+    /// existing one. If that version is newer than the one this node last
+    /// stored or read, the put fails with [`DhtError::Conflict`] instead.
+    pub async fn put_with_ttl(&self, key: &str, value: Vec<u8>, quorum: Quorum, ttl: Option<Duration>) -> Result<(), DhtError> {
+        self.put_command(key, value, quorum, ttl, false, false).await
+    }
+
+    /// Like [`put_with_ttl`](DhtNode::put_with_ttl), but a newer version in the
+    /// DHT is overwritten rather than reported as a conflict.
+    pub async fn force_put(&self, key: &str, value: Vec<u8>, quorum: Quorum, ttl: Option<Duration>) -> Result<(), DhtError> {
+        self.put_command(key, value, quorum, ttl, true, false).await
+    }
+
+    /// Like [`put_with_ttl`](DhtNode::put_with_ttl), but the record also claims
+    /// `key` for this node. The first claim a node sees for a key wins: from
+    /// then on it refuses writes to the key not signed by the owner with
+    /// [`DhtError::Unauthorized`], and skips such copies when reading.
+    ///
+    /// Ownership is enforced by nodes running this code, on the records they
+    /// store and serve. Other nodes may still store and return overwrites, so
+    /// the claim is advisory beyond them. Puts by the owner keep the claim.
+    pub async fn claim(&self, key: &str, value: Vec<u8>, quorum: Quorum, ttl: Option<Duration>) -> Result<(), DhtError> {
+        self.put_command(key, value, quorum, ttl, false, true).await
+    }
+
+    /// Hand zone `child` to `key`, storing a [`Delegation`] signed by this
+    /// node, which claims its key so only this node can change it. With a
+    /// trust anchor configured this node must hold the key that signs
+    /// `parent`, or resolvers following the chain would pass the delegation over.
+    ///
+    /// Like a put, a [`DhtError::QuorumFailed`] still leaves the delegation stored locally.
+    pub async fn delegate(&self, parent: &str, child: &str, key: PeerId) -> Result<(), DhtError> {
+        let delegation = Delegation::new(parent, child, key)?;
+        if let Some(anchor) = &self.trust_anchor {
+            let (zone, signer) = delegation::zone_key(self, anchor, &delegation.parent).await?;
+            if signer != self.local_peer_id {
+                return Err(DhtError::Untrusted {
+                    name: delegation.parent.clone(),
+                    reason: format!("zone {} is signed by {}, not by this node", zone, signer),
+                });
+            }
+        }
+        let record_key = delegation::record_key(&delegation.child);
+        self.claim(&record_key, delegation.encode(), self.default_quorum(), None).await
+    }
+
+    /// Look `key` up locally, falling back to the DHT, with the default quorum.
+    ///
+    /// A key the network does not know resolves to `Ok(None)` once the query
+    /// finishes; [`DhtError::Timeout`] means no answer arrived within the
+    /// configured `get_timeout_secs`, after `get_retries` further attempts.
+    pub async fn get(&self, key: &str) -> Result<Option<Vec<u8>>, DhtError> {
+        self.get_with_quorum(key, self.default_quorum()).await
+    }
+
+    /// Like [`get`](DhtNode::get), but the value is only returned once `quorum`
+    /// peers have returned a valid copy, the local store counting as one.
+    ///
+    /// Above [`Quorum::One`] the DHT is always queried. If the query finishes
+    /// with fewer copies the result is [`DhtError::QuorumFailed`].
+    pub async fn get_with_quorum(&self, key: &str, quorum: Quorum) -> Result<Option<Vec<u8>>, DhtError> {
+        Ok(self.get_entry(key, quorum).await?.map(|entry| entry.value))
+    }
+
+    /// Like [`get_with_quorum`](DhtNode::get_with_quorum), but also reports when the value expires.
+    pub async fn get_entry(&self, key: &str, quorum: Quorum) -> Result<Option<Entry>, DhtError> {
+        self.get_command(key, quorum, false).await
+    }
+
+    /// Like [`get`](DhtNode::get), but only other peers' copies count: this
+    /// node's own copy and its cache are passed over, so the DHT is always
+    /// queried, as when measuring lookups.
+    pub async fn get_remote(&self, key: &str) -> Result<Option<Vec<u8>>, DhtError> {
+        let entry = self.get_command(key, self.default_quorum(), true).await?;
+        Ok(entry.map(|entry| entry.value))
+    }
+
+    /// Every distinct valid value the DHT returns for `key` before the lookup
+    /// ends, the local copy included, in the order they were found.
+    ///
+    /// Peers may hold different values under one name, for example several A
+    /// records; identical copies are reported once. A key nobody holds yields
+    /// an empty list.
+    pub async fn get_all(&self, key: &str) -> Result<Vec<Entry>, DhtError> {
+        let key = self.normalize(key)?;
+        bounded(self.query_timeout, self.request(|reply| Command::GetAll { key, reply })).await
+    }
+
+    /// Look `key` up every `watch_interval_secs` and report each time its value changes.
+    ///
+    /// The first lookup's value is always reported; after that only changes
+    /// are, with `None` once the key is no longer found. Watching a key again
+    /// replaces the earlier watch, whose receiver then closes, as it does
+    /// after [`unwatch`](DhtNode::unwatch).
+    pub async fn watch(&self, key: &str) -> Result<mpsc::Receiver<Option<Vec<u8>>>, DhtError> {
+        let key = self.normalize(key)?;
+        let (updates, receiver) = mpsc::channel(16);
+        self.request(|reply| Command::Watch { key, updates, reply }).await?;
+        Ok(receiver)
+    }
+
+    /// Stop watching `key`, returning whether it was watched.
+    pub async fn unwatch(&self, key: &str) -> Result<bool, DhtError> {
+        let key = self.normalize(key)?;
+        self.request(|reply| Command::Unwatch { key, reply }).await
+    }
+
+    /// Remove `key` from the local store and stop republishing it, returning whether it was stored.
+    ///
+    /// Peers holding a copy are told to drop it; any that miss the notice
+    /// keep serving theirs until its TTL runs out.
+    pub async fn delete(&self, key: &str) -> Result<bool, DhtError> {
+        let key = self.normalize(key)?;
+        let started = Instant::now();
+        let result = match self.writable("delete", &key) {
+            Ok(()) => self.request(|reply| Command::Delete { key: key.clone(), reply }).await,
+            Err(e) => Err(e),
+        };
+        self.log_operation("delete", &key, started, &result, |removed| json!({ "removed": removed }));
+        result
+    }
+
+    /// Check a put without making it: the key is normalized and mapped to its
+    /// record key, and the value checked against the size limit, the key's
+    /// owner and the room in the local store, then sealed in a signed envelope
+    /// that is verified and thrown away. Fails with the error the put would.
+    ///
+    /// Nothing is stored or sent to the network.
+    pub async fn dry_put(&self, key: &str, value: Vec<u8>, claim: bool) -> Result<DryRun, DhtError> {
+        let key = self.normalize(key)?;
+        self.writable("put", &key)?;
+        self.request(|reply| Command::DryPut { key, value, claim, reply }).await?
+    }
+
+    /// Report what [`delete`](DhtNode::delete) would remove, without removing it.
+    pub async fn dry_delete(&self, key: &str) -> Result<DryRun, DhtError> {
+        let key = self.normalize(key)?;
+        self.writable("delete", &key)?;
+        self.request(|reply| Command::DryDelete { key, reply }).await
+    }
+
+    /// Describe the record stored under `key`: its version, signer, size, expiry and the
+    /// metadata in its envelope. The local copy is described unless `remote` is set, in
+    /// which case the newest valid copy a DHT lookup turns up is. Without a local copy,
+    /// a value an earlier `get` cached is described along with the peer it came from.
+    pub async fn describe(&self, key: &str, remote: bool) -> Result<Option<RecordDescription>, DhtError> {
+        let key = self.normalize(key)?;
+        bounded(self.query_timeout, self.request(|reply| Command::Describe { key, remote, reply })).await
+    }
+
+    /// Pin `key`, returning whether it is stored locally; a missing key cannot be pinned.
+    ///
+    /// A pinned record is republished with a fresh TTL well before it expires,
+    /// so it stays in the DHT instead of lapsing with the record TTL. Pins are
+    /// saved next to the records and survive restarts. Deleting the key unpins it.
+    pub async fn pin(&self, key: &str) -> Result<bool, DhtError> {
+        let key = self.normalize(key)?;
+        self.writable("pin", &key)?;
+        self.request(|reply| Command::Pin { key, reply }).await
+    }
+
+    /// Unpin `key`, returning whether it was pinned. The record stays until its TTL runs out.
+    pub async fn unpin(&self, key: &str) -> Result<bool, DhtError> {
+        let key = self.normalize(key)?;
+        self.writable("unpin", &key)?;
+        self.request(|reply| Command::Unpin { key, reply }).await
+    }
+
+    /// Every record in the local store.
+    pub async fn records(&self) -> Result<Vec<RecordSummary>, DhtError> {
+        self.request(|reply| Command::Records { reply }).await
+    }
+
+    /// Records in the local store whose TTL has passed but that the periodic
+    /// sweep has not removed yet. Lookups already treat them as missing.
+    pub async fn expired(&self) -> Result<Vec<RecordSummary>, DhtError> {
+        self.request(|reply| Command::Expired { reply }).await
+    }
+
+    /// Every live record in the local store as `(key, entry)`, with its value
+    /// opened from the signed envelope. Records that fail verification are left out.
+    pub async fn entries(&self) -> Result<Vec<(Vec<u8>, Entry)>, DhtError> {
+        self.request(|reply| Command::Entries { reply }).await
+    }
+
+    /// Announce that this node provides `key`, without storing a value for it.
+    ///
+    /// Resolves once the provider record has been handed to the closest peers.
+    /// The announcement is renewed periodically while the node runs.
+    pub async fn provide(&self, key: &str) -> Result<(), DhtError> {
+        let key = self.normalize(key)?;
+        self.writable("provide", &key)?;
+        bounded(self.query_timeout, self.request(|reply| Command::Provide { key, reply })).await
+    }
+
+    /// Every peer the DHT knows to provide `key`, this node included if it does.
+    pub async fn providers(&self, key: &str) -> Result<Vec<PeerId>, DhtError> {
+        let key = self.normalize(key)?;
+        bounded(self.query_timeout, self.request(|reply| Command::Providers { key, reply })).await
+    }
+
+    /// Ask the network which peers are closest to `target`, a peer id or a key.
+    ///
+    /// A diagnostic: this is the lookup a `get` of the key, or a dial of the
+    /// peer, starts with, without fetching any record.
+    pub async fn find_node(&self, target: &str) -> Result<ClosestPeers, DhtError> {
+        let target = match target.parse::<PeerId>() {
+            Ok(_) => target.to_string(),
+            Err(_) => self.normalize(target)?,
+        };
+        bounded(self.query_timeout, self.request(|reply| Command::FindNode { target, reply })).await
+    }
+
+    /// Add a known peer and start populating the routing table from it.
+    pub async fn bootstrap(&self, addr: Multiaddr, peer_id: PeerId) -> Result<(), DhtError> {
+        self.request(|reply| Command::Bootstrap { addr, peer_id, reply }).await?
+    }
+
+    /// Start dialing `addr`; resolves once the dial is under way, not when it connects.
+    pub async fn dial(&self, addr: Multiaddr) -> Result<(), DhtError> {
+        self.request(|reply| Command::Dial { addr, reply }).await?
+    }
+
+    /// Round-trip time to `peer_id`, dialing it first if it is not connected.
+    ///
+    /// A new connection is pinged at once; on an open one this waits for its
+    /// next scheduled ping, which may take a few seconds.
+    pub async fn ping(&self, peer_id: PeerId) -> Result<Duration, DhtError> {
+        bounded(PING_INTERVAL + PING_TIMEOUT, self.request(|reply| Command::Ping { peer_id, reply })).await
+    }
+
+    /// Connected peers and peers in the routing table.
+    pub async fn peers(&self) -> Result<Vec<PeerInfo>, DhtError> {
+        self.request(|reply| Command::Peers { reply }).await
+    }
+
+    /// The security protocol and stream multiplexer of every open connection.
+    pub async fn security(&self) -> Result<Vec<ConnectionSecurity>, DhtError> {
+        self.request(|reply| Command::Security { reply }).await
+    }
+
+    /// The non-empty k-buckets of the routing table, nearest first.
+    pub async fn buckets(&self) -> Result<Vec<BucketInfo>, DhtError> {
+        self.request(|reply| Command::Buckets { reply }).await
+    }
+
+    /// The addresses this node can be dialed on.
+    pub async fn addrs(&self) -> Result<NodeAddrs, DhtError> {
+        self.request(|reply| Command::Addrs { reply }).await
+    }
+
+    /// Peer, store and query counts for a quick health check.
+    pub async fn stats(&self) -> Result<NodeStats, DhtError> {
+        self.request(|reply| Command::Stats { reply }).await
+    }
+
+    /// Whether the node is listening and connected, for liveness and readiness checks.
+    pub async fn health(&self) -> Result<Health, DhtError> {
+        self.request(|reply| Command::Health { reply }).await
+    }
+
+    /// Save the local store and stop the event loop; later requests fail with [`DhtError::NodeStopped`].
+    pub async fn shutdown(&self) -> Result<(), DhtError> {
+        self.request(|reply| Command::Shutdown { reply }).await
+    }
+
+    // The puts behind `put_with_ttl`, `force_put` and `claim`, recorded in the event log
+    async fn put_command(&self, key: &str, value: Vec<u8>, quorum: Quorum, ttl: Option<Duration>, force: bool, claim: bool) -> Result<(), DhtError> {
+        let key = self.normalize(key)?;
+        let started = Instant::now();
+        let result = match self.writable("put", &key) {
+            Ok(()) => {
+                let command = |reply| Command::Put { key: key.clone(), value, quorum, ttl, force, claim, reply };
+                let timeout = read_settings(&self.settings).put_timeout() + REPLY_GRACE;
+                bounded(timeout, self.request(command)).await
+            }
+            Err(e) => Err(e),
+        };
+        self.log_operation("put", &key, started, &result, |_| json!({ "force": force, "claim": claim }));
+        result
+    }
+
+    // The gets behind `get_entry` and `get_remote`, recorded in the event log
+    async fn get_command(&self, key: &str, quorum: Quorum, remote: bool) -> Result<Option<Entry>, DhtError> {
+        let key = self.normalize(key)?;
+        let started = Instant::now();
+        let command = |reply| Command::Get { key: key.clone(), quorum, remote, reply };
+        // Long enough for every retry the event loop makes
+        let (attempt, retries) = read_settings(&self.settings).get_attempts();
+        let result = bounded(attempt.saturating_mul(retries.saturating_add(1)).saturating_add(REPLY_GRACE), self.request(command)).await;
+        self.log_operation("get", &key, started, &result, |entry: &Option<Entry>| {
+            json!({ "remote": remote, "found": entry.is_some(), "version": entry.as_ref().map(|entry| entry.version) })
+        });
+        result
+    }
+
+    // Record how an operation on `key` went, with `details` of a success
+    fn log_operation<T>(&self, operation: &str, key: &str, started: Instant, result: &Result<T, DhtError>, details: impl FnOnce(&T) -> Value) {
+        let mut fields = json!({ "key": key, "millis": started.elapsed().as_millis() as u64 });
+        match result {
+            Ok(value) => {
+                fields["ok"] = true.into();
+                if let (Value::Object(fields), Value::Object(details)) = (&mut fields, details(value)) {
+                    fields.extend(details);
+                }
+            }
+            Err(e) => {
+                fields["ok"] = false.into();
+                fields["error"] = e.to_string().into();
+            }
+        }
+        self.event_log.record(operation, fields);
+    }
+
+    // Hand a command to the event loop and wait for its reply
+    async fn request<T>(&self, command: impl FnOnce(oneshot::Sender<T>) -> Command) -> Result<T, DhtError> {
+        let (reply, outcome) = oneshot::channel();
+        self.commands.send(command(reply)).await.map_err(|_| DhtError::NodeStopped)?;
+        outcome.await.map_err(|_| DhtError::NodeStopped)
+    }
+
+    // A name normalized, or a binary key in the `0x` form it is carried in
+    fn normalize(&self, key: &str) -> Result<String, DhtError> {
+        match self.key_encoding.decode(key).map_err(DhtError::InvalidKey)? {
+            Some(bytes) => Ok(keys::hex_key(&bytes)),
+            None => normalize_key(key).map_err(DhtError::InvalidKey),
+        }
+    }
+
+    // Refuse a write to `key` on a read-only node, counting and logging it
+    fn writable(&self, action: &str, key: &str) -> Result<(), DhtError> {
+        if !self.readonly {
+            return Ok(());
+        }
+        self.metrics.readonly_refusals.inc();
+        warn!("refused {} of key {}: the node is read-only", action, key);
+        Err(DhtError::ReadOnly)
+    }
+}
+
+// Wait on a request that queries the network, giving up after `limit`
+async fn bounded<T>(limit: Duration, request: impl Future<Output = Result<Result<T, DhtError>, DhtError>>) -> Result<T, DhtError> {
+    tokio::time::timeout(limit, request).await.map_err(|_| DhtError::Timeout)??
+}
+
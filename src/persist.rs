@@ -0,0 +1,218 @@
+//! Saving and restoring the local record store and node identity so they survive restarts.
+
+use libp2p::{
+    identity::{self, Keypair},
+    kad::{store::{MemoryStore, RecordStore}, Record, RecordKey},
+    PeerId,
+};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+// On-disk form of a record; `Instant` has no fixed epoch, so expiry is kept as unix seconds
+#[derive(Serialize, Deserialize)]
+struct StoredRecord {
+    key: Vec<u8>,
+    value: Vec<u8>,
+    publisher: Option<String>,
+    expires_at: Option<u64>,
+}
+
+/// Default location of the record file: `~/.dht/records.db`.
+pub fn default_store_path() -> PathBuf {
+    data_dir().join("records.db")
+}
+
+/// Default location of the identity key: `~/.dht/identity`.
+pub fn default_identity_path() -> PathBuf {
+    data_dir().join("identity")
+}
+
+/// Default location of the interactive prompt's history: `~/.dht/history`.
+pub fn default_history_path() -> PathBuf {
+    data_dir().join("history")
+}
+
+fn data_dir() -> PathBuf {
+    let home = std::env::var_os("HOME").map(PathBuf::from).unwrap_or_else(|| PathBuf::from("."));
+    home.join(".dht")
+}
+
+/// Read the node's keypair from `path`, or generate an ed25519 one and save it
+/// there if the file does not exist yet. Also returns whether it was generated.
+///
+/// The key is written readable by the owner only. An unreadable or corrupt key
+/// file is an error rather than a reason to silently change the peer id.
+pub fn load_or_generate_identity(path: &Path) -> io::Result<(Keypair, bool)> {
+    match fs::read(path) {
+        Ok(bytes) => {
+            let keypair = Keypair::from_protobuf_encoding(&bytes)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, format!("invalid identity key: {}", e)))?;
+            return Ok((keypair, false));
+        }
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+        Err(e) => return Err(e),
+    }
+
+    let keypair = Keypair::generate_ed25519();
+    let bytes = keypair.to_protobuf_encoding()
+        .map_err(|e| io::Error::other(format!("could not encode identity key: {}", e)))?;
+
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    options.open(path)?.write_all(&bytes)?;
+    Ok((keypair, true))
+}
+
+/// The ed25519 keypair whose secret key is the SHA-256 hash of `seed`, the
+/// same wherever it is derived.
+pub fn identity_from_seed(seed: &str) -> Keypair {
+    let mut secret: [u8; 32] = Sha256::digest(seed.as_bytes()).into();
+    let secret = identity::ed25519::SecretKey::try_from_bytes(&mut secret)
+        .expect("any 32 bytes are an ed25519 secret key");
+    identity::ed25519::Keypair::from(secret).into()
+}
+
+/// Read the records saved at `path`.
+///
+/// A missing file yields no records. A corrupt or partial file is reported and
+/// also yields no records, so the node starts empty instead of failing.
+pub fn load_records(path: &Path) -> Vec<Record> {
+    let data = match fs::read(path) {
+        Ok(data) => data,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Vec::new(),
+        Err(e) => {
+            tracing::warn!("could not read record store {}: {}, starting empty", path.display(), e);
+            return Vec::new();
+        }
+    };
+
+    let stored: Vec<StoredRecord> = match serde_json::from_slice(&data) {
+        Ok(stored) => stored,
+        Err(e) => {
+            tracing::warn!("record store {} is corrupt: {}, starting empty", path.display(), e);
+            return Vec::new();
+        }
+    };
+
+    let now = SystemTime::now();
+    stored.into_iter()
+        .filter_map(|stored| {
+            let mut record = Record::new(RecordKey::new(&stored.key), stored.value);
+            record.publisher = stored.publisher.and_then(|p| p.parse::<PeerId>().ok());
+            if let Some(expires_at) = stored.expires_at {
+                // Drop records that expired while the node was down
+                let remaining = (UNIX_EPOCH + Duration::from_secs(expires_at)).duration_since(now).ok()?;
+                record.expires = Some(Instant::now() + remaining);
+            }
+            Some(record)
+        })
+        .collect()
+}
+
+/// Read the key owners saved at `path`, skipping entries that do not parse.
+///
+/// Like [`load_records`], a missing or corrupt file yields no owners.
+pub fn load_owners(path: &Path) -> HashMap<RecordKey, PeerId> {
+    let data = match fs::read(path) {
+        Ok(data) => data,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return HashMap::new(),
+        Err(e) => {
+            tracing::warn!("could not read key owners {}: {}, starting with none", path.display(), e);
+            return HashMap::new();
+        }
+    };
+
+    let stored: Vec<(Vec<u8>, String)> = match serde_json::from_slice(&data) {
+        Ok(stored) => stored,
+        Err(e) => {
+            tracing::warn!("key owners file {} is corrupt: {}, starting with none", path.display(), e);
+            return HashMap::new();
+        }
+    };
+    stored.into_iter()
+        .filter_map(|(key, owner)| Some((RecordKey::new(&key), owner.parse::<PeerId>().ok()?)))
+        .collect()
+}
+
+/// Write the owner of each owned key to `path`, replacing it atomically like [`save_records`].
+pub fn save_owners(path: &Path, owners: &HashMap<RecordKey, PeerId>) -> io::Result<()> {
+    let stored: Vec<(Vec<u8>, String)> = owners.iter()
+        .map(|(key, owner)| (key.to_vec(), owner.to_string()))
+        .collect();
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let tmp_path = path.with_extension("acl.tmp");
+    fs::write(&tmp_path, serde_json::to_vec(&stored)?)?;
+    fs::rename(&tmp_path, path)?;
+    Ok(())
+}
+
+/// Read the pinned keys saved at `path`; like [`load_owners`], a missing or corrupt file yields none.
+pub fn load_pins(path: &Path) -> HashSet<RecordKey> {
+    let data = match fs::read(path) {
+        Ok(data) => data,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return HashSet::new(),
+        Err(e) => {
+            tracing::warn!("could not read pinned keys {}: {}, starting with none", path.display(), e);
+            return HashSet::new();
+        }
+    };
+
+    match serde_json::from_slice::<Vec<Vec<u8>>>(&data) {
+        Ok(stored) => stored.into_iter().map(|key| RecordKey::new(&key)).collect(),
+        Err(e) => {
+            tracing::warn!("pinned keys file {} is corrupt: {}, starting with none", path.display(), e);
+            HashSet::new()
+        }
+    }
+}
+
+/// Write the pinned keys to `path`, replacing it atomically like [`save_records`].
+pub fn save_pins(path: &Path, pins: &HashSet<RecordKey>) -> io::Result<()> {
+    let stored: Vec<Vec<u8>> = pins.iter().map(|key| key.to_vec()).collect();
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let tmp_path = path.with_extension("pins.tmp");
+    fs::write(&tmp_path, serde_json::to_vec(&stored)?)?;
+    fs::rename(&tmp_path, path)?;
+    Ok(())
+}
+
+/// Write every record in `store` to `path`.
+///
+/// The file is written to a temporary sibling first and renamed into place,
+/// so a crash mid-write never leaves a truncated store behind.
+pub fn save_records(path: &Path, store: &mut MemoryStore) -> io::Result<usize> {
+    let now = Instant::now();
+    let stored: Vec<StoredRecord> = store.records()
+        .map(|record| StoredRecord {
+            key: record.key.to_vec(),
+            value: record.value.clone(),
+            publisher: record.publisher.map(|p| p.to_string()),
+            expires_at: record.expires.map(|expires| {
+                let remaining = expires.saturating_duration_since(now);
+                (SystemTime::now() + remaining).duration_since(UNIX_EPOCH).unwrap_or_default().as_secs()
+            }),
+        })
+        .collect();
+
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let tmp_path = path.with_extension("tmp");
+    fs::write(&tmp_path, serde_json::to_vec(&stored)?)?;
+    fs::rename(&tmp_path, path)?;
+    Ok(stored.len())
+}
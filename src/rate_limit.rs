@@ -0,0 +1,34 @@
+//! Token buckets that throttle bursts of commands before they reach the swarm.
+
+use std::time::Instant;
+
+/// Allows `burst` requests at once, refilled at `rate` requests per second.
+#[derive(Debug, Clone)]
+pub struct TokenBucket {
+    rate: f64,
+    capacity: f64,
+    tokens: f64,
+    refilled: Instant,
+}
+
+impl TokenBucket {
+    /// A full bucket. A `rate` of zero never refills, so slow it down with a
+    /// small positive rate instead.
+    pub fn new(rate: f64, burst: u32) -> Self {
+        TokenBucket { rate, capacity: burst as f64, tokens: burst as f64, refilled: Instant::now() }
+    }
+
+    /// Take a token if one is left, returning whether the request may go ahead.
+    pub fn try_acquire(&mut self) -> bool {
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.refilled).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.rate).min(self.capacity);
+        self.refilled = now;
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+}
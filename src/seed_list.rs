@@ -0,0 +1,34 @@
+//! Bootstrap peers fetched at startup from a published list, so joining a
+//! network takes a URL instead of hardcoded multiaddrs.
+//!
+//! The list is either a JSON array of multiaddr strings or plain text with one
+//! per line, blank lines and `#` comments skipped.
+
+use std::time::Duration;
+
+/// Longest the fetch may take before the node starts with its configured peers alone.
+pub const FETCH_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Fetch the list at `url` over HTTP or HTTPS.
+pub async fn fetch(url: &str) -> Result<Vec<String>, String> {
+    let client = reqwest::Client::builder().timeout(FETCH_TIMEOUT).build().map_err(|e| e.to_string())?;
+    let response = client.get(url).send().await.map_err(|e| e.to_string())?;
+    let status = response.status();
+    if !status.is_success() {
+        return Err(format!("the server answered {}", status));
+    }
+    let body = response.text().await.map_err(|e| e.to_string())?;
+    parse(&body)
+}
+
+/// The entries of a list in either format, not yet checked to be multiaddrs.
+pub fn parse(body: &str) -> Result<Vec<String>, String> {
+    if body.trim_start().starts_with('[') {
+        return serde_json::from_str(body).map_err(|e| format!("invalid JSON list: {}", e));
+    }
+    Ok(body.lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(str::to_string)
+        .collect())
+}
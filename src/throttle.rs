@@ -0,0 +1,305 @@
+//! Per-peer accounting of inbound Kademlia requests, so a peer flooding the
+//! node with queries is refused for a while instead of being served.
+//!
+//! [`Throttled`] wraps the Kademlia behaviour and hands each of its connection
+//! handlers the shared accounts. Every inbound Kademlia stream counts as one
+//! request, as libp2p peers open a stream per request; once a peer is over
+//! its limit, its new streams are dropped unanswered until the cooldown ends.
+
+use crate::rate_limit::TokenBucket;
+use futures::future::Either;
+use libp2p::core::{Endpoint, Multiaddr};
+use libp2p::swarm::behaviour::{ConnectionClosed, FromSwarm};
+use libp2p::swarm::handler::ConnectionEvent;
+use libp2p::swarm::{
+    CloseConnection, ConnectionDenied, ConnectionHandler, ConnectionHandlerEvent, ConnectionId, KeepAlive, NetworkBehaviour,
+    PollParameters, SubstreamProtocol, THandler, THandlerInEvent, THandlerOutEvent, ToSwarm,
+};
+use libp2p::kad::{self, store::MemoryStore};
+use libp2p::PeerId;
+use prometheus::IntCounter;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::io;
+use std::ops::{Deref, DerefMut};
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+use tracing::warn;
+
+/// How many inbound requests each peer may make, and what happens once it makes more.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Limits {
+    /// Requests a second once the burst is spent; zero turns throttling off.
+    pub rate: f64,
+    pub burst: u32,
+    /// How long a peer over its limit is refused.
+    pub cooldown: Duration,
+    /// Whether to also close every connection to such a peer.
+    pub disconnect: bool,
+}
+
+// What to do with one inbound request
+enum Admission {
+    Serve,
+    Refuse,
+    // Refuse, and the peer has only just gone over its limit
+    Throttle,
+}
+
+struct Accounts {
+    limits: Limits,
+    buckets: HashMap<PeerId, TokenBucket>,
+    // Peers being refused, until when
+    throttled: HashMap<PeerId, Instant>,
+    exempt: HashSet<PeerId>,
+    refused: IntCounter,
+}
+
+impl Accounts {
+    fn admit(&mut self, peer: PeerId) -> Admission {
+        if self.limits.rate <= 0.0 || self.exempt.contains(&peer) {
+            return Admission::Serve;
+        }
+        let now = Instant::now();
+        match self.throttled.get(&peer) {
+            Some(until) if *until > now => {
+                self.refused.inc();
+                return Admission::Refuse;
+            }
+            Some(_) => {
+                self.throttled.remove(&peer);
+            }
+            None => {}
+        }
+        let limits = self.limits;
+        let bucket = self.buckets.entry(peer).or_insert_with(|| TokenBucket::new(limits.rate, limits.burst));
+        if bucket.try_acquire() {
+            return Admission::Serve;
+        }
+        // A fresh burst awaits the peer once its cooldown is over
+        self.buckets.remove(&peer);
+        self.throttled.insert(peer, now + limits.cooldown);
+        self.refused.inc();
+        Admission::Throttle
+    }
+}
+
+fn lock(accounts: &Mutex<Accounts>) -> std::sync::MutexGuard<'_, Accounts> {
+    accounts.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+/// Tells the behaviour that the peer of a connection has just gone over its limit.
+#[derive(Debug)]
+pub(crate) struct Throttle;
+
+type Kademlia = kad::Behaviour<MemoryStore>;
+type KadHandler = THandler<Kademlia>;
+
+/// Kademlia, with inbound requests counted per peer and refused above [`Limits`].
+pub(crate) struct Throttled {
+    inner: Kademlia,
+    accounts: Arc<Mutex<Accounts>>,
+    disconnect: bool,
+    // Peers that went over their limit since the last poll
+    newly_throttled: VecDeque<PeerId>,
+    throttled_peers: IntCounter,
+}
+
+impl Throttled {
+    /// `throttled_peers` counts each time a peer goes over its limit, `refused` each request refused.
+    pub(crate) fn new(inner: Kademlia, limits: Limits, throttled_peers: IntCounter, refused: IntCounter) -> Self {
+        let accounts = Accounts { limits, buckets: HashMap::new(), throttled: HashMap::new(), exempt: HashSet::new(), refused };
+        Throttled {
+            inner,
+            accounts: Arc::new(Mutex::new(accounts)),
+            disconnect: limits.disconnect,
+            newly_throttled: VecDeque::new(),
+            throttled_peers,
+        }
+    }
+
+    /// Count requests against `limits` from now on. Peers start over with a
+    /// fresh burst; ones already refused stay so until their cooldown ends.
+    pub(crate) fn set_limits(&mut self, limits: Limits) {
+        let mut accounts = lock(&self.accounts);
+        accounts.limits = limits;
+        accounts.buckets.clear();
+    }
+
+    /// Never throttle `peer`, as for bootstrap peers the network is joined through.
+    pub(crate) fn exempt(&mut self, peer: PeerId) {
+        lock(&self.accounts).exempt.insert(peer);
+    }
+
+    fn handler(&self, peer: PeerId, inner: KadHandler) -> Handler {
+        Handler { inner, peer, accounts: self.accounts.clone(), throttled: false }
+    }
+}
+
+impl Deref for Throttled {
+    type Target = Kademlia;
+
+    fn deref(&self) -> &Kademlia {
+        &self.inner
+    }
+}
+
+impl DerefMut for Throttled {
+    fn deref_mut(&mut self) -> &mut Kademlia {
+        &mut self.inner
+    }
+}
+
+impl NetworkBehaviour for Throttled {
+    type ConnectionHandler = Handler;
+    type ToSwarm = kad::Event;
+
+    fn handle_pending_inbound_connection(
+        &mut self,
+        connection_id: ConnectionId,
+        local_addr: &Multiaddr,
+        remote_addr: &Multiaddr,
+    ) -> Result<(), ConnectionDenied> {
+        self.inner.handle_pending_inbound_connection(connection_id, local_addr, remote_addr)
+    }
+
+    fn handle_established_inbound_connection(
+        &mut self,
+        connection_id: ConnectionId,
+        peer: PeerId,
+        local_addr: &Multiaddr,
+        remote_addr: &Multiaddr,
+    ) -> Result<THandler<Self>, ConnectionDenied> {
+        let inner = self.inner.handle_established_inbound_connection(connection_id, peer, local_addr, remote_addr)?;
+        Ok(self.handler(peer, inner))
+    }
+
+    fn handle_pending_outbound_connection(
+        &mut self,
+        connection_id: ConnectionId,
+        maybe_peer: Option<PeerId>,
+        addresses: &[Multiaddr],
+        effective_role: Endpoint,
+    ) -> Result<Vec<Multiaddr>, ConnectionDenied> {
+        self.inner.handle_pending_outbound_connection(connection_id, maybe_peer, addresses, effective_role)
+    }
+
+    fn handle_established_outbound_connection(
+        &mut self,
+        connection_id: ConnectionId,
+        peer: PeerId,
+        addr: &Multiaddr,
+        role_override: Endpoint,
+    ) -> Result<THandler<Self>, ConnectionDenied> {
+        let inner = self.inner.handle_established_outbound_connection(connection_id, peer, addr, role_override)?;
+        Ok(self.handler(peer, inner))
+    }
+
+    fn on_swarm_event(&mut self, event: FromSwarm<Self::ConnectionHandler>) {
+        let event = match event {
+            FromSwarm::ConnectionClosed(ConnectionClosed { peer_id, connection_id, endpoint, handler, remaining_established }) => {
+                if remaining_established == 0 {
+                    let mut accounts = lock(&self.accounts);
+                    accounts.buckets.remove(&peer_id);
+                    let now = Instant::now();
+                    accounts.throttled.retain(|_, until| *until > now);
+                }
+                FromSwarm::ConnectionClosed(ConnectionClosed { peer_id, connection_id, endpoint, handler: handler.inner, remaining_established })
+            }
+            FromSwarm::ConnectionEstablished(e) => FromSwarm::ConnectionEstablished(e),
+            FromSwarm::AddressChange(e) => FromSwarm::AddressChange(e),
+            FromSwarm::DialFailure(e) => FromSwarm::DialFailure(e),
+            FromSwarm::ListenFailure(e) => FromSwarm::ListenFailure(e),
+            FromSwarm::NewListener(e) => FromSwarm::NewListener(e),
+            FromSwarm::NewListenAddr(e) => FromSwarm::NewListenAddr(e),
+            FromSwarm::ExpiredListenAddr(e) => FromSwarm::ExpiredListenAddr(e),
+            FromSwarm::ListenerError(e) => FromSwarm::ListenerError(e),
+            FromSwarm::ListenerClosed(e) => FromSwarm::ListenerClosed(e),
+            FromSwarm::NewExternalAddrCandidate(e) => FromSwarm::NewExternalAddrCandidate(e),
+            FromSwarm::ExternalAddrConfirmed(e) => FromSwarm::ExternalAddrConfirmed(e),
+            FromSwarm::ExternalAddrExpired(e) => FromSwarm::ExternalAddrExpired(e),
+        };
+        self.inner.on_swarm_event(event);
+    }
+
+    fn on_connection_handler_event(&mut self, peer_id: PeerId, connection_id: ConnectionId, event: THandlerOutEvent<Self>) {
+        match event {
+            Either::Left(event) => self.inner.on_connection_handler_event(peer_id, connection_id, event),
+            Either::Right(Throttle) => self.newly_throttled.push_back(peer_id),
+        }
+    }
+
+    fn poll(&mut self, cx: &mut Context<'_>, params: &mut impl PollParameters) -> Poll<ToSwarm<Self::ToSwarm, THandlerInEvent<Self>>> {
+        while let Some(peer_id) = self.newly_throttled.pop_front() {
+            self.throttled_peers.inc();
+            let limits = lock(&self.accounts).limits;
+            warn!(
+                "{} went over its Kademlia request limit ({}/s, burst {}), refusing its requests for {}s{}",
+                peer_id, limits.rate, limits.burst, limits.cooldown.as_secs(), if self.disconnect { " and disconnecting it" } else { "" },
+            );
+            if self.disconnect {
+                return Poll::Ready(ToSwarm::CloseConnection { peer_id, connection: CloseConnection::All });
+            }
+        }
+        self.inner.poll(cx, params)
+    }
+}
+
+/// Wraps Kademlia's handler, dropping inbound streams from a peer over its limit.
+pub(crate) struct Handler {
+    inner: KadHandler,
+    peer: PeerId,
+    accounts: Arc<Mutex<Accounts>>,
+    // The peer just went over its limit on this connection and the behaviour is yet to hear
+    throttled: bool,
+}
+
+impl ConnectionHandler for Handler {
+    type FromBehaviour = <KadHandler as ConnectionHandler>::FromBehaviour;
+    type ToBehaviour = Either<<KadHandler as ConnectionHandler>::ToBehaviour, Throttle>;
+    // What Kademlia's handler fails with
+    type Error = io::Error;
+    type InboundProtocol = <KadHandler as ConnectionHandler>::InboundProtocol;
+    type OutboundProtocol = <KadHandler as ConnectionHandler>::OutboundProtocol;
+    type InboundOpenInfo = <KadHandler as ConnectionHandler>::InboundOpenInfo;
+    type OutboundOpenInfo = <KadHandler as ConnectionHandler>::OutboundOpenInfo;
+
+    fn listen_protocol(&self) -> SubstreamProtocol<Self::InboundProtocol, Self::InboundOpenInfo> {
+        self.inner.listen_protocol()
+    }
+
+    fn connection_keep_alive(&self) -> KeepAlive {
+        self.inner.connection_keep_alive()
+    }
+
+    fn poll(
+        &mut self,
+        cx: &mut Context<'_>,
+    ) -> Poll<ConnectionHandlerEvent<Self::OutboundProtocol, Self::OutboundOpenInfo, Self::ToBehaviour, io::Error>> {
+        if std::mem::take(&mut self.throttled) {
+            return Poll::Ready(ConnectionHandlerEvent::NotifyBehaviour(Either::Right(Throttle)));
+        }
+        self.inner.poll(cx).map(|event| event.map_custom(Either::Left))
+    }
+
+    fn on_behaviour_event(&mut self, event: Self::FromBehaviour) {
+        self.inner.on_behaviour_event(event);
+    }
+
+    fn on_connection_event(
+        &mut self,
+        event: ConnectionEvent<Self::InboundProtocol, Self::OutboundProtocol, Self::InboundOpenInfo, Self::OutboundOpenInfo>,
+    ) {
+        if let ConnectionEvent::FullyNegotiatedInbound(_) = &event {
+            match lock(&self.accounts).admit(self.peer) {
+                Admission::Serve => {}
+                Admission::Refuse => return,
+                Admission::Throttle => {
+                    self.throttled = true;
+                    return;
+                }
+            }
+        }
+        self.inner.on_connection_event(event);
+    }
+}
@@ -0,0 +1,325 @@
+//! The transport stack the swarm listens and dials with.
+
+use crate::config::Security;
+use crate::error::DhtError;
+use futures::future::{BoxFuture, Either};
+use futures::{AsyncRead, AsyncWrite, FutureExt};
+use libp2p::core::transport::{timeout::TransportTimeout, Boxed, ListenerId, OptionalTransport, TransportError, TransportEvent};
+use libp2p::core::upgrade::{InboundUpgrade, OutboundUpgrade, UpgradeInfo};
+use libp2p::core::{muxing::StreamMuxerBox, upgrade, ConnectedPoint, Transport};
+use libp2p::pnet::{PnetConfig, PnetError, PnetOutput, PreSharedKey};
+use libp2p::{dns, identity, noise, quic, relay, tcp, tls, websocket, yamux, Multiaddr, PeerId};
+use std::collections::HashMap;
+use std::io;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+use tracing::info;
+
+// The protocols each upgrade offers, so the ones multistream-select settles on
+const NOISE: &str = "/noise";
+const TLS: &str = "/tls/1.0.0";
+const YAMUX: &str = "/yamux/1.0.0";
+
+/// The security and stream multiplexing protocols a connection was upgraded with.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Upgrade {
+    pub security: &'static str,
+    pub muxer: &'static str,
+    pub private_network: bool,
+}
+
+/// Upgrades of connections not yet reported by the swarm, by peer and remote address.
+/// A connection that never passed through an encrypting upgrade has no entry.
+pub(crate) type Upgrades = Arc<Mutex<HashMap<(PeerId, Multiaddr), Upgrade>>>;
+
+/// TCP, QUIC and WebSocket, all able to dial `/dns` addresses, plus circuits
+/// through relays via `relay`. A connection that is not established and
+/// upgraded within `timeout` fails.
+///
+/// With a `psk` every connection first proves knowledge of the key, below
+/// noise, so only nodes of the same private network can connect. QUIC has no
+/// place for that handshake and is left out.
+///
+/// `security` picks the handshake for every transport but QUIC, which always runs TLS.
+///
+/// Each upgraded connection is entered in `upgrades` for the event loop to pick up.
+/// With `trace_dials` every address dialed is logged along with each step of
+/// the attempt and the full reason it failed.
+pub(crate) fn build(
+    keypair: &identity::Keypair,
+    timeout: Duration,
+    relay: relay::client::Transport,
+    psk: Option<PreSharedKey>,
+    security: Security,
+    upgrades: Upgrades,
+    trace_dials: bool,
+) -> Result<Boxed<(PeerId, StreamMuxerBox)>, DhtError> {
+    let setup = |e: &dyn std::fmt::Display| DhtError::Setup(e.to_string());
+    let secure = SecurityUpgrade {
+        noise: match security {
+            Security::Noise | Security::Both => Some(noise::Config::new(keypair).map_err(|e| setup(&e))?),
+            Security::Tls => None,
+        },
+        tls: match security {
+            Security::Tls | Security::Both => Some(tls::Config::new(keypair).map_err(|e| setup(&e))?),
+            Security::Noise => None,
+        },
+        private_network: psk.is_some(),
+        negotiated: Arc::default(),
+    };
+
+    // TCP and WebSocket connections get the same security/yamux upgrade; QUIC brings its own
+    let tcp = tcp::tokio::Transport::new(tcp::Config::default())
+        .and_then(move |stream, endpoint| {
+            trace_open(trace_dials, &endpoint);
+            protect(stream, psk)
+        })
+        .upgrade(upgrade::Version::V1Lazy)
+        .authenticate(secure.clone())
+        .multiplex(yamux::Config::default())
+        .map({
+            let secure = secure.clone();
+            move |(peer_id, muxer), _| (peer_id, StreamMuxerBox::new(muxer), secure.upgraded(peer_id))
+        });
+    let quic = match psk {
+        Some(_) => OptionalTransport::none(),
+        None => OptionalTransport::some(quic::tokio::Transport::new(quic::Config::new(keypair))),
+    }
+    // QUIC always runs TLS 1.3 with the libp2p certificate extension, and multiplexes natively
+    .map(|(peer_id, muxer), _| {
+        (peer_id, StreamMuxerBox::new(muxer), Upgrade { security: "/tls/1.0.0 (QUIC)", muxer: "QUIC streams", private_network: false })
+    });
+    let websocket_tcp = dns::tokio::Transport::system(tcp::tokio::Transport::new(tcp::Config::default()))
+        .map_err(|e| setup(&e))?;
+    let websocket = websocket::WsConfig::new(websocket_tcp)
+        .and_then(move |stream, endpoint| {
+            trace_open(trace_dials, &endpoint);
+            protect(stream, psk)
+        })
+        .upgrade(upgrade::Version::V1Lazy)
+        .authenticate(secure.clone())
+        .multiplex(yamux::Config::default())
+        .map({
+            let secure = secure.clone();
+            move |(peer_id, muxer), _| (peer_id, StreamMuxerBox::new(muxer), secure.upgraded(peer_id))
+        });
+
+    // A relayed connection is end-to-end encrypted between us and the far peer, not the relay
+    let relayed = relay
+        .and_then(move |stream, endpoint| {
+            trace_open(trace_dials, &endpoint);
+            protect(stream, psk)
+        })
+        .upgrade(upgrade::Version::V1Lazy)
+        .authenticate(secure.clone())
+        .multiplex(yamux::Config::default())
+        .map({
+            let secure = secure.clone();
+            move |(peer_id, muxer), _| (peer_id, StreamMuxerBox::new(muxer), secure.upgraded(peer_id))
+        });
+
+    let direct = dns::tokio::Transport::system(tcp.or_transport(quic).map(|either, _| either.into_inner()))
+        .map_err(|e| setup(&e))?;
+    // Circuit addresses go first: the DNS layer rejects any address its inner transports cannot
+    // dial instead of passing it on, so nothing after `direct` would ever see one
+    let transport = relayed
+        .or_transport(websocket)
+        .map(|either, _| either.into_inner())
+        .or_transport(direct)
+        .map(move |either, endpoint| {
+            let (peer_id, muxer, upgrade) = either.into_inner();
+            // Keyed on the outermost endpoint, as the swarm reports it, not one resolved by the DNS layer
+            let mut upgrades = upgrades.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+            upgrades.insert((peer_id, endpoint.get_remote_address().clone()), upgrade);
+            (peer_id, muxer)
+        });
+    let transport = TransportTimeout::new(transport, timeout).boxed();
+    Ok(if trace_dials { TraceDials(transport).boxed() } else { transport })
+}
+
+// Note that a dialed connection is open and about to negotiate its security
+fn trace_open(trace_dials: bool, endpoint: &ConnectedPoint) {
+    if trace_dials && let ConnectedPoint::Dialer { address, .. } = endpoint {
+        info!("dial {}: connection open, negotiating security and muxer", address);
+    }
+}
+
+/// An error followed by each of its causes, outermost first, joined with `: `.
+pub(crate) fn error_chain(error: &dyn std::error::Error) -> String {
+    let mut chain = String::new();
+    let mut next = Some(error);
+    while let Some(cause) = next {
+        let text = cause.to_string();
+        // Some wrappers display nothing, or repeat their cause verbatim
+        if !text.is_empty() && !chain.ends_with(&text) {
+            if !chain.is_empty() {
+                chain.push_str(": ");
+            }
+            chain.push_str(&text);
+        }
+        next = cause.source();
+    }
+    chain
+}
+
+// Logs each address the swarm dials, and how and how fast the attempt ended
+struct TraceDials(Boxed<(PeerId, StreamMuxerBox)>);
+
+impl TraceDials {
+    fn trace(
+        addr: Multiaddr,
+        dial: Result<<Boxed<(PeerId, StreamMuxerBox)> as Transport>::Dial, TransportError<io::Error>>,
+    ) -> Result<BoxFuture<'static, io::Result<(PeerId, StreamMuxerBox)>>, TransportError<io::Error>> {
+        let dial = match dial {
+            Ok(dial) => dial,
+            Err(e) => {
+                info!("dial {}: not started: {}", addr, error_chain(&e));
+                return Err(e);
+            }
+        };
+        info!("dial {}: started", addr);
+        let started = Instant::now();
+        Ok(dial
+            .inspect(move |outcome| match outcome {
+                Ok((peer_id, _)) => info!("dial {}: secured and multiplexed as {} in {:?}", addr, peer_id, started.elapsed()),
+                Err(e) => info!("dial {}: failed after {:?}: {}", addr, started.elapsed(), error_chain(e)),
+            })
+            .boxed())
+    }
+}
+
+impl Transport for TraceDials {
+    type Output = (PeerId, StreamMuxerBox);
+    type Error = io::Error;
+    type ListenerUpgrade = <Boxed<(PeerId, StreamMuxerBox)> as Transport>::ListenerUpgrade;
+    type Dial = BoxFuture<'static, io::Result<(PeerId, StreamMuxerBox)>>;
+
+    fn listen_on(&mut self, id: ListenerId, addr: Multiaddr) -> Result<(), TransportError<io::Error>> {
+        self.0.listen_on(id, addr)
+    }
+
+    fn remove_listener(&mut self, id: ListenerId) -> bool {
+        self.0.remove_listener(id)
+    }
+
+    fn dial(&mut self, addr: Multiaddr) -> Result<Self::Dial, TransportError<io::Error>> {
+        TraceDials::trace(addr.clone(), self.0.dial(addr))
+    }
+
+    fn dial_as_listener(&mut self, addr: Multiaddr) -> Result<Self::Dial, TransportError<io::Error>> {
+        TraceDials::trace(addr.clone(), self.0.dial_as_listener(addr))
+    }
+
+    fn address_translation(&self, listen: &Multiaddr, observed: &Multiaddr) -> Option<Multiaddr> {
+        self.0.address_translation(listen, observed)
+    }
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<TransportEvent<Self::ListenerUpgrade, io::Error>> {
+        Pin::new(&mut self.0).poll(cx)
+    }
+}
+
+// Noise, TLS or both, offered in that order. Which one a connection settled on is only known
+// inside the handshake, so it is left under the remote's peer id until the connection is
+// multiplexed and reported; two connections to one peer racing through different handshakes
+// may swap labels, but not how they are secured
+#[derive(Clone)]
+struct SecurityUpgrade {
+    noise: Option<noise::Config>,
+    tls: Option<tls::Config>,
+    private_network: bool,
+    negotiated: Arc<Mutex<HashMap<PeerId, &'static str>>>,
+}
+
+type Secured<S> = Either<noise::Output<S>, tls::TlsStream<S>>;
+
+impl SecurityUpgrade {
+    fn secure<S>(self, stream: S, protocol: &'static str, inbound: bool) -> BoxFuture<'static, io::Result<(PeerId, Secured<S>)>>
+    where
+        S: AsyncRead + AsyncWrite + Send + Unpin + 'static,
+    {
+        async move {
+            let (peer_id, stream) = match (protocol, self.noise, self.tls) {
+                (NOISE, Some(noise), _) => {
+                    let (peer_id, stream) = match inbound {
+                        true => noise.upgrade_inbound(stream, NOISE).await,
+                        false => noise.upgrade_outbound(stream, NOISE).await,
+                    }
+                    .map_err(io::Error::other)?;
+                    (peer_id, Either::Left(stream))
+                }
+                (TLS, _, Some(tls)) => {
+                    let (peer_id, stream) = match inbound {
+                        true => tls.upgrade_inbound(stream, TLS).await,
+                        false => tls.upgrade_outbound(stream, TLS).await,
+                    }
+                    .map_err(io::Error::other)?;
+                    (peer_id, Either::Right(stream))
+                }
+                _ => return Err(io::Error::other(format!("security protocol {} not offered", protocol))),
+            };
+            let mut negotiated = self.negotiated.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+            negotiated.insert(peer_id, protocol);
+            Ok((peer_id, stream))
+        }
+        .boxed()
+    }
+
+    // How the just-multiplexed connection to `peer_id` was upgraded
+    fn upgraded(&self, peer_id: PeerId) -> Upgrade {
+        let mut negotiated = self.negotiated.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        let security = negotiated.remove(&peer_id).unwrap_or(NOISE);
+        Upgrade { security, muxer: YAMUX, private_network: self.private_network }
+    }
+}
+
+impl UpgradeInfo for SecurityUpgrade {
+    type Info = &'static str;
+    type InfoIter = Vec<&'static str>;
+
+    fn protocol_info(&self) -> Self::InfoIter {
+        let noise = self.noise.as_ref().map(|_| NOISE);
+        let tls = self.tls.as_ref().map(|_| TLS);
+        noise.into_iter().chain(tls).collect()
+    }
+}
+
+impl<S> InboundUpgrade<S> for SecurityUpgrade
+where
+    S: AsyncRead + AsyncWrite + Send + Unpin + 'static,
+{
+    type Output = (PeerId, Secured<S>);
+    type Error = io::Error;
+    type Future = BoxFuture<'static, io::Result<Self::Output>>;
+
+    fn upgrade_inbound(self, stream: S, protocol: Self::Info) -> Self::Future {
+        self.secure(stream, protocol, true)
+    }
+}
+
+impl<S> OutboundUpgrade<S> for SecurityUpgrade
+where
+    S: AsyncRead + AsyncWrite + Send + Unpin + 'static,
+{
+    type Output = (PeerId, Secured<S>);
+    type Error = io::Error;
+    type Future = BoxFuture<'static, io::Result<Self::Output>>;
+
+    fn upgrade_outbound(self, stream: S, protocol: Self::Info) -> Self::Future {
+        self.secure(stream, protocol, false)
+    }
+}
+
+// Run the pre-shared-key handshake on a fresh stream, or pass it through outside a private network.
+// A peer with another key fails here, before noise starts
+async fn protect<S>(stream: S, psk: Option<PreSharedKey>) -> Result<Either<PnetOutput<S>, S>, PnetError>
+where
+    S: AsyncRead + AsyncWrite + Send + Unpin + 'static,
+{
+    match psk {
+        Some(psk) => PnetConfig::new(psk).handshake(stream).await.map(Either::Left),
+        None => Ok(Either::Right(stream)),
+    }
+}
@@ -0,0 +1,61 @@
+//! Operator policy on which records a node accepts.
+//!
+//! A [`RecordValidator`] sees every record before it is stored: local puts,
+//! which fail with [`DhtError::Rejected`](crate::DhtError::Rejected), and
+//! records pushed by peers, which are dropped and logged. Pass one to
+//! [`DhtNode::with_validator`](crate::DhtNode::with_validator); otherwise the
+//! node uses a [`SuffixValidator`] when `allow_suffixes` is set, and accepts
+//! everything when it is not.
+
+/// Decides whether a record may be stored.
+pub trait RecordValidator: Send + Sync {
+    /// `Ok` to accept the value stored under record key `key`, or the reason
+    /// to refuse it. The value is the one written, already decompressed and
+    /// out of its envelope. Accepts everything unless overridden.
+    fn validate(&self, key: &[u8], value: &[u8]) -> Result<(), String> {
+        let _ = (key, value);
+        Ok(())
+    }
+}
+
+/// Accepts every record.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct AllowAll;
+
+impl RecordValidator for AllowAll {}
+
+/// Accepts only names at or below one of its domains: `example.com` accepts
+/// `example.com` and `www.example.com`, but not `evilexample.com`.
+///
+/// A leading dot on a suffix, as in `.example.com`, is ignored. Names are
+/// matched without regard to case. Record keys are only names with
+/// `key_hashing = "raw"`, so binary and hashed keys are refused.
+#[derive(Debug, Clone)]
+pub struct SuffixValidator {
+    suffixes: Vec<String>,
+}
+
+impl SuffixValidator {
+    pub fn new<S: AsRef<str>>(suffixes: impl IntoIterator<Item = S>) -> Self {
+        let suffixes = suffixes.into_iter()
+            .map(|suffix| suffix.as_ref().trim_start_matches('.').to_ascii_lowercase())
+            .collect();
+        SuffixValidator { suffixes }
+    }
+}
+
+impl RecordValidator for SuffixValidator {
+    fn validate(&self, key: &[u8], _value: &[u8]) -> Result<(), String> {
+        let Ok(name) = std::str::from_utf8(key) else {
+            return Err("binary keys are not under any allowed suffix".to_string());
+        };
+        let name = name.to_ascii_lowercase();
+        // Whole labels only, so a suffix never matches the middle of one
+        let under = |suffix: &String| name == *suffix || name.strip_suffix(suffix.as_str()).is_some_and(|rest| rest.ends_with('.'));
+        if self.suffixes.iter().any(under) {
+            Ok(())
+        } else {
+            Err(format!("{} is not under any allowed suffix ({})", name, self.suffixes.join(", ")))
+        }
+    }
+}
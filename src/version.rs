@@ -0,0 +1,22 @@
+//! What the running binary was built from, for bug reports.
+
+/// The crate version from `Cargo.toml`.
+pub const VERSION: &str = env!("CARGO_PKG_VERSION");
+
+/// The git commit built, suffixed `-dirty` for uncommitted changes, or "unknown" outside a checkout.
+pub const GIT_COMMIT: &str = env!("DHT_GIT_COMMIT");
+
+/// The output of `rustc --version` for the compiler used.
+pub const RUSTC_VERSION: &str = env!("DHT_RUSTC_VERSION");
+
+/// The crate's cargo features enabled in the build.
+pub fn features() -> Vec<&'static str> {
+    env!("DHT_FEATURES").split(',').filter(|feature| !feature.is_empty()).collect()
+}
+
+/// One line naming the version, commit and compiler, e.g. for a banner.
+pub fn describe() -> String {
+    let features = features();
+    let features = if features.is_empty() { "none".to_string() } else { features.join(", ") };
+    format!("DHT {} (commit {}, {}, features: {})", VERSION, GIT_COMMIT, RUSTC_VERSION, features)
+}
@@ -0,0 +1,92 @@
+//! Reading RFC 1035 zone files for the `import-zone` command.
+//!
+//! Every supported resource record becomes a [`DnsRecord`] under its owner's
+//! fully-qualified name, and all the records of one name are stored together
+//! as a single value, the way `put` stores several SRV or MX records. Other
+//! record types, such as the zone's SOA and NS records, have no place in the
+//! DHT and are counted instead.
+
+use crate::dns_record::{normalize_key, DnsRecord};
+use hickory_proto::rr::{Name, RData};
+use hickory_proto::serialize::txt::Parser;
+use std::collections::BTreeMap;
+use std::path::Path;
+
+/// The records of a parsed zone file.
+#[derive(Debug, Default)]
+pub struct Zone {
+    /// The zone's origin, from `$ORIGIN` or the one passed to [`parse`].
+    pub origin: String,
+    /// Each name with its records, sorted by name.
+    pub names: BTreeMap<String, Vec<DnsRecord>>,
+    /// Records of unsupported types that were left out, counted by type.
+    pub skipped: BTreeMap<String, usize>,
+}
+
+/// Parse the zone file at `path` holding `text`. Names that are not fully
+/// qualified are relative to `origin`, unless the file sets `$ORIGIN` itself.
+pub fn parse(text: &str, path: &Path, origin: Option<&str>) -> Result<Zone, String> {
+    let origin = match origin {
+        Some(origin) => {
+            let mut name = Name::from_ascii(origin).map_err(|e| format!("invalid origin '{}': {}", origin, e))?;
+            name.set_fqdn(true);
+            Some(name)
+        }
+        None => None,
+    };
+    let (origin, record_sets) = Parser::new(text, Some(path.to_path_buf()), origin)
+        .parse()
+        .map_err(|e| format!("invalid zone file {}: {}", path.display(), e))?;
+
+    let mut zone = Zone { origin: name_of(&origin), ..Zone::default() };
+    for record in record_sets.values().flat_map(|set| set.records_without_rrsigs()) {
+        let Some(converted) = record.data().and_then(convert) else {
+            *zone.skipped.entry(record.record_type().to_string()).or_default() += 1;
+            continue;
+        };
+        let name = normalize_key(&name_of(record.name()))?;
+        zone.names.entry(name).or_default().push(converted);
+    }
+    Ok(zone)
+}
+
+/// The type name of a record, as counted in import reports.
+pub fn type_name(record: &DnsRecord) -> &'static str {
+    match record {
+        DnsRecord::A(_) => "A",
+        DnsRecord::Aaaa(_) => "AAAA",
+        DnsRecord::Txt(_) => "TXT",
+        DnsRecord::Cname(_) => "CNAME",
+        DnsRecord::Srv { .. } => "SRV",
+        DnsRecord::Mx { .. } => "MX",
+    }
+}
+
+// The typed record for a resource record's data, or `None` for a type we do not store
+fn convert(data: &RData) -> Option<DnsRecord> {
+    let record = match data {
+        RData::A(a) => DnsRecord::A(a.0),
+        RData::AAAA(aaaa) => DnsRecord::Aaaa(aaaa.0),
+        // A TXT record's strings make up one text, as SPF and DKIM read them
+        RData::TXT(txt) => {
+            let text: Vec<u8> = txt.txt_data().iter().flat_map(|part| part.iter().copied()).collect();
+            DnsRecord::Txt(String::from_utf8_lossy(&text).into_owned())
+        }
+        RData::CNAME(cname) => DnsRecord::Cname(name_of(&cname.0)),
+        RData::SRV(srv) => DnsRecord::Srv {
+            priority: srv.priority(),
+            weight: srv.weight(),
+            port: srv.port(),
+            target: name_of(srv.target()),
+        },
+        RData::MX(mx) => DnsRecord::Mx { preference: mx.preference(), exchange: name_of(mx.exchange()) },
+        _ => return None,
+    };
+    Some(record)
+}
+
+// A name as `put` takes it: lowercase, without the trailing dot of the root
+fn name_of(name: &Name) -> String {
+    let name = name.to_ascii().to_ascii_lowercase();
+    name.strip_suffix('.').map(str::to_string).unwrap_or(name)
+}
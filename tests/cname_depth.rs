@@ -0,0 +1,95 @@
+//! A CNAME chain of exactly `MAX_CNAME_DEPTH` aliases resolves, on the
+//! command paths and through the DNS frontend alike; a longer chain or a
+//! loop does not.
+
+mod common;
+
+use common::{finish, start, test_config};
+use dht::dns_record::{self, DnsRecord, MAX_CNAME_DEPTH};
+use dht::{dns, DhtError, DhtNode, Quorum};
+use hickory_proto::op::{Message, Query, ResponseCode};
+use hickory_proto::rr::{Name, RecordType};
+use std::net::Ipv4Addr;
+
+fn names(count: usize) -> Vec<String> {
+    (0..count).map(|i| format!("hop{}.test", i)).collect()
+}
+
+#[test]
+fn chain_of_exactly_the_limit_is_followed() {
+    let chain = names(MAX_CNAME_DEPTH);
+    // `chain[i]` was reached through i CNAMEs; following its own makes i + 1
+    for hops in 0..MAX_CNAME_DEPTH {
+        assert!(dns_record::follow_cname(&chain[..=hops], hops, "next.test").is_ok(), "hop {} was refused", hops + 1);
+    }
+    assert!(matches!(dns_record::follow_cname(&chain, MAX_CNAME_DEPTH, "next.test"), Err(DhtError::CnameChain(_))));
+}
+
+#[test]
+fn loop_is_refused() {
+    let chain = names(2);
+    match dns_record::follow_cname(&chain, 1, "hop0.test") {
+        Err(DhtError::CnameChain(reason)) => assert!(reason.contains("loop"), "unexpected reason: {}", reason),
+        other => panic!("expected CnameChain, got {:?}", other),
+    }
+}
+
+// Store `hops` CNAMEs from hop0.test, the last pointing at `last`
+async fn store_chain(node: &DhtNode, hops: usize, last: &str) {
+    let chain = names(hops);
+    for (i, name) in chain.iter().enumerate() {
+        let target = chain.get(i + 1).map_or(last, String::as_str);
+        put(node, name, DnsRecord::Cname(target.to_string())).await;
+    }
+}
+
+// Without peers the publish may fail, but the record is still stored locally
+async fn put(node: &DhtNode, name: &str, record: DnsRecord) {
+    match node.put(name, record.encode(), Quorum::One).await {
+        Ok(()) | Err(DhtError::QuorumFailed { .. }) => {}
+        Err(e) => panic!("put of {} failed: {}", name, e),
+    }
+}
+
+async fn query_a(node: &DhtNode, name: &str) -> Message {
+    let mut request = Message::new();
+    request.add_query(Query::query(Name::from_ascii(name).unwrap(), RecordType::A));
+    dns::answer(&request, node).await
+}
+
+#[tokio::test]
+async fn dns_follows_a_chain_of_exactly_the_limit() {
+    let config = test_config("cname-limit");
+    let node = start(&config).await;
+    store_chain(&node, MAX_CNAME_DEPTH, "end.test").await;
+    put(&node, "end.test", DnsRecord::A(Ipv4Addr::new(192, 0, 2, 1))).await;
+
+    let response = query_a(&node, "hop0.test").await;
+    assert_eq!(response.response_code(), ResponseCode::NoError);
+    assert_eq!(response.answers().len(), MAX_CNAME_DEPTH + 1);
+
+    finish(node, config).await;
+}
+
+#[tokio::test]
+async fn dns_refuses_a_chain_over_the_limit() {
+    let config = test_config("cname-over");
+    let node = start(&config).await;
+    store_chain(&node, MAX_CNAME_DEPTH + 1, "end.test").await;
+    put(&node, "end.test", DnsRecord::A(Ipv4Addr::new(192, 0, 2, 1))).await;
+
+    assert_eq!(query_a(&node, "hop0.test").await.response_code(), ResponseCode::ServFail);
+
+    finish(node, config).await;
+}
+
+#[tokio::test]
+async fn dns_refuses_a_loop() {
+    let config = test_config("cname-loop");
+    let node = start(&config).await;
+    store_chain(&node, 2, "hop0.test").await;
+
+    assert_eq!(query_a(&node, "hop0.test").await.response_code(), ResponseCode::ServFail);
+
+    finish(node, config).await;
+}
@@ -0,0 +1,47 @@
+//! Helpers shared by the integration tests.
+
+#![allow(dead_code)]
+
+use dht::{Config, DhtNode};
+use libp2p::Multiaddr;
+use std::time::Duration;
+
+// A node keeping its identity and records in a fresh directory, listening only on loopback TCP
+pub fn test_config(name: &str) -> Config {
+    let dir = std::env::temp_dir().join(format!("dht-test-{}-{}", name, std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    Config {
+        transport: "tcp".to_string(),
+        listen: vec!["/ip4/127.0.0.1/tcp/0".to_string()],
+        store_path: Some(dir.join("records.db")),
+        identity_path: Some(dir.join("identity")),
+        ..Config::default()
+    }
+}
+
+// Build a node from `config` and start its event loop
+pub async fn start(config: &Config) -> DhtNode {
+    let (node, event_loop) = DhtNode::new(config).await.unwrap();
+    tokio::spawn(event_loop.run());
+    node
+}
+
+// The node's first listen address, once its listener is up, ready to dial
+pub async fn dialable_addr(node: &DhtNode) -> Multiaddr {
+    tokio::time::timeout(Duration::from_secs(5), async {
+        loop {
+            if let Some(addr) = node.addrs().await.unwrap().listen.into_iter().next() {
+                return addr;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    })
+    .await
+    .expect("node never started listening")
+}
+
+// Stop the node and remove its directory
+pub async fn finish(node: DhtNode, config: Config) {
+    node.shutdown().await.unwrap();
+    let _ = std::fs::remove_dir_all(config.store_path().parent().unwrap());
+}
@@ -0,0 +1,30 @@
+//! The control socket, and a directory created for it, are reachable by their owner only.
+
+#![cfg(unix)]
+
+use dht::control::ControlSocket;
+use std::os::unix::fs::PermissionsExt;
+use tokio::sync::mpsc;
+
+fn mode(path: &std::path::Path) -> u32 {
+    std::fs::metadata(path).unwrap().permissions().mode() & 0o777
+}
+
+#[tokio::test]
+async fn socket_is_owner_only() {
+    let dir = std::env::temp_dir().join(format!("dht-test-control-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    let path = dir.join("run").join("control.sock");
+
+    let (requests, _) = mpsc::channel(1);
+    let socket = ControlSocket::open(&path, |_| false, requests).await.unwrap();
+    assert_eq!(mode(&path), 0o600);
+    assert_eq!(mode(path.parent().unwrap()), 0o700);
+    // Nothing is left behind from binding it
+    assert_eq!(std::fs::read_dir(path.parent().unwrap()).unwrap().count(), 1);
+    tokio::net::UnixStream::connect(&path).await.unwrap();
+
+    drop(socket);
+    assert!(!path.exists());
+    let _ = std::fs::remove_dir_all(&dir);
+}
@@ -0,0 +1,50 @@
+//! Failures come back as `DhtError` variants callers can match on.
+
+mod common;
+
+use common::{finish, start, test_config};
+use dht::dns_record;
+use dht::{Config, DhtError};
+use std::error::Error;
+use std::io;
+
+#[tokio::test]
+async fn malformed_request_is_an_invalid_command() {
+    let config = test_config("errors-invalid");
+    let node = start(&config).await;
+
+    match node.ping(node.local_peer_id()).await {
+        Err(DhtError::InvalidCommand(reason)) => assert!(reason.contains("cannot ping this node"), "unexpected reason: {}", reason),
+        other => panic!("expected InvalidCommand, got {:?}", other),
+    }
+
+    finish(node, config).await;
+}
+
+#[test]
+fn invalid_setting_is_a_config_error() {
+    match Config::default().set("command_burst", "0") {
+        Err(DhtError::Config(reason)) => assert!(reason.contains("command_burst"), "unexpected reason: {}", reason),
+        other => panic!("expected Config, got {:?}", other),
+    }
+}
+
+#[test]
+fn file_error_is_io_and_keeps_its_source() {
+    let error = DhtError::from(io::Error::new(io::ErrorKind::NotFound, "no such file"));
+    match &error {
+        DhtError::Io(e) => assert_eq!(e.kind(), io::ErrorKind::NotFound),
+        other => panic!("expected Io, got {:?}", other),
+    }
+    assert_eq!(error.to_string(), "no such file");
+    assert!(error.source().is_some());
+}
+
+#[test]
+fn cname_loop_is_a_cname_chain_error() {
+    let chain = vec!["a.test".to_string(), "b.test".to_string()];
+    match dns_record::follow_cname(&chain, 1, "a.test") {
+        Err(DhtError::CnameChain(reason)) => assert_eq!(reason, "CNAME loop detected: a.test -> b.test -> a.test"),
+        other => panic!("expected CnameChain, got {:?}", other),
+    }
+}
@@ -0,0 +1,44 @@
+//! A read-only node refuses every write but still answers lookups.
+
+mod common;
+
+use common::{dialable_addr, finish, start, test_config};
+use dht::{Config, DhtError, Quorum};
+
+// Short enough that a lookup cannot stall the test run
+fn node_config(name: &str, readonly: bool) -> Config {
+    Config { query_timeout_secs: 5, readonly, ..test_config(name) }
+}
+
+#[tokio::test]
+async fn writes_are_refused() {
+    let config = node_config("readonly-writes", true);
+    let node = start(&config).await;
+
+    assert!(matches!(node.put("refused.test", b"v".to_vec(), Quorum::One).await, Err(DhtError::ReadOnly)));
+    assert!(matches!(node.delete("refused.test").await, Err(DhtError::ReadOnly)));
+    assert!(matches!(node.pin("refused.test").await, Err(DhtError::ReadOnly)));
+    assert!(matches!(node.provide("refused.test").await, Err(DhtError::ReadOnly)));
+    assert_eq!(node.records().await.unwrap().len(), 0);
+
+    finish(node, config).await;
+}
+
+#[tokio::test]
+async fn get_finds_a_record_written_elsewhere() {
+    let (config_a, config_b) = (node_config("readonly-get-a", false), node_config("readonly-get-b", true));
+    let a = start(&config_a).await;
+    let b = start(&config_b).await;
+
+    // With no peers yet the publish fails, but A keeps the record and serves it
+    match a.put("shared.test", b"hello".to_vec(), Quorum::One).await {
+        Ok(()) | Err(DhtError::QuorumFailed { .. }) => {}
+        Err(e) => panic!("put failed: {}", e),
+    }
+
+    b.dial(dialable_addr(&a).await).await.unwrap();
+    assert_eq!(b.get("shared.test").await.unwrap(), Some(b"hello".to_vec()));
+
+    finish(a, config_a).await;
+    finish(b, config_b).await;
+}
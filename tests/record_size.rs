@@ -0,0 +1,44 @@
+//! The configured maximum value size is enforced before a record is stored.
+
+mod common;
+
+use common::finish;
+use dht::{Config, DhtError, Quorum};
+
+const MAX_RECORD_BYTES: usize = 1024;
+
+// A node whose largest accepted value is MAX_RECORD_BYTES
+fn test_config(name: &str) -> Config {
+    Config { max_record_bytes: MAX_RECORD_BYTES, ..common::test_config(name) }
+}
+
+#[tokio::test]
+async fn put_at_the_limit_is_stored() {
+    let config = test_config("at-limit");
+    let node = common::start(&config).await;
+
+    let value = vec![b'x'; MAX_RECORD_BYTES];
+    // Without peers the publish may fail, but the record must still be stored locally
+    match node.put("at-limit.test", value.clone(), Quorum::One).await {
+        Ok(()) | Err(DhtError::QuorumFailed { .. }) => {}
+        Err(e) => panic!("put at the limit failed: {}", e),
+    }
+    assert_eq!(node.get("at-limit.test").await.unwrap(), Some(value));
+    finish(node, config).await;
+}
+
+#[tokio::test]
+async fn put_one_byte_over_the_limit_is_rejected() {
+    let config = test_config("over-limit");
+    let node = common::start(&config).await;
+
+    match node.put("over-limit.test", vec![b'x'; MAX_RECORD_BYTES + 1], Quorum::One).await {
+        Err(DhtError::ValueTooLarge { size, max }) => {
+            assert_eq!(size, MAX_RECORD_BYTES + 1);
+            assert_eq!(max, MAX_RECORD_BYTES);
+        }
+        other => panic!("expected ValueTooLarge, got {:?}", other),
+    }
+    assert_eq!(node.records().await.unwrap().len(), 0);
+    finish(node, config).await;
+}
@@ -0,0 +1,29 @@
+//! Records must be re-published before their TTL runs out, so settings that
+//! would let them lapse are refused.
+
+mod common;
+
+use dht::{Config, DhtError, DhtNode};
+use std::time::Duration;
+
+#[tokio::test]
+async fn republish_interval_longer_than_ttl_is_rejected() {
+    let config = Config {
+        record_ttl_secs: 60,
+        republish_interval_secs: Some(120),
+        ..common::test_config("republish-too-long")
+    };
+    assert!(config.republish_interval().is_err());
+
+    match DhtNode::new(&config).await {
+        Err(DhtError::Setup(reason)) => assert!(reason.contains("republish"), "unexpected reason: {}", reason),
+        Err(e) => panic!("expected a setup error, got {}", e),
+        Ok(_) => panic!("node started with a republish interval longer than the TTL"),
+    }
+}
+
+#[tokio::test]
+async fn republish_interval_defaults_to_half_the_ttl() {
+    let config = Config { record_ttl_secs: 60, ..common::test_config("republish-default") };
+    assert_eq!(config.republish_interval().unwrap(), Duration::from_secs(30));
+}
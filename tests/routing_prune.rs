@@ -0,0 +1,65 @@
+//! A peer that can no longer be reached leaves the routing table after
+//! `prune_after_failures` failures in a row, unless pruning is turned off.
+
+mod common;
+
+use common::{dialable_addr, finish, start, test_config};
+use dht::{Config, DhtNode};
+use libp2p::PeerId;
+use std::time::Duration;
+
+// Short enough that the lookups meant to dial the lost peer finish quickly
+fn node_config(name: &str, prune_after_failures: u32) -> Config {
+    Config { query_timeout_secs: 2, prune_after_failures, ..test_config(name) }
+}
+
+async fn routable(node: &DhtNode, peer_id: PeerId) -> bool {
+    node.peers().await.unwrap().iter().any(|peer| peer.peer_id == peer_id && peer.routable)
+}
+
+// Start A and B, wait until A has B in its routing table, then stop B
+async fn lose_peer(name: &str, prune_after_failures: u32) -> (DhtNode, Config, PeerId) {
+    let config_a = node_config(&format!("{}-a", name), prune_after_failures);
+    let config_b = node_config(&format!("{}-b", name), prune_after_failures);
+    let a = start(&config_a).await;
+    let b = start(&config_b).await;
+    let b_id = b.local_peer_id();
+    a.dial(dialable_addr(&b).await).await.unwrap();
+    tokio::time::timeout(Duration::from_secs(10), async {
+        while !routable(&a, b_id).await {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+    })
+    .await
+    .expect("B never entered A's routing table");
+    finish(b, config_b).await;
+    (a, config_a, b_id)
+}
+
+#[tokio::test]
+async fn unreachable_peer_is_pruned() {
+    let (a, config_a, b_id) = lose_peer("prune", 2).await;
+
+    // Each lookup dials B again, and each dial fails
+    let pruned = tokio::time::timeout(Duration::from_secs(20), async {
+        while routable(&a, b_id).await {
+            let _ = a.get("anything.test").await;
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+    });
+    pruned.await.expect("B was never pruned from A's routing table");
+
+    finish(a, config_a).await;
+}
+
+#[tokio::test]
+async fn unreachable_peer_is_kept_without_pruning() {
+    let (a, config_a, b_id) = lose_peer("keep", 0).await;
+
+    for _ in 0..3 {
+        let _ = a.get("anything.test").await;
+    }
+    assert!(routable(&a, b_id).await, "B was removed from the routing table with pruning off");
+
+    finish(a, config_a).await;
+}
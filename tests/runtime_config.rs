@@ -0,0 +1,107 @@
+//! Runtime settings changed with `set_config` reach the requests made after
+//! the change; other settings, and values out of range, are refused.
+
+mod common;
+
+use common::{dialable_addr, finish, start, test_config};
+use dht::{Config, DhtError, Quorum};
+use libp2p::{Multiaddr, PeerId};
+use std::net::TcpListener;
+use std::time::{Duration, Instant};
+
+// A query timeout far longer than the get timeouts the tests set, so it is clear which one ended a get
+fn node_config(name: &str) -> Config {
+    Config { query_timeout_secs: 30, ..test_config(name) }
+}
+
+#[tokio::test]
+async fn default_quorum_change_is_used() {
+    let config = node_config("runtime-quorum");
+    let node = start(&config).await;
+
+    node.set_config("default_quorum", "all").await.unwrap();
+    assert_eq!(node.default_quorum(), Quorum::All);
+    assert_eq!(node.config().default_quorum, "all");
+
+    finish(node, config).await;
+}
+
+#[tokio::test]
+async fn get_timeout_change_is_used() {
+    let config = Config { dial_timeout_secs: 30, ..node_config("runtime-timeout") };
+    let node = start(&config).await;
+
+    // A peer that accepts connections but never answers, so a lookup waits for it until the get times out
+    let silent = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr: Multiaddr = format!("/ip4/127.0.0.1/tcp/{}", silent.local_addr().unwrap().port()).parse().unwrap();
+    let _ = node.bootstrap(addr, PeerId::random()).await;
+
+    node.set_config("get_timeout_secs", "1").await.unwrap();
+    let started = Instant::now();
+    let _ = node.get("missing.test").await;
+    let elapsed = started.elapsed();
+    assert!(elapsed >= Duration::from_secs(1), "the get ended after {:?}, before anything timed out", elapsed);
+    assert!(elapsed < Duration::from_secs(10), "the get waited {:?}, not the 1s get_timeout_secs", elapsed);
+
+    finish(node, config).await;
+}
+
+#[tokio::test]
+async fn inbound_limits_change_is_used() {
+    let (config_a, config_b) = (node_config("runtime-limits-a"), node_config("runtime-limits-b"));
+    let a = start(&config_a).await;
+    let b = start(&config_b).await;
+
+    // B answers one request from A, then none for the rest of the test
+    b.set_config("inbound_burst", "1").await.unwrap();
+    b.set_config("inbound_rate", "0.001").await.unwrap();
+    a.dial(dialable_addr(&b).await).await.unwrap();
+    for i in 0..3 {
+        let _ = a.get(&format!("missing-{}.test", i)).await;
+    }
+    assert!(b.metrics().throttled_requests.get() > 0, "B never throttled A");
+
+    finish(a, config_a).await;
+    finish(b, config_b).await;
+}
+
+#[tokio::test]
+async fn restart_only_setting_is_refused() {
+    let config = node_config("runtime-restart");
+    let node = start(&config).await;
+
+    match node.set_config("listen", r#"["/ip4/127.0.0.1/tcp/0"]"#).await {
+        Err(DhtError::InvalidCommand(reason)) => assert!(reason.contains("requires restart"), "unexpected reason: {}", reason),
+        other => panic!("expected InvalidCommand, got {:?}", other),
+    }
+
+    finish(node, config).await;
+}
+
+#[tokio::test]
+async fn out_of_range_values_are_refused() {
+    let config = node_config("runtime-invalid");
+    let node = start(&config).await;
+
+    let invalid = [
+        ("command_rate", "-1"),
+        ("inbound_rate", "NaN"),
+        ("inbound_rate", "inf"),
+        ("command_burst", "0"),
+        ("inbound_burst", "0"),
+        ("get_timeout_secs", "0"),
+        ("get_timeout_secs", "18446744073709551615"),
+        ("inbound_cooldown_secs", "18446744073709551615"),
+        ("get_retries", "4294967295"),
+        ("default_quorum", "some"),
+    ];
+    for (key, value) in invalid {
+        match node.set_config(key, value).await {
+            Err(DhtError::InvalidCommand(_)) => {}
+            other => panic!("expected {} {} to be refused, got {:?}", key, value, other),
+        }
+        assert_eq!(node.config().get(key), config.get(key), "{} changed after a refused set", key);
+    }
+
+    finish(node, config).await;
+}
@@ -0,0 +1,19 @@
+//! The suffix validator accepts a domain and the names below it, and nothing
+//! that merely ends in the same letters.
+
+use dht::validator::{RecordValidator, SuffixValidator};
+
+#[test]
+fn lookalike_name_is_refused() {
+    let validator = SuffixValidator::new(["example.com"]);
+    assert!(validator.validate(b"www.example.com", b"").is_ok());
+    assert!(validator.validate(b"evilexample.com", b"").is_err());
+}
+
+#[test]
+fn apex_is_accepted_with_a_leading_dot() {
+    let validator = SuffixValidator::new([".Example.COM"]);
+    assert!(validator.validate(b"example.com", b"").is_ok());
+    assert!(validator.validate(b"mail.EXAMPLE.com", b"").is_ok());
+    assert!(validator.validate(b"evilexample.com", b"").is_err());
+}
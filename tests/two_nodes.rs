@@ -0,0 +1,45 @@
+//! Two nodes in one process exchange records over loopback TCP.
+
+mod common;
+
+use common::{dialable_addr, finish, start, test_config};
+use dht::{Config, DhtError, Quorum};
+use std::time::Duration;
+
+// Short enough that a lookup of a missing key cannot stall the test run
+fn node_config(name: &str) -> Config {
+    Config { query_timeout_secs: 5, ..test_config(name) }
+}
+
+#[tokio::test]
+async fn put_on_one_node_is_found_by_the_other() {
+    let (config_a, config_b) = (node_config("pair-a"), node_config("pair-b"));
+    let a = start(&config_a).await;
+    let b = start(&config_b).await;
+
+    // With no peers yet the publish fails, but A keeps the record and serves it
+    match a.put("shared.test", b"hello".to_vec(), Quorum::One).await {
+        Ok(()) | Err(DhtError::QuorumFailed { .. }) => {}
+        Err(e) => panic!("put failed: {}", e),
+    }
+
+    b.dial(dialable_addr(&a).await).await.unwrap();
+    assert_eq!(b.get("shared.test").await.unwrap(), Some(b"hello".to_vec()));
+
+    finish(a, config_a).await;
+    finish(b, config_b).await;
+}
+
+#[tokio::test]
+async fn get_of_an_unknown_key_resolves_to_none() {
+    let (config_a, config_b) = (node_config("unknown-a"), node_config("unknown-b"));
+    let a = start(&config_a).await;
+    let b = start(&config_b).await;
+    b.dial(dialable_addr(&a).await).await.unwrap();
+
+    let lookup = tokio::time::timeout(config_b.query_timeout() + Duration::from_secs(1), b.get("missing.test"));
+    assert_eq!(lookup.await.expect("get did not resolve within the query timeout").unwrap(), None);
+
+    finish(a, config_a).await;
+    finish(b, config_b).await;
+}